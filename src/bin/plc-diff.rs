@@ -0,0 +1,368 @@
+use std::convert::TryInto;
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+
+use plc_diff::coverage::Coverage;
+use plc_diff::diff::ProjectDiff;
+use plc_diff::dump::Dump;
+use plc_diff::equation::rung_equations;
+use plc_diff::equivalence::{self, Verdict};
+use plc_diff::html::HtmlOptions;
+use plc_diff::lint::{self, DocPolicy, LintConfig};
+use plc_diff::plcopen;
+use plc_diff::project::Project;
+use plc_diff::scenario::{self, TestSuite};
+use plc_diff::st;
+use plc_diff::timers::block_usage;
+use plc_diff::volatile::Volatile;
+use plc_diff::xref::{cross_reference, usage_diff};
+
+const USAGE: &str = "\
+Usage: plc-diff <command> [options] <files>
+
+Commands:
+  diff [--color] OLD NEW   Semantic diff between two project files
+  stat OLD NEW             Summary of changed rungs and IL lines per POU
+  html [--ladder] OLD NEW  Side-by-side diff as a single HTML page
+  io-diff [--filter=%I,%Q,...] OLD NEW
+                           Addresses whose set of reading or writing rungs changed
+  xref FILE [ADDRESS|SYMBOL...]
+                           Where each address is read, written or called
+  equations FILE [POU...]  Rung IL as boolean equations with symbol names
+  equiv OLD NEW            Check that the changed rungs have the same logic, exits
+                           with status 1 if a rung differs
+  lint [--lint=NAME,...] [--doc-policy=RULE,...] FILE
+                           Run the lints, exits with status 1 if anything is found
+  timers FILE              Timer and counter presets and the rungs using them
+  test [--junit=FILE] [--coverage] FILE SCENARIOS...
+                           Run the scenario files, or directories of them, in the
+                           simulator, exits with status 1 if a scenario fails
+  st FILE                  The POUs as IEC 61131-3 Structured Text
+  plcopen FILE             The POUs, variables and Grafcet charts as PLCopen XML
+  dump --json FILE         The whole parsed project as JSON, see the README
+
+Project files can also be given as REV:PATH, to read them from a git revision.
+
+Options:
+  --volatile=NAME,...      Elements to ignore, instead of the default
+                           FullName,ProjectVersion,CurrentCultureName,
+                           IsLadderSelected,DownloadSettings
+";
+
+/// Command line arguments, split into `--flags` and positional arguments
+struct Args {
+    flags: Vec<String>,
+    files: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> Self {
+        let (flags, files) = args.partition(|a| a.starts_with("--"));
+        Self { flags, files }
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|f| f == name)
+    }
+
+    /// Value of a `--name=value` flag
+    fn value(&self, name: &str) -> Option<&str> {
+        self.flags
+            .iter()
+            .find_map(|f| f.strip_prefix(name)?.strip_prefix('='))
+    }
+
+    fn volatile(&self) -> Volatile {
+        self.value("--volatile")
+            .map_or_else(Volatile::default, Volatile::from_list)
+    }
+
+    /// Load the projects given on the command line
+    fn projects<const N: usize>(&self) -> Result<[Project; N]> {
+        if self.files.len() != N {
+            bail!("Expected {} file arguments\n\n{}", N, USAGE);
+        }
+        let projects = self
+            .files
+            .iter()
+            .map(|f| load(f))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(projects.try_into().unwrap_or_else(|_| unreachable!()))
+    }
+}
+
+/// Load a project from a file, or from a git revision given as `REV:PATH`
+fn load(spec: &str) -> Result<Project> {
+    let path = Path::new(spec);
+    match spec.split_once(':') {
+        Some((rev, _)) if !rev.is_empty() && !path.exists() => {
+            let output = Command::new("git")
+                .args(["show", spec])
+                .output()
+                .context("Failed to run git")?;
+            if !output.status.success() {
+                bail!(
+                    "git show {} failed: {}",
+                    spec,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            Project::from_bytes(&output.stdout).with_context(|| format!("Failed to parse {}", spec))
+        }
+        _ => Project::from_file(path),
+    }
+}
+
+fn diff(args: &Args) -> Result<()> {
+    let [old, new] = args.projects()?;
+    let diff = ProjectDiff::with_volatile(&old, &new, &args.volatile());
+    print!("{}", diff.render(args.flag("--color")));
+    Ok(())
+}
+
+fn stat(args: &Args) -> Result<()> {
+    let [old, new] = args.projects()?;
+    print!(
+        "{}",
+        ProjectDiff::with_volatile(&old, &new, &args.volatile()).stat()
+    );
+    Ok(())
+}
+
+fn html(args: &Args) -> Result<()> {
+    let [old, new] = args.projects()?;
+    let diff = ProjectDiff::with_volatile(&old, &new, &args.volatile());
+    let options = HtmlOptions {
+        ladder: args.flag("--ladder"),
+    };
+    print!("{}", plc_diff::html::render(&diff, &old, &new, &options));
+    Ok(())
+}
+
+fn io_diff(args: &Args) -> Result<()> {
+    let [old, new] = args.projects()?;
+    let prefixes: Vec<&str> = args
+        .value("--filter")
+        .map_or_else(Vec::new, |f| f.split(',').collect());
+    for change in usage_diff(&old, &new, &prefixes) {
+        print!("{}", change);
+    }
+    Ok(())
+}
+
+fn xref(args: &Args) -> Result<()> {
+    let (file, filter) = args.files.split_first().context(USAGE)?;
+    let project = load(file)?;
+    for entry in cross_reference(&project) {
+        // Function block pins, e.g. %TM0.Q, are listed together with the block
+        let block = entry.address.split('.').next().unwrap_or_default();
+        let selected = filter
+            .iter()
+            .any(|f| [&entry.address, &entry.symbol, block].contains(&f.as_str()));
+        if filter.is_empty() || selected {
+            print!("{}", entry.render(&project));
+        }
+    }
+    Ok(())
+}
+
+fn equations(args: &Args) -> Result<()> {
+    let (file, filter) = args.files.split_first().context(USAGE)?;
+    let project = load(file)?;
+    let symbols = project.symbols();
+    for pou in &project.pous {
+        // Grafcet steps and transitions are selected by their chart name
+        let selected = filter
+            .iter()
+            .any(|f| *f == pou.name || pou.chart.as_ref() == Some(f));
+        if !filter.is_empty() && !selected {
+            continue;
+        }
+        for (idx, rung) in pou.rungs.iter().enumerate() {
+            println!("{}", pou.rung_context(idx));
+            match rung_equations(rung, &symbols) {
+                Some(equations) => equations.iter().for_each(|e| println!("  {}", e)),
+                // Constructs like jumps are shown as IL
+                None => rung.il_text().for_each(|l| println!("  | {}", l)),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn equiv(args: &Args) -> Result<()> {
+    let [old, new] = args.projects()?;
+    let checks = equivalence::check(&old, &new);
+    for check in &checks {
+        println!("{}", check);
+    }
+    if checks
+        .iter()
+        .any(|c| matches!(c.verdict, Verdict::Different(_)))
+    {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn lint(args: &Args) -> Result<()> {
+    let [project] = args.projects()?;
+    let names: Vec<&str> = args
+        .value("--lint")
+        .map_or_else(Vec::new, |l| l.split(',').collect());
+    if let Some(unknown) = names
+        .iter()
+        .find(|n| !lint::LINTS.iter().any(|(name, _)| name == *n))
+    {
+        bail!("Unknown lint {:?}", unknown);
+    }
+    let config = LintConfig {
+        documentation: match args.value("--doc-policy") {
+            Some(rules) => DocPolicy::parse(rules)?,
+            None => DocPolicy::default(),
+        },
+    };
+    let findings = lint::run(&project, &names, &config);
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    if !findings.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn timers(args: &Args) -> Result<()> {
+    let [project] = args.projects()?;
+    for block in block_usage(&project) {
+        print!("{}", block);
+    }
+    Ok(())
+}
+
+fn test(args: &Args) -> Result<()> {
+    let (file, paths) = args.files.split_first().context(USAGE)?;
+    if paths.is_empty() {
+        bail!("Expected scenario files\n\n{}", USAGE);
+    }
+    let project = load(file)?;
+    let mut suites = Vec::new();
+    for path in paths {
+        for path in scenario::scenario_files(Path::new(path))? {
+            let file = scenario::load(&path)?;
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+            let cases = file
+                .scenarios
+                .iter()
+                .map(|s| scenario::run(&project, s))
+                .collect();
+            suites.push(TestSuite {
+                name: name.into_owned(),
+                cases,
+            });
+        }
+    }
+    let mut failed = 0;
+    for suite in &suites {
+        for case in &suite.cases {
+            let status = if case.passed() { "ok" } else { "FAILED" };
+            println!("{} > {} ... {}", suite.name, case.name, status);
+            for failure in case.error.iter().chain(&case.failures) {
+                println!("  {}", failure);
+            }
+            failed += !case.passed() as usize;
+        }
+    }
+    let total: usize = suites.iter().map(|s| s.cases.len()).sum();
+    println!("{} scenarios, {} failed", total, failed);
+    if args.flag("--coverage") {
+        let mut coverage = Coverage::default();
+        for case in suites.iter().flat_map(|s| &s.cases) {
+            coverage.merge(&case.coverage);
+        }
+        print!("\n{}", coverage.report(&project));
+    }
+    if let Some(junit) = args.value("--junit") {
+        std::fs::write(junit, scenario::junit(&suites))
+            .with_context(|| format!("Failed to write {}", junit))?;
+    }
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn structured_text(args: &Args) -> Result<()> {
+    let [project] = args.projects()?;
+    print!("{}", st::export(&project));
+    Ok(())
+}
+
+/// Creation time of an exported file: `SOURCE_DATE_EPOCH` for reproducible builds, or the
+/// modification time of the project file, or the commit time of a `REV:PATH`
+fn creation_time(spec: &str) -> Result<SystemTime> {
+    if let Ok(epoch) = env::var("SOURCE_DATE_EPOCH") {
+        let seconds = epoch.trim().parse().context("Invalid SOURCE_DATE_EPOCH")?;
+        return Ok(UNIX_EPOCH + Duration::from_secs(seconds));
+    }
+    let path = Path::new(spec);
+    match spec.split_once(':') {
+        Some((rev, _)) if !rev.is_empty() && !path.exists() => {
+            let output = Command::new("git")
+                .args(["show", "-s", "--format=%ct", rev])
+                .output()
+                .context("Failed to run git")?;
+            let seconds = String::from_utf8_lossy(&output.stdout)
+                .trim()
+                .parse()
+                .with_context(|| format!("Failed to get the commit time of {}", rev))?;
+            Ok(UNIX_EPOCH + Duration::from_secs(seconds))
+        }
+        _ => path
+            .metadata()
+            .and_then(|m| m.modified())
+            .with_context(|| format!("Failed to get the modification time of {}", spec)),
+    }
+}
+
+fn plcopen_xml(args: &Args) -> Result<()> {
+    let [project] = args.projects()?;
+    let created = creation_time(&args.files[0])?;
+    print!("{}", plcopen::export(&project, created));
+    Ok(())
+}
+
+fn dump(args: &Args) -> Result<()> {
+    if !args.flag("--json") {
+        bail!("Expected --json, the only dump format\n\n{}", USAGE);
+    }
+    let [project] = args.projects()?;
+    println!("{}", Dump::new(&project).to_json());
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let command = args.next().context(USAGE)?;
+    let args = Args::parse(args);
+    match command.as_str() {
+        "diff" => diff(&args),
+        "stat" => stat(&args),
+        "html" => html(&args),
+        "io-diff" => io_diff(&args),
+        "xref" => xref(&args),
+        "equations" => equations(&args),
+        "equiv" => equiv(&args),
+        "lint" => lint(&args),
+        "timers" => timers(&args),
+        "test" => test(&args),
+        "st" => structured_text(&args),
+        "plcopen" => plcopen_xml(&args),
+        "dump" => dump(&args),
+        _ => bail!("Unknown command {:?}\n\n{}", command, USAGE),
+    }
+}
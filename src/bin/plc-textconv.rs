@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryFrom;
 use std::env;
 #[allow(unused_imports)]
 use std::fs::File;
@@ -10,14 +10,13 @@ use std::path::Path;
 
 use anyhow::{Context, Result};
 use arrayvec::ArrayVec;
-use itertools::Itertools;
 use quick_xml::events::{BytesStart, BytesText, Event};
 use quick_xml::Writer;
 
 use plc_diff::grafcet::{GrafcetCounter, GrafcetTracer};
-use plc_diff::{
-    process_file, CurrentTag, Guid, GuidMap, VisitProcessing, VisitResult, XmlNodeVisitor,
-};
+use plc_diff::names::NameTracker;
+use plc_diff::volatile::{Volatile, VolatileFilter};
+use plc_diff::{process_file, CurrentTag, GuidMap, VisitProcessing, VisitResult, XmlNodeVisitor};
 
 #[derive(Debug)]
 struct NormalizeInstructionLine<'a> {
@@ -66,7 +65,7 @@ impl XmlNodeVisitor for NormalizeInstructionLine<'_> {
 
             Event::End(_) if current == CurrentTag::InstructionLineEntity => {
                 self.in_entity = false;
-                let text = std::mem::replace(&mut self.text, Vec::new());
+                let text = take(&mut self.text);
                 return Ok(VisitProcessing::Continue(Event::Text(
                     BytesText::from_escaped(text),
                 )));
@@ -195,89 +194,6 @@ impl XmlNodeVisitor for IoNames {
     }
 }
 
-#[derive(Debug, Default)]
-struct Rung {
-    name: Vec<u8>,
-    main_comment: Vec<u8>,
-}
-#[derive(Debug, Default)]
-struct NameTracker {
-    rungs: Vec<Rung>,
-    ids: HashMap<Guid, String>,
-    names: Vec<(usize, String)>,
-    new_comment: Vec<u8>,
-    new_id: Guid,
-    depth: usize,
-}
-impl NameTracker {
-    fn mk_rung_name(&self) -> Vec<u8> {
-        self.names
-            .iter()
-            .skip(1) // Skip the project name
-            .take_while(|(depth, _)| depth <= &(self.depth + 2))
-            .map(|(_, name)| name.as_str())
-            .join(" > ")
-            .into()
-    }
-    fn latest_name(&self) -> String {
-        self.names
-            .last()
-            .map_or_else(String::new, |(_, name)| name.clone())
-    }
-    fn remove_old_names(&mut self) {
-        while self
-            .names
-            .last()
-            .map_or(false, |(depth, _)| depth >= &self.depth)
-        {
-            self.names.pop();
-        }
-    }
-}
-impl XmlNodeVisitor for NameTracker {
-    fn visit<'a>(&mut self, event: Event<'a>, current: CurrentTag) -> VisitResult<'a> {
-        match &event {
-            Event::Text(txt) => match current {
-                CurrentTag::Id => self.new_id = txt.try_into()?,
-                CurrentTag::MainComment => self.new_comment = txt.to_vec(),
-                CurrentTag::Name => {
-                    self.remove_old_names();
-                    self.names
-                        .push((self.depth, std::str::from_utf8(&**txt)?.to_string()));
-                }
-                _ => {}
-            },
-            Event::Start(_) => self.depth += 1,
-            Event::End(_) => {
-                match current {
-                    CurrentTag::RungEntity => {
-                        let main_comment = std::mem::replace(&mut self.new_comment, Vec::new());
-                        let name = self.mk_rung_name();
-                        self.rungs.push(Rung { name, main_comment });
-                    }
-                    CurrentTag::GrafcetNodeStep => {
-                        let name = self
-                            .names
-                            .iter()
-                            .find(|&&(depth, _)| depth > self.depth)
-                            .map_or_else(String::new, |(_, name)| name.clone());
-                        self.ids.insert(self.new_id.clone(), name);
-                    }
-                    CurrentTag::GrafcetTransition => {
-                        let name = self.latest_name();
-                        self.ids.insert(self.new_id.clone(), name);
-                        self.remove_old_names();
-                    }
-                    _ => {}
-                }
-                self.depth -= 1;
-            }
-            _ => {}
-        }
-        Ok(VisitProcessing::Continue(event))
-    }
-}
-
 #[derive(Debug)]
 struct DiffHeader<'a> {
     trk: &'a NameTracker,
@@ -297,18 +213,9 @@ impl<'a> DiffHeader<'a> {
     fn add_ctx_attr(bytes: &mut BytesStart, hdr: &dyn AsRef<[u8]>) {
         bytes.push_attribute((&b"ctx"[..], hdr.as_ref()));
     }
-    fn id(&self, id: &'a Guid) -> &'a str {
-        if let Some(name) = &self.trk.ids.get(id) {
-            name
-        } else {
-            let x = self.grc.get_unique_link(id);
-            self.id(x)
-        }
-    }
-    fn trans_ctx(&self) -> Vec<u8> {
+    fn trans_ctx(&self) -> String {
         let node = self.grc.get_current_node(&self.grc_cnt);
-        let (from, id, to) = node.uniq_triple().expect("Failed to get uniq triple");
-        format!("{}->[{}]->{}", self.id(from), self.id(id), self.id(to)).into()
+        self.trk.transition(self.grc, node)
     }
 }
 impl XmlNodeVisitor for DiffHeader<'_> {
@@ -317,7 +224,7 @@ impl XmlNodeVisitor for DiffHeader<'_> {
             self.grc_cnt.process_current_tag(current);
             match current {
                 CurrentTag::RungEntity => {
-                    Self::add_ctx_attr(bytes, &self.trk.rungs[self.current_rung].name);
+                    Self::add_ctx_attr(bytes, &self.trk.rungs()[self.current_rung]);
                     self.current_rung += 1;
                 }
                 CurrentTag::GrafcetTransition => {
@@ -330,7 +237,7 @@ impl XmlNodeVisitor for DiffHeader<'_> {
     }
 }
 
-fn output_visitor(filename: &Path) -> Result<()> {
+fn output_visitor(filename: &Path, volatile: &Volatile) -> Result<()> {
     let mut ionames = IoNames::new();
     let mut name_tracker = NameTracker::default();
     let mut grafcet_tracer = GrafcetTracer::default();
@@ -348,6 +255,7 @@ fn output_visitor(filename: &Path) -> Result<()> {
     // let out = std::io::sink();
     let out = std::io::stdout();
 
+    let mut volatile_filter = VolatileFilter::new(volatile);
     let mut guid_map = GuidVisitor::new();
     let mut writer = EventWriter(Writer::new(out));
     let mut tag_skipper = SkipTag::new(CurrentTag::LadderElements);
@@ -356,6 +264,7 @@ fn output_visitor(filename: &Path) -> Result<()> {
     process_file(
         filename,
         &mut [
+            &mut volatile_filter,  // drop fields which change on every save
             &mut tag_skipper,      // skip ladder diagram tags
             &mut diff_headers,     // Generate diff headers
             &mut inst_line_mangle, // Mangle instruction lines
@@ -367,8 +276,14 @@ fn output_visitor(filename: &Path) -> Result<()> {
 }
 
 fn main() -> Result<()> {
-    let filename = env::args()
-        .nth(1)
-        .context("Missing filename on commandline")?;
-    output_visitor(Path::new(&*filename))
+    let mut volatile = Volatile::default();
+    let mut filename = None;
+    for arg in env::args().skip(1) {
+        match arg.strip_prefix("--volatile=") {
+            Some(list) => volatile = Volatile::from_list(list),
+            None => filename = Some(arg),
+        }
+    }
+    let filename = filename.context("Missing filename on commandline")?;
+    output_visitor(Path::new(&*filename), &volatile)
 }
@@ -0,0 +1,221 @@
+//! Function block models for the simulator
+//!
+//! A model is updated when the simulator executes the `BLK` structure of its instance,
+//! after the inputs, e.g. `%TM0.IN`, have been written to the memory, and it writes the
+//! outputs, e.g. `%TM0.Q` and `%TM0.V`, before the `OUT_BLK` section reads them. Time is
+//! the virtual clock of the simulator, so tests can advance it deterministically.
+use std::time::Duration;
+
+use crate::project::ObjectEntry;
+use crate::sim::{Memory, Value};
+use crate::timers::base_millis;
+
+/// Behaviour of a function block instance
+pub trait BlockModel {
+    /// Update the outputs of `instance`, e.g. `%TM0`, from its inputs at the time `now`
+    fn update(&mut self, instance: &str, memory: &mut Memory, now: Duration);
+}
+
+/// Built-in model of the object, for the timers and counters of the symbol tables
+pub fn builtin(object: &ObjectEntry) -> Option<Box<dyn BlockModel>> {
+    Some(match object.table.as_str() {
+        "Timers" => Box::new(Timer::from_object(object)?),
+        "Counters" => Box::new(Counter::default()),
+        _ => return None,
+    })
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TimerType {
+    /// On-delay, `Q` is set when `IN` has been true for the preset time
+    Ton,
+    /// Off-delay, `Q` is reset when `IN` has been false for the preset time
+    Tof,
+    /// Pulse, `Q` is true for the preset time after a rising edge of `IN`
+    Tp,
+}
+
+/// Timer `%TMi` with the input `IN`, the output `Q`, and the preset `P` and current value
+/// `V` in units of the time base
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timer {
+    pub kind: TimerType,
+    pub base: Duration,
+    /// Start of the running delay or pulse
+    start: Option<Duration>,
+    input: bool,
+}
+
+impl Timer {
+    pub fn new(kind: TimerType, base: Duration) -> Self {
+        Self {
+            kind,
+            base,
+            start: None,
+            input: false,
+        }
+    }
+
+    /// Timer from the `Type` and `Base` of a `Timers` table entry
+    pub fn from_object(object: &ObjectEntry) -> Option<Self> {
+        let kind = match object.fields.get("Type")?.as_str() {
+            "TON" => TimerType::Ton,
+            "TOF" => TimerType::Tof,
+            "TP" => TimerType::Tp,
+            _ => return None,
+        };
+        let base = Duration::from_millis(base_millis(object.fields.get("Base")?)?);
+        Some(Self::new(kind, base))
+    }
+}
+
+impl BlockModel for Timer {
+    fn update(&mut self, instance: &str, memory: &mut Memory, now: Duration) {
+        let input = memory.bit(&format!("{}.IN", instance));
+        let preset = memory.get(&format!("{}.P", instance)).int().max(0);
+        let rising = input && !self.input;
+        let falling = !input && self.input;
+        self.input = input;
+
+        // Elapsed time of the running delay or pulse, in units of the time base
+        let base = self.base.as_nanos().max(1);
+        let elapsed = |start: Option<Duration>| {
+            let units = now.saturating_sub(start?).as_nanos() / base;
+            Some((units as i64).min(preset))
+        };
+        let (q, v) = match self.kind {
+            TimerType::Ton => {
+                if rising {
+                    self.start = Some(now);
+                } else if !input {
+                    self.start = None;
+                }
+                let v = elapsed(self.start).unwrap_or(0);
+                (input && v >= preset, v)
+            }
+            TimerType::Tof => {
+                if falling {
+                    self.start = Some(now);
+                } else if input {
+                    self.start = None;
+                }
+                let v = elapsed(self.start).unwrap_or(0);
+                (input || self.start.is_some() && v < preset, v)
+            }
+            TimerType::Tp => {
+                let running = elapsed(self.start).is_some_and(|v| v < preset);
+                if rising && !running {
+                    self.start = Some(now);
+                }
+                match elapsed(self.start) {
+                    Some(v) if v < preset => (true, v),
+                    // The value is kept until the input is reset
+                    Some(v) if input => (false, v),
+                    _ => {
+                        self.start = None;
+                        (false, 0)
+                    }
+                }
+            }
+        };
+        memory.set_bit(&format!("{}.Q", instance), q);
+        memory.set(&format!("{}.V", instance), Value::Int(v));
+    }
+}
+
+/// Largest value of a counter, it wraps around to 0 when counting up from it
+pub const COUNTER_MAX: i64 = 9999;
+
+/// Up/down counter `%Ci` with the inputs `R`, `S`, `CU` and `CD`, the outputs `E` (empty,
+/// counted down from 0), `D` (done, the value is the preset) and `F` (full, counted up from
+/// `COUNTER_MAX`), the preset `P` and the value `V`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Counter {
+    up: bool,
+    down: bool,
+}
+
+impl BlockModel for Counter {
+    fn update(&mut self, instance: &str, memory: &mut Memory, _now: Duration) {
+        let pin = |name: &str| format!("{}.{}", instance, name);
+        let (up, down) = (memory.bit(&pin("CU")), memory.bit(&pin("CD")));
+        let preset = memory.get(&pin("P")).int();
+        let mut value = memory.get(&pin("V")).int();
+        let (mut empty, mut full) = (false, false);
+        if memory.bit(&pin("R")) {
+            value = 0;
+        } else if memory.bit(&pin("S")) {
+            value = preset;
+        } else {
+            if up && !self.up {
+                full = value == COUNTER_MAX;
+                value = if full { 0 } else { value + 1 };
+            }
+            if down && !self.down {
+                empty = value == 0;
+                value = if empty { COUNTER_MAX } else { value - 1 };
+            }
+        }
+        self.up = up;
+        self.down = down;
+        memory.set(&pin("V"), Value::Int(value));
+        memory.set_bit(&pin("D"), value == preset);
+        memory.set_bit(&pin("E"), empty);
+        memory.set_bit(&pin("F"), full);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timers() {
+        let ms = Duration::from_millis;
+        let run = |kind, inputs: &[(u64, bool)]| -> Vec<(bool, i64)> {
+            let mut timer = Timer::new(kind, ms(100));
+            let mut memory = Memory::default();
+            memory.set("%TM0.P", Value::Int(5));
+            let mut outputs = Vec::new();
+            for (t, input) in inputs {
+                memory.set_bit("%TM0.IN", *input);
+                timer.update("%TM0", &mut memory, ms(*t));
+                outputs.push((memory.bit("%TM0.Q"), memory.get("%TM0.V").int()));
+            }
+            outputs
+        };
+        let inputs = [
+            (0, true),
+            (300, true),
+            (500, true),
+            (600, false),
+            (800, false),
+        ];
+        assert_eq!(
+            run(TimerType::Ton, &inputs),
+            [(false, 0), (false, 3), (true, 5), (false, 0), (false, 0)]
+        );
+        let inputs = [
+            (0, true),
+            (100, false),
+            (400, false),
+            (600, false),
+            (700, true),
+        ];
+        assert_eq!(
+            run(TimerType::Tof, &inputs),
+            [(true, 0), (true, 0), (true, 3), (false, 5), (true, 0)]
+        );
+        let inputs = [
+            (0, true),
+            (300, false),
+            (350, true),
+            (500, true),
+            (600, false),
+        ];
+        assert_eq!(
+            run(TimerType::Tp, &inputs),
+            [(true, 0), (true, 3), (true, 3), (false, 5), (false, 0)]
+        );
+    }
+}
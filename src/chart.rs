@@ -0,0 +1,260 @@
+//! Structure of the Grafcet charts: initial steps, reachability, balanced forks and
+//! junctions, links to unknown elements and duplicate step numbers
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::grafcet::Links;
+use crate::lint::Finding;
+use crate::project::{GrafcetChart, GrafcetElement, GrafcetKind, Project};
+use crate::Guid;
+
+/// Short description of a chart element for warnings, e.g. "step 2 Home"
+fn element_label(project: &Project, chart: &GrafcetChart, element: &GrafcetElement) -> String {
+    let name = element.pou.map_or("", |p| project.pous[p].name.as_str());
+    let neighbour = |id: Option<&Guid>| {
+        let e = chart.element(id?)?;
+        matches!(e.kind, GrafcetKind::Step | GrafcetKind::Transition)
+            .then(|| element_label(project, chart, e))
+    };
+    match &element.kind {
+        GrafcetKind::Step => match element.step_number {
+            Some(n) => format!("step {} {}", n, name),
+            None => format!("step {}", name),
+        },
+        GrafcetKind::Transition => format!("transition {}", name),
+        GrafcetKind::OrFork => match neighbour(element.node.from.first()) {
+            Some(from) => format!("OR fork after {}", from),
+            None => format!("OR fork {}", element.node.id),
+        },
+        GrafcetKind::OrJunction => match neighbour(element.node.to.first()) {
+            Some(to) => format!("OR junction before {}", to),
+            None => format!("OR junction {}", element.node.id),
+        },
+        GrafcetKind::Other(kind) => format!("{} {}", kind, element.node.id),
+    }
+}
+
+/// The forks and junctions on a cycle of unique links from `start`, in chart order
+///
+/// Unnamed elements are named after the element of their only `To`, or else their only
+/// `From`, like in the textconv output, so a cycle of them can't be named.
+fn link_cycle<'a>(chart: &'a GrafcetChart, start: &'a GrafcetElement) -> Vec<&'a GrafcetElement> {
+    let mut path: Vec<&GrafcetElement> = Vec::new();
+    let mut e = start;
+    while !matches!(e.kind, GrafcetKind::Step | GrafcetKind::Transition) {
+        if let Some(pos) = path.iter().position(|p| std::ptr::eq(*p, e)) {
+            let cycle = &path[pos..];
+            return chart
+                .elements
+                .iter()
+                .filter(|c| cycle.iter().any(|p| std::ptr::eq(*p, *c)))
+                .collect();
+        }
+        path.push(e);
+        let node = &e.node;
+        let next = match (node.to.as_slice(), node.from.as_slice()) {
+            ([to], _) => to,
+            (_, [from]) => from,
+            _ => break,
+        };
+        e = match chart.element(next) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    Vec::new()
+}
+
+/// Check the structure of every Grafcet chart in the project
+pub fn check(project: &Project) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut step_numbers: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+    for chart in &project.grafcets {
+        let label = |e: &GrafcetElement| element_label(project, chart, e);
+        let mut warn = |location: String, message: String| {
+            findings.push(Finding {
+                lint: "grafcet",
+                location,
+                message,
+            })
+        };
+        let at = |e: &GrafcetElement| format!("{} > {}", chart.name, label(e));
+
+        for e in &chart.elements {
+            for link in e.node.from.iter().chain(&e.node.to) {
+                if chart.element(link).is_none() {
+                    warn(at(e), format!("links to unknown element {}", link));
+                }
+            }
+        }
+        let Links {
+            successors,
+            predecessors,
+        } = Links::new(chart);
+        let count =
+            |links: &HashMap<&Guid, HashSet<&Guid>>, id| links.get(id).map_or(0, |l| l.len());
+
+        // Forks and junctions are named after the element their only link leads to
+        let mut named: HashSet<&Guid> = HashSet::new();
+        for e in &chart.elements {
+            if named.contains(&e.node.id) {
+                continue;
+            }
+            let cycle = link_cycle(chart, e);
+            if cycle.is_empty() {
+                continue;
+            }
+            named.extend(cycle.iter().map(|e| &e.node.id));
+            let labels: Vec<String> = cycle.iter().map(|e| label(e)).collect();
+            warn(
+                at(e),
+                format!(
+                    "links form a cycle without a step or transition: {}",
+                    labels.join(", ")
+                ),
+            );
+        }
+
+        let steps = chart
+            .elements
+            .iter()
+            .filter(|e| e.kind == GrafcetKind::Step);
+        let initial: Vec<&GrafcetElement> = steps.clone().filter(|e| e.initial).collect();
+        match initial.len() {
+            0 => warn(chart.name.clone(), "no initial step".to_string()),
+            1 => {}
+            _ => warn(
+                chart.name.clone(),
+                format!(
+                    "multiple initial steps: {}",
+                    initial
+                        .iter()
+                        .map(|e| label(e))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ),
+        }
+
+        let mut reachable: HashSet<&Guid> = initial.iter().map(|e| &e.node.id).collect();
+        let mut queue: Vec<&Guid> = reachable.iter().copied().collect();
+        while let Some(id) = queue.pop() {
+            for next in successors.get(id).into_iter().flatten() {
+                if reachable.insert(next) {
+                    queue.push(next);
+                }
+            }
+        }
+
+        for step in steps {
+            if !initial.is_empty() && !reachable.contains(&step.node.id) {
+                warn(at(step), "unreachable from the initial step".to_string());
+            }
+            if count(&successors, &step.node.id) == 0 {
+                warn(at(step), "no outgoing transition".to_string());
+            }
+            if let Some(n) = step.step_number {
+                step_numbers.entry(n).or_default().push(at(step));
+            }
+        }
+
+        for e in &chart.elements {
+            let (inputs, outputs) = (
+                count(&predecessors, &e.node.id),
+                count(&successors, &e.node.id),
+            );
+            let balanced = match e.kind {
+                GrafcetKind::OrFork => inputs == 1 && outputs >= 2,
+                GrafcetKind::OrJunction => inputs >= 2 && outputs == 1,
+                GrafcetKind::Transition => inputs >= 1 && outputs >= 1,
+                _ => true,
+            };
+            if !balanced {
+                warn(
+                    at(e),
+                    format!("unbalanced, {} inputs and {} outputs", inputs, outputs),
+                );
+            }
+        }
+    }
+    for (n, steps) in step_numbers.into_iter().filter(|(_, s)| s.len() > 1) {
+        for step in &steps[1..] {
+            findings.push(Finding {
+                lint: "grafcet",
+                location: step.clone(),
+                message: format!("step number {} is also used by {}", n, steps[0]),
+            });
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let project = Project::from_bytes(orig.as_bytes()).unwrap();
+        assert_eq!(check(&project), vec![]);
+
+        // Make step 2 Home initial, give it the number of step 3 and break a link
+        let project = Project::from_bytes(
+            orig.replacen("<IsInitialStep>false<", "<IsInitialStep>true<", 1)
+                .replacen("<StepNumber>2<", "<StepNumber>3<", 1)
+                .replacen(
+                    "<To>3eb444fa-c0d6-4f1a-9c28-3ba1748fb353</To>",
+                    "<To>00000000-0000-0000-0000-000000000000</To>",
+                    1,
+                )
+                .as_bytes(),
+        )
+        .unwrap();
+        let findings: Vec<String> = check(&project).iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            findings,
+            vec![
+                "Operational states > OR fork after step 3 Home: links to unknown element 00000000-0000-0000-0000-000000000000 [grafcet]",
+                "Operational states: multiple initial steps: step 1 Lock and unlock, step 3 Home [grafcet]",
+                "Operational states > step 3 Below horizon, inch ok: step number 3 is also used by Operational states > step 3 Home [grafcet]",
+            ]
+        );
+
+        // An OR junction linked back to the OR fork it leads to
+        let project = Project::from_bytes(
+            orig.replacen(
+                "<From>3b8110b9-c7d0-4a8c-ab98-a78195a85e96</From>\n          </GrafcetOrFork>",
+                "<From>3fb6b0b5-5824-4fa1-b691-2e4aff8e3cbc</From>\n          </GrafcetOrFork>",
+                1,
+            )
+            .replacen(
+                "<To>3b8110b9-c7d0-4a8c-ab98-a78195a85e96</To>\n          </GrafcetOrJunction>",
+                "<To>6e8f2aed-7c64-4ef9-b7f5-853ddb94bcd0</To>\n          </GrafcetOrJunction>",
+                1,
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let findings: Vec<String> = check(&project).iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            findings,
+            vec![
+                "Operational states > OR fork 6e8f2aed-7c64-4ef9-b7f5-853ddb94bcd0: links form a cycle without a step or transition: OR fork 6e8f2aed-7c64-4ef9-b7f5-853ddb94bcd0, OR junction 3fb6b0b5-5824-4fa1-b691-2e4aff8e3cbc [grafcet]",
+                "Operational states > OR fork 6e8f2aed-7c64-4ef9-b7f5-853ddb94bcd0: unbalanced, 2 inputs and 2 outputs [grafcet]",
+                "Operational states > OR junction 3fb6b0b5-5824-4fa1-b691-2e4aff8e3cbc: unbalanced, 2 inputs and 2 outputs [grafcet]",
+            ]
+        );
+        // The transition before the junction can't be named after its target
+        assert_eq!(
+            project
+                .pous
+                .iter()
+                .find(|p| p.name == "Home switch active")
+                .unwrap()
+                .context(),
+            "Below horizon, inch ok->[Home switch active]->?"
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//! Coverage of the logic by simulation runs
+//!
+//! The simulator counts how often each rung is executed and ends with a true accumulator,
+//! which way each conditional jump, return and end goes, and which Grafcet steps are active
+//! and transitions fire. The coverage of several runs, e.g. the scenarios of a test suite,
+//! is merged into one report, with the rungs named by their context, and their number
+//! when several rungs of a POU share it.
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+
+use crate::project::{GrafcetElement, GrafcetKind, Project};
+
+/// Number of times something was reached, and how often it was true or taken
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Hits {
+    pub count: u64,
+    pub true_count: u64,
+}
+
+impl Hits {
+    fn add(&mut self, value: bool) {
+        self.count += 1;
+        self.true_count += value as u64;
+    }
+
+    fn merge(&mut self, other: Hits) {
+        self.count += other.count;
+        self.true_count += other.true_count;
+    }
+}
+
+/// Coverage counters of a simulation, by index into the project
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Coverage {
+    /// Executions and true results, by POU and rung
+    pub rungs: BTreeMap<(usize, usize), Hits>,
+    /// Evaluations of conditional jumps, returns and ends, and how often they were taken,
+    /// by POU, rung and line
+    pub branches: BTreeMap<(usize, usize, usize), Hits>,
+    /// Scans where a step was active, by chart and element
+    pub steps: BTreeMap<(usize, usize), u64>,
+    /// Times a transition fired, by chart and element
+    pub transitions: BTreeMap<(usize, usize), u64>,
+    /// Subroutine calls which were skipped, as subroutines aren't simulated, by POU, rung
+    /// and line
+    pub skipped_calls: BTreeMap<(usize, usize, usize), u64>,
+}
+
+impl Coverage {
+    pub(crate) fn rung(&mut self, pou: usize, rung: usize, result: bool) {
+        self.rungs.entry((pou, rung)).or_default().add(result);
+    }
+
+    pub(crate) fn branch(&mut self, key: (usize, usize, usize), taken: bool) {
+        self.branches.entry(key).or_default().add(taken);
+    }
+
+    pub(crate) fn step(&mut self, chart: usize, element: usize) {
+        *self.steps.entry((chart, element)).or_default() += 1;
+    }
+
+    pub(crate) fn transition(&mut self, chart: usize, element: usize) {
+        *self.transitions.entry((chart, element)).or_default() += 1;
+    }
+
+    pub(crate) fn skipped_call(&mut self, key: (usize, usize, usize)) {
+        *self.skipped_calls.entry(key).or_default() += 1;
+    }
+
+    /// Add the counters of another run
+    pub fn merge(&mut self, other: &Coverage) {
+        for (key, hits) in &other.rungs {
+            self.rungs.entry(*key).or_default().merge(*hits);
+        }
+        for (key, hits) in &other.branches {
+            self.branches.entry(*key).or_default().merge(*hits);
+        }
+        for (key, count) in &other.steps {
+            *self.steps.entry(*key).or_default() += count;
+        }
+        for (key, count) in &other.transitions {
+            *self.transitions.entry(*key).or_default() += count;
+        }
+        for (key, count) in &other.skipped_calls {
+            *self.skipped_calls.entry(*key).or_default() += count;
+        }
+    }
+
+    /// The coverage of every rung, branch, step and transition of the project
+    pub fn report<'a>(&self, project: &'a Project) -> CoverageReport<'a> {
+        let mut rungs = Vec::new();
+        for (p, pou) in project.pous.iter().enumerate() {
+            for (r, rung) in pou.rungs.iter().enumerate() {
+                if rung.il.is_empty() {
+                    continue;
+                }
+                let branches = rung
+                    .il
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, line)| is_branch(&line.text))
+                    .map(|(l, line)| BranchCoverage {
+                        line: l + 1,
+                        text: line.text.trim().to_string(),
+                        hits: self.branches.get(&(p, r, l)).copied().unwrap_or_default(),
+                    })
+                    .collect();
+                let skipped_calls = self
+                    .skipped_calls
+                    .range((p, r, 0)..=(p, r, usize::MAX))
+                    .map(|(&(_, _, l), &count)| (l + 1, rung.il[l].text.trim().to_string(), count))
+                    .collect();
+                rungs.push(RungCoverage {
+                    context: pou.rung_label(r),
+                    hits: self.rungs.get(&(p, r)).copied().unwrap_or_default(),
+                    branches,
+                    skipped_calls,
+                });
+            }
+        }
+        let mut elements = Vec::new();
+        for (c, chart) in project.grafcets.iter().enumerate() {
+            for (e, element) in chart.elements.iter().enumerate() {
+                let count = match element.kind {
+                    GrafcetKind::Step => self.steps.get(&(c, e)),
+                    GrafcetKind::Transition => self.transitions.get(&(c, e)),
+                    _ => continue,
+                };
+                elements.push(ElementCoverage {
+                    element,
+                    context: element.context.clone(),
+                    count: count.copied().unwrap_or_default(),
+                });
+            }
+        }
+        CoverageReport { rungs, elements }
+    }
+}
+
+/// The IL line is a conditional jump, call, return or end, e.g. `JMPC` or `RETCN`
+fn is_branch(line: &str) -> bool {
+    let op = line.split_ascii_whitespace().next().unwrap_or_default();
+    [
+        "JMPC", "JMPCN", "CALLC", "CALLCN", "RETC", "RETCN", "ENDC", "ENDCN",
+    ]
+    .contains(&op)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BranchCoverage {
+    /// Line number in the rung, from 1
+    pub line: usize,
+    pub text: String,
+    pub hits: Hits,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RungCoverage {
+    pub context: String,
+    pub hits: Hits,
+    pub branches: Vec<BranchCoverage>,
+    /// Line number, text and count of the subroutine calls which weren't simulated
+    pub skipped_calls: Vec<(usize, String, u64)>,
+}
+
+/// A Grafcet step and the scans it was active, or a transition and the times it fired
+#[derive(Clone, Debug)]
+pub struct ElementCoverage<'a> {
+    pub element: &'a GrafcetElement,
+    pub context: String,
+    pub count: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct CoverageReport<'a> {
+    /// Every rung with IL, in POU order
+    pub rungs: Vec<RungCoverage>,
+    /// Every step and transition, in chart order
+    pub elements: Vec<ElementCoverage<'a>>,
+}
+
+impl CoverageReport<'_> {
+    /// Descriptions of the rungs which were never true, branches which never went one of
+    /// their ways, subroutine calls which were skipped, and steps and transitions which
+    /// were never activated
+    pub fn uncovered(&self) -> Vec<String> {
+        let mut uncovered = Vec::new();
+        for rung in &self.rungs {
+            if rung.hits.count == 0 {
+                uncovered.push(format!("{}: never executed", rung.context));
+                continue;
+            }
+            if rung.hits.true_count == 0 {
+                uncovered.push(format!("{}: never true", rung.context));
+            }
+            for branch in &rung.branches {
+                let hits = branch.hits;
+                let never = match (hits.true_count, hits.count - hits.true_count) {
+                    (0, _) => "never taken",
+                    (_, 0) => "always taken",
+                    _ => continue,
+                };
+                uncovered.push(format!(
+                    "{} line {} {}: {}",
+                    rung.context, branch.line, branch.text, never
+                ));
+            }
+            for (line, text, count) in &rung.skipped_calls {
+                uncovered.push(format!(
+                    "{} line {} {}: not simulated, skipped {} times",
+                    rung.context, line, text, count
+                ));
+            }
+        }
+        for element in self.elements.iter().filter(|e| e.count == 0) {
+            let never = match element.element.kind {
+                GrafcetKind::Step => "never active",
+                _ => "never fired",
+            };
+            uncovered.push(format!("{}: {}", element.context, never));
+        }
+        uncovered
+    }
+}
+
+impl Display for CoverageReport<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let count = |covered: usize, total: usize, what: &str| {
+            let percent = 100.0 * covered as f64 / total.max(1) as f64;
+            format!("{:5.1}% {} ({} of {})", percent, what, covered, total)
+        };
+        let rungs = &self.rungs;
+        let branches: Vec<&BranchCoverage> = rungs.iter().flat_map(|r| &r.branches).collect();
+        let (steps, transitions): (Vec<_>, Vec<_>) = self
+            .elements
+            .iter()
+            .partition(|e| e.element.kind == GrafcetKind::Step);
+        let lines = [
+            count(
+                rungs.iter().filter(|r| r.hits.count > 0).count(),
+                rungs.len(),
+                "rungs executed",
+            ),
+            count(
+                rungs.iter().filter(|r| r.hits.true_count > 0).count(),
+                rungs.len(),
+                "rungs true",
+            ),
+            count(
+                branches
+                    .iter()
+                    .map(|b| {
+                        (b.hits.true_count > 0) as usize
+                            + (b.hits.count > b.hits.true_count) as usize
+                    })
+                    .sum(),
+                2 * branches.len(),
+                "branch directions",
+            ),
+            count(
+                steps.iter().filter(|s| s.count > 0).count(),
+                steps.len(),
+                "steps active",
+            ),
+            count(
+                transitions.iter().filter(|t| t.count > 0).count(),
+                transitions.len(),
+                "transitions fired",
+            ),
+        ];
+        for line in &lines {
+            writeln!(f, "{}", line)?;
+        }
+        for line in self.uncovered() {
+            writeln!(f, "  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+    use crate::project::{Pou, Rung};
+    use crate::sim::Simulator;
+
+    #[test]
+    fn test_coverage() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let mut sim = Simulator::new(&project).unwrap();
+        sim.memory.set_bit("%I0.2", true);
+        sim.memory.set_bit("%I0.3", true);
+        sim.memory.set_bit("%I0.6", true);
+        for _ in 0..3 {
+            sim.scan().unwrap();
+        }
+        let report = sim.coverage.report(&project);
+        let uncovered = report.uncovered();
+        let has = |line: &str| uncovered.iter().any(|u| u == line);
+        assert!(!has("Operational states > Home: never active"));
+        assert!(has(
+            "Operational states > Below horizon, inch ok: never active"
+        ));
+        assert!(!has(
+            "Lock and unlock->[Stow lock released]->Home: never fired"
+        ));
+        assert!(has(
+            "Home->[Lock sequence started]->Lock and unlock: never fired"
+        ));
+        assert!(has("Validate inputs > Flaps: never true"));
+        // Unnamed rungs of a POU are told apart by their number
+        assert!(has(
+            "Operational states > Above horizon, flaps out rung 3: never executed"
+        ));
+
+        let program = Pou::program(
+            "Main",
+            vec![
+                Rung::from_il(&["LD %I0.0", "JMPC %L1", "ST %Q0.0"]),
+                Rung::from_il(&["%L1:", "LD %I0.1", "ST %Q0.1"]),
+            ],
+        );
+        let project = Project {
+            pous: vec![program],
+            ..Default::default()
+        };
+        let mut sim = Simulator::new(&project).unwrap();
+        sim.scan().unwrap();
+        let mut coverage = sim.coverage.clone();
+        assert_eq!(
+            coverage.report(&project).uncovered(),
+            [
+                "Main rung 1: never true",
+                "Main rung 1 line 2 JMPC %L1: never taken",
+                "Main rung 2: never true"
+            ]
+        );
+        let mut sim = Simulator::new(&project).unwrap();
+        sim.memory.set_bit("%I0.0", true);
+        sim.memory.set_bit("%I0.1", true);
+        sim.scan().unwrap();
+        coverage.merge(&sim.coverage);
+        assert_eq!(
+            coverage.rungs[&(0, 0)],
+            Hits {
+                count: 2,
+                true_count: 1
+            }
+        );
+        assert!(coverage.report(&project).uncovered().is_empty());
+        assert!(coverage
+            .report(&project)
+            .to_string()
+            .starts_with("100.0% rungs executed (2 of 2)\n100.0% rungs true (2 of 2)\n100.0% branch directions (2 of 2)\n"));
+    }
+}
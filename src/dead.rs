@@ -0,0 +1,263 @@
+//! Dead and constant logic: conditions which are always true or false, memory bits which
+//! are written but never read, and code which can't be reached after an unconditional
+//! jump, return or end
+use std::collections::{HashMap, HashSet};
+
+use crate::equation::{equations, symbol_name, BoolExpr, Effect, Equation};
+use crate::equivalence::MAX_INPUTS;
+use crate::il::{parse_rung, Condition, Instruction, Statement};
+use crate::lint::{used_addresses_by, Finding};
+use crate::project::{PouKind, Project};
+use crate::sim::udfb_scope;
+use crate::xref::{cmp_address, references, Access};
+
+const LINT: &str = "dead-logic";
+
+/// The value of a condition which doesn't depend on its inputs, e.g. `%M0 OR NOT %M0`
+fn constant(condition: &BoolExpr) -> Option<bool> {
+    if let BoolExpr::Const(b) = condition {
+        return Some(*b);
+    }
+    let atoms = condition.atoms();
+    if atoms.len() > MAX_INPUTS {
+        return None;
+    }
+    let mut values = (0..1u64 << atoms.len()).map(|assignment| {
+        condition.eval(&mut |atom| {
+            let i = atoms.iter().position(|a| *a == atom).unwrap_or_default();
+            assignment & 1 << i != 0
+        })
+    });
+    let first = values.next()?;
+    values.all(|v| v == first).then_some(first)
+}
+
+/// Name of the user function block parameters, e.g. `%UDF2.PARAMW0:X0` is
+/// `input COMMAND of POL_DRIVE`
+fn udfb_inputs(project: &Project) -> HashMap<String, String> {
+    let blocks = project
+        .pous
+        .iter()
+        .filter(|p| p.kind == PouKind::FunctionBlock);
+    let mut inputs = HashMap::new();
+    for (n, pou) in blocks.enumerate() {
+        for (name, bit) in udfb_scope(&pou.fields, n) {
+            if bit.contains(".PARAMW") {
+                let name = format!("input {} of {}", name.trim_start_matches('%'), pou.name);
+                inputs.insert(bit, name);
+            }
+        }
+    }
+    inputs
+}
+
+/// Problem with the condition of an equation, if it is constant
+fn constant_condition(
+    equation: &Equation,
+    name: &dyn Fn(&str) -> String,
+    inputs: &HashMap<String, String>,
+) -> Option<String> {
+    let value = constant(&equation.condition)?;
+    let literal = matches!(equation.condition, BoolExpr::Const(_));
+    let what = match (&equation.effect, value) {
+        (Effect::Assign(bit), _) if bit.contains(".PARAMW") => match inputs.get(bit) {
+            Some(input) => format!("{} is always {}", input, value as u8),
+            // The enable input, which the ladder editor sets with `LD 1`
+            None => return None,
+        },
+        (Effect::Assign(bit), _) => format!("{} is always {}", name(bit), value as u8),
+        // `LD 1` before an operation block or a latch is the usual unconditional execution
+        (_, true) if literal => return None,
+        (Effect::Transition, false) => "the transition never fires".to_string(),
+        (Effect::Transition, true) => "the transition always fires".to_string(),
+        (_, false) => "never executed".to_string(),
+        (_, true) => "always executed".to_string(),
+    };
+    let condition = match literal {
+        true => String::new(),
+        false => format!(
+            ", {} is always {}",
+            equation.condition.render(name),
+            value as u8
+        ),
+    };
+    Some(format!("{}{}", what, condition))
+}
+
+/// Statements which can't be reached in the rungs of a POU, by rung, as the first
+/// unreachable line and the jump, return or end before it
+fn unreachable(code: &[Vec<Statement>]) -> Vec<(usize, usize, String)> {
+    let targets: HashSet<&str> = code
+        .iter()
+        .flatten()
+        .filter_map(|s| match &s.instruction {
+            Instruction::Jump { label, .. } => Some(label.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mut found = Vec::new();
+    let mut after: Option<String> = None;
+    for (r, rung) in code.iter().enumerate() {
+        let mut reported = false;
+        for (l, statement) in rung.iter().enumerate() {
+            match &statement.instruction {
+                Instruction::Label(label) if targets.contains(label.as_str()) => after = None,
+                instruction => {
+                    if let (Some(jump), false) = (&after, reported) {
+                        found.push((r, l, jump.clone()));
+                        reported = true;
+                    }
+                    if let Instruction::Jump {
+                        condition: Condition::Always,
+                        ..
+                    }
+                    | Instruction::Return(Condition::Always)
+                    | Instruction::End(Condition::Always) = instruction
+                    {
+                        after.get_or_insert_with(|| instruction.to_string());
+                    }
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Constant conditions, unreachable code and memory bits which are never read
+pub fn check(project: &Project) -> Vec<Finding> {
+    let symbols = project.symbols();
+    let name = |a: &str| symbol_name(a, &symbols);
+    let inputs = udfb_inputs(project);
+    let mut findings = Vec::new();
+    for pou in &project.pous {
+        let mut code = Vec::new();
+        for (r, rung) in pou.rungs.iter().enumerate() {
+            let statements = match parse_rung(rung) {
+                Ok(statements) => statements,
+                Err(e) => {
+                    findings.push(Finding {
+                        lint: LINT,
+                        location: pou.rung_context(r).to_string(),
+                        message: format!("the IL can't be parsed and isn't checked, {}", e),
+                    });
+                    Vec::new()
+                }
+            };
+            // The conditions before any label, jump, return or end
+            let start = match statements.first().map(|s| &s.instruction) {
+                Some(Instruction::Label(_)) => 1,
+                _ => 0,
+            };
+            let end = statements[start..]
+                .iter()
+                .position(|s| equations(std::slice::from_ref(s)).is_none())
+                .map_or(statements.len(), |n| start + n);
+            for equation in equations(&statements[start..end]).unwrap_or_default() {
+                if let Some(message) = constant_condition(&equation, &name, &inputs) {
+                    let line = equation.span.line;
+                    findings.push(Finding {
+                        lint: LINT,
+                        location: pou.rung_context(r).to_string(),
+                        message: format!("line {} `{}`: {}", line + 1, rung.il[line].text, message),
+                    });
+                }
+            }
+            code.push(statements);
+        }
+        for (r, l, jump) in unreachable(&code) {
+            findings.push(Finding {
+                lint: LINT,
+                location: pou.rung_context(r).to_string(),
+                message: format!(
+                    "line {} `{}` and the following code is unreachable after `{}`",
+                    l + 1,
+                    pou.rungs[r].il[l].text,
+                    jump
+                ),
+            });
+        }
+    }
+
+    // Memory bits, with the reads of word bits and bit strings counting for the word, and
+    // the reads of bit strings for their bits
+    let mut written: HashMap<String, (usize, usize)> = HashMap::new();
+    for r in references(project)
+        .into_iter()
+        .filter(|r| r.access == Access::Write)
+    {
+        written.entry(r.address).or_insert((r.pou, r.rung));
+    }
+    let read = used_addresses_by(project, |access| access != Access::Write);
+    let mut unread: Vec<(String, (usize, usize))> = written
+        .into_iter()
+        .filter(|(address, _)| {
+            address.starts_with("%M")
+                && address[2..].starts_with(|c: char| c.is_ascii_digit())
+                && !read.contains(address)
+        })
+        .collect();
+    unread.sort_by(|a, b| cmp_address(&a.0, &b.0));
+    for (address, (pou, rung)) in unread {
+        findings.push(Finding {
+            lint: LINT,
+            location: project.pous[pou].rung_context(rung).to_string(),
+            message: format!("{} is written but never read", name(&address)),
+        });
+    }
+    findings
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+    use crate::project::{Pou, Rung};
+
+    #[test]
+    fn test_check() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let findings: Vec<String> = check(&project).iter().map(Finding::to_string).collect();
+        assert_eq!(findings.len(), 13);
+        assert_eq!(
+            findings[0],
+            "Operational states > Lock and unlock > Stop decl drive: line 4 `ST  %UDF4.PARAMW0:X0`: input RUN_DECL of DECL_DRIVE is always 0 [dead-logic]"
+        );
+
+        let program = Pou::program(
+            "Main",
+            vec![
+                Rung::from_il(&["LD 0", "S %M5", "LD 1", "[ %MW0 := 1 ]"]),
+                Rung::from_il(&[
+                    "LD %I0.0",
+                    "ORN %I0.0",
+                    "ST %Q0.0",
+                    "JMP %L1",
+                    "LD %M0",
+                    "ST %M1",
+                ]),
+                Rung::from_il(&["LD %I0.1", "ST %Q0.1"]),
+                Rung::from_il(&["%L1:", "LD %I0.2", "ANDN %M1", "ST %Q0.2"]),
+                // %M11 is read as a part of the bit string
+                Rung::from_il(&["LD %I0.3", "ST %M11", "[ %MW1 := %M8:8 ]"]),
+                Rung::from_il(&["LD %I0.4", "MPS", "ST %Q0.4"]),
+            ],
+        );
+        let project = Project {
+            pous: vec![program],
+            ..Default::default()
+        };
+        let findings: Vec<String> = check(&project).iter().map(Finding::to_string).collect();
+        assert_eq!(
+            findings,
+            [
+                "Main: line 2 `S %M5`: never executed [dead-logic]",
+                "Main: line 3 `ST %Q0.0`: %Q0.0 is always 1, %I0.0 OR NOT %I0.0 is always 1 [dead-logic]",
+                "Main: the IL can't be parsed and isn't checked, line 2 column 1: MPS without MPP [dead-logic]",
+                "Main: line 5 `LD %M0` and the following code is unreachable after `JMP  %L1` [dead-logic]",
+                "Main: line 1 `LD %I0.1` and the following code is unreachable after `JMP  %L1` [dead-logic]",
+                "Main: %M5 is written but never read [dead-logic]",
+            ]
+        );
+    }
+}
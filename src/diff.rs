@@ -0,0 +1,1082 @@
+//! Semantic diff between two parsed projects
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter, Write};
+
+use itertools::Itertools;
+
+use crate::grafcet::Links;
+use crate::project::{GrafcetChart, GrafcetKind, IlLine, ObjectEntry, Pou, PouKind, Project, Rung};
+use crate::tree::Element;
+use crate::volatile::Volatile;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// One step of an edit script between two slices
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Edit {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// Longest common subsequence edit script from `old` to `new`
+pub fn diff_slices<T, U>(old: &[T], new: &[U], eq: impl Fn(&T, &U) -> bool) -> Vec<Edit> {
+    let (n, m) = (old.len(), new.len());
+    // lcs[i][j] is the LCS length of old[i..] and new[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if eq(&old[i], &new[j]) {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut edits = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && eq(&old[i], &new[j]) {
+            edits.push(Edit::Same(i, j));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            edits.push(Edit::Removed(i));
+            i += 1;
+        } else {
+            edits.push(Edit::Added(j));
+            j += 1;
+        }
+    }
+    edits
+}
+
+/// Replace runs of removals directly followed by additions with pairs of modifications
+fn pair_up(edits: Vec<Edit>) -> Vec<(Option<usize>, Option<usize>)> {
+    type Pair = (Option<usize>, Option<usize>);
+    fn flush(removed: &mut Vec<usize>, added: &mut Vec<usize>, out: &mut Vec<Pair>) {
+        for k in 0..removed.len().max(added.len()) {
+            out.push((removed.get(k).copied(), added.get(k).copied()));
+        }
+        removed.clear();
+        added.clear();
+    }
+    let mut out = Vec::with_capacity(edits.len());
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for edit in edits {
+        match edit {
+            Edit::Removed(i) => {
+                if !added.is_empty() {
+                    flush(&mut removed, &mut added, &mut out);
+                }
+                removed.push(i)
+            }
+            Edit::Added(j) => added.push(j),
+            Edit::Same(i, j) => {
+                flush(&mut removed, &mut added, &mut out);
+                out.push((Some(i), Some(j)));
+            }
+        }
+    }
+    flush(&mut removed, &mut added, &mut out);
+    out
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TokenKind {
+    Opcode,
+    Operand,
+    /// The symbol of the preceding operand, from the project symbol tables
+    Symbol,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+}
+
+/// Split an IL line into opcode, operand and symbol tokens
+pub fn tokenize(line: &str, symbols: &HashMap<&str, &str>) -> Vec<Token> {
+    // Operation blocks, e.g. "[ %MW0 := %MW1 AND 16#FF ]", contain operators between the operands
+    let block = line.trim_start().starts_with('[');
+    let mut tokens = Vec::new();
+    for (n, word) in line.split_ascii_whitespace().enumerate() {
+        let is_operator = word.starts_with(|c: char| !c.is_ascii_alphanumeric() && c != '%')
+            || (block && word.chars().all(|c| c.is_ascii_uppercase()));
+        let kind = if n == 0 || is_operator {
+            TokenKind::Opcode
+        } else {
+            TokenKind::Operand
+        };
+        tokens.push(Token {
+            kind,
+            text: word.to_string(),
+        });
+        if kind == TokenKind::Operand {
+            if let Some(symbol) = symbols.get(word) {
+                tokens.push(Token {
+                    kind: TokenKind::Symbol,
+                    text: symbol.to_string(),
+                });
+            }
+        }
+    }
+    tokens
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TokenChange {
+    Same(Token),
+    Removed(Token),
+    Added(Token),
+}
+
+#[derive(Clone, Debug)]
+pub enum LineChange<'a> {
+    Same(&'a IlLine),
+    Removed(&'a IlLine),
+    Added(&'a IlLine),
+    /// The instruction or its comment changed
+    Changed {
+        old: &'a IlLine,
+        new: &'a IlLine,
+        tokens: Vec<TokenChange>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct RungDiff<'a> {
+    pub context: String,
+    pub old: Option<&'a Rung>,
+    pub new: Option<&'a Rung>,
+    /// The IL of the rung, empty for added and removed rungs
+    pub lines: Vec<LineChange<'a>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PouDiff<'a> {
+    pub context: String,
+    pub kind: PouKind,
+    pub old: Option<&'a Pou>,
+    pub new: Option<&'a Pou>,
+    /// Only the rungs which differ
+    pub rungs: Vec<RungDiff<'a>>,
+    /// Changed POU fields, such as function block inputs and outputs
+    pub fields: Vec<SettingChange<'a>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ObjectChange<'a> {
+    pub address: &'a str,
+    pub old: Option<&'a ObjectEntry>,
+    pub new: Option<&'a ObjectEntry>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SettingChange<'a> {
+    pub key: &'a str,
+    pub old: Option<&'a str>,
+    pub new: Option<&'a str>,
+}
+
+/// A Grafcet chart element whose kind, step number, initial flag or links changed
+///
+/// Elements are described by name, with their links resolved to the names of the linked
+/// elements, e.g. `step Home #2 from [transition Stow lock released] to [OR fork after step Home]`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GrafcetChange {
+    pub chart: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ProjectDiff<'a> {
+    pub pous: Vec<PouDiff<'a>>,
+    pub grafcets: Vec<GrafcetChange>,
+    pub objects: Vec<ObjectChange<'a>>,
+    pub settings: Vec<SettingChange<'a>>,
+    /// Ignored changes to volatile fields
+    pub volatile: Vec<String>,
+    /// Element path of the first change outside of the volatile fields, from a comparison of the
+    /// whole files, which also finds changes outside of the project model, e.g. to watch lists
+    pub first_change: Option<String>,
+}
+
+impl RungDiff<'_> {
+    /// Number of IL lines which were added, removed or changed
+    pub fn changed_lines(&self) -> usize {
+        match (self.old, self.new) {
+            (Some(_), Some(_)) => self
+                .lines
+                .iter()
+                .filter(|l| !matches!(l, LineChange::Same(_)))
+                .count(),
+            (Some(rung), None) | (None, Some(rung)) => rung.il.len(),
+            (None, None) => 0,
+        }
+    }
+}
+
+impl<'a> ProjectDiff<'a> {
+    /// Diff two projects, ignoring the default set of volatile fields
+    pub fn new(old: &'a Project, new: &'a Project) -> Self {
+        Self::with_volatile(old, new, &Volatile::default())
+    }
+
+    pub fn with_volatile(old: &'a Project, new: &'a Project, volatile: &Volatile) -> Self {
+        let mut volatile_changes = Vec::new();
+        let mut settings = diff_settings(&old.settings, &new.settings);
+        settings.retain(|c| {
+            let ignore = volatile.is_volatile_key(c.key);
+            if ignore {
+                volatile_changes.push(c.key.to_string());
+            }
+            !ignore
+        });
+        Self {
+            pous: diff_pous(old, new, volatile, &mut volatile_changes),
+            grafcets: diff_grafcets(old, new),
+            objects: diff_objects(&old.objects, &new.objects),
+            settings,
+            volatile: volatile_changes,
+            first_change: DocumentCompare::new(volatile).first_change(&old.document, &new.document),
+        }
+    }
+
+    /// True if there are no changes to the project model, except for ignored volatile fields
+    pub fn is_empty(&self) -> bool {
+        self.pous.is_empty()
+            && self.grafcets.is_empty()
+            && self.objects.is_empty()
+            && self.settings.is_empty()
+    }
+
+    /// The projects only differ in volatile fields, e.g. the file was just opened and saved
+    pub fn is_metadata_only(&self) -> bool {
+        self.first_change.is_none() && !self.volatile.is_empty()
+    }
+
+    /// Render the diff as text, with ANSI colors highlighting the changed tokens if `color` is set
+    pub fn render(&self, color: bool) -> String {
+        let mut out = String::new();
+        let (red, green, bold, reset) = if color {
+            (RED, GREEN, BOLD, RESET)
+        } else {
+            ("", "", "", "")
+        };
+        let status = |old: bool, new: bool| match (old, new) {
+            (false, true) => " (added)",
+            (true, false) => " (removed)",
+            _ => "",
+        };
+        for pou in &self.pous {
+            let _ = writeln!(
+                out,
+                "{}=== {} {}{}{}",
+                bold,
+                pou.kind,
+                pou.context,
+                status(pou.old.is_some(), pou.new.is_some()),
+                reset
+            );
+            if let (Some(old), Some(new)) = (pou.old, pou.new) {
+                if old.comment != new.comment {
+                    let _ = writeln!(out, "~ comment: {:?} -> {:?}", old.comment, new.comment);
+                }
+                if old.section != new.section {
+                    let _ = writeln!(out, "~ section: {:?} -> {:?}", old.section, new.section);
+                }
+            }
+            for field in &pou.fields {
+                let _ = writeln!(
+                    out,
+                    "~ {}: {:?} -> {:?}",
+                    field.key,
+                    field.old.unwrap_or("-"),
+                    field.new.unwrap_or("-")
+                );
+            }
+            for rung in &pou.rungs {
+                let _ = writeln!(
+                    out,
+                    "{}@@ {}{}{}",
+                    bold,
+                    rung.context,
+                    status(rung.old.is_some(), rung.new.is_some()),
+                    reset
+                );
+                match (rung.old, rung.new) {
+                    (Some(old), None) => {
+                        for line in &old.il {
+                            let _ = writeln!(out, "{}- {}{}", red, line.text, reset);
+                        }
+                    }
+                    (None, Some(new)) => {
+                        for line in &new.il {
+                            let _ = writeln!(out, "{}+ {}{}", green, line.text, reset);
+                        }
+                    }
+                    _ => self.render_lines(&mut out, rung, color),
+                }
+            }
+        }
+        for change in &self.grafcets {
+            let _ = writeln!(
+                out,
+                "{}grafcet {}{}: {} -> {}",
+                bold,
+                change.chart,
+                reset,
+                change.old.as_deref().unwrap_or("-"),
+                change.new.as_deref().unwrap_or("-")
+            );
+        }
+        for obj in &self.objects {
+            let show = |o: Option<&ObjectEntry>| {
+                o.map_or_else(|| "-".to_string(), |o| format!("{:?}", o.symbol))
+            };
+            let _ = writeln!(
+                out,
+                "{}object {}{}: {} -> {}",
+                bold,
+                obj.address,
+                reset,
+                show(obj.old),
+                show(obj.new)
+            );
+        }
+        for setting in &self.settings {
+            let _ = writeln!(
+                out,
+                "{}setting {}{}: {:?} -> {:?}",
+                bold,
+                setting.key,
+                reset,
+                setting.old.unwrap_or("-"),
+                setting.new.unwrap_or("-")
+            );
+        }
+        if self.is_metadata_only() {
+            let _ = writeln!(out, "metadata-only change: {}", self.volatile.join(", "));
+        }
+        if let (true, Some(path)) = (self.is_empty(), &self.first_change) {
+            let _ = writeln!(out, "{}other change{}: {}", bold, reset, path);
+        }
+        out
+    }
+
+    fn render_lines(&self, out: &mut String, rung: &RungDiff, color: bool) {
+        let (red, green, reset) = if color {
+            (RED, GREEN, RESET)
+        } else {
+            ("", "", "")
+        };
+        let (old, new) = (rung.old.unwrap(), rung.new.unwrap());
+        if old.name != new.name {
+            let _ = writeln!(out, "~ name: {:?} -> {:?}", old.name, new.name);
+        }
+        if old.main_comment != new.main_comment {
+            let _ = writeln!(
+                out,
+                "~ comment: {:?} -> {:?}",
+                old.main_comment, new.main_comment
+            );
+        }
+        if old.ladder != new.ladder {
+            let _ = writeln!(out, "~ ladder diagram changed");
+        }
+        for line in &rung.lines {
+            let _ = match line {
+                LineChange::Same(l) => writeln!(out, "  {}", l.text),
+                LineChange::Removed(l) => writeln!(out, "{}- {}{}", red, l.text, reset),
+                LineChange::Added(l) => writeln!(out, "{}+ {}{}", green, l.text, reset),
+                LineChange::Changed { old, new, tokens } => {
+                    let _ = writeln!(out, "~ {}", render_tokens(tokens, color));
+                    if old.comment != new.comment {
+                        writeln!(out, "    comment: {:?} -> {:?}", old.comment, new.comment)
+                    } else {
+                        Ok(())
+                    }
+                }
+            };
+        }
+    }
+}
+
+/// Change counts for one POU
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PouStat {
+    pub context: String,
+    pub rungs_added: usize,
+    pub rungs_removed: usize,
+    /// Rungs with changed IL, name, comment or label
+    pub rungs_modified: usize,
+    /// Rungs where only the ladder diagram changed, e.g. for a renamed symbol
+    pub ladder_only: usize,
+    pub lines_changed: usize,
+}
+
+/// Summary of a `ProjectDiff`, in the style of `git diff --stat`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DiffStat {
+    pub pous: Vec<PouStat>,
+    /// Grafcet chart elements which were added, removed or changed
+    pub grafcet: usize,
+    /// Objects whose symbol was added, removed or renamed
+    pub symbols: usize,
+    /// Other object changes and changed settings
+    pub configuration: usize,
+}
+
+impl ProjectDiff<'_> {
+    pub fn stat(&self) -> DiffStat {
+        let pous = self
+            .pous
+            .iter()
+            .map(|pou| {
+                let mut stat = PouStat {
+                    context: pou.context.clone(),
+                    ..Default::default()
+                };
+                for rung in &pou.rungs {
+                    match (rung.old, rung.new) {
+                        (Some(old), Some(new)) if is_ladder_only(old, new) => stat.ladder_only += 1,
+                        (Some(_), Some(_)) => stat.rungs_modified += 1,
+                        (None, _) => stat.rungs_added += 1,
+                        (_, None) => stat.rungs_removed += 1,
+                    }
+                    stat.lines_changed += rung.changed_lines();
+                }
+                stat
+            })
+            .collect();
+        fn symbol(o: Option<&ObjectEntry>) -> &str {
+            o.map_or("", |o| o.symbol.as_str())
+        }
+        let symbols = self
+            .objects
+            .iter()
+            .filter(|o| symbol(o.old) != symbol(o.new))
+            .count();
+        DiffStat {
+            pous,
+            grafcet: self.grafcets.len(),
+            symbols,
+            configuration: self.objects.len() - symbols + self.settings.len(),
+        }
+    }
+}
+
+impl Display for DiffStat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let width = self.pous.iter().map(|p| p.context.len()).max().unwrap_or(0);
+        let mut total = PouStat::default();
+        for pou in &self.pous {
+            writeln!(
+                f,
+                " {:width$} | rungs +{} -{} ~{} | {} IL lines | {} ladder only",
+                pou.context,
+                pou.rungs_added,
+                pou.rungs_removed,
+                pou.rungs_modified,
+                pou.lines_changed,
+                pou.ladder_only,
+                width = width
+            )?;
+            total.rungs_added += pou.rungs_added;
+            total.rungs_removed += pou.rungs_removed;
+            total.rungs_modified += pou.rungs_modified;
+            total.ladder_only += pou.ladder_only;
+            total.lines_changed += pou.lines_changed;
+        }
+        writeln!(
+            f,
+            " {} POUs changed, {} rungs added, {} removed, {} modified, {} ladder only, \
+             {} IL lines changed, {} Grafcet changes, {} symbol changes, {} configuration changes",
+            self.pous.len(),
+            total.rungs_added,
+            total.rungs_removed,
+            total.rungs_modified,
+            total.ladder_only,
+            total.lines_changed,
+            self.grafcet,
+            self.symbols,
+            self.configuration
+        )
+    }
+}
+
+fn token_text(token: &Token) -> String {
+    match token.kind {
+        TokenKind::Symbol => format!("[{}]", token.text),
+        _ => token.text.clone(),
+    }
+}
+
+/// Render a token level line diff, either in color or in `git diff --word-diff` style
+pub fn render_tokens(tokens: &[TokenChange], color: bool) -> String {
+    tokens
+        .iter()
+        .map(|t| match (t, color) {
+            (TokenChange::Same(t), _) => token_text(t),
+            (TokenChange::Removed(t), true) => format!("{}{}{}", RED, token_text(t), RESET),
+            (TokenChange::Added(t), true) => format!("{}{}{}", GREEN, token_text(t), RESET),
+            (TokenChange::Removed(t), false) => format!("[-{}-]", token_text(t)),
+            (TokenChange::Added(t), false) => format!("{{+{}+}}", token_text(t)),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn diff_tokens(old: &[Token], new: &[Token]) -> Vec<TokenChange> {
+    diff_slices(old, new, |a, b| a == b)
+        .into_iter()
+        .map(|edit| match edit {
+            Edit::Same(i, _) => TokenChange::Same(old[i].clone()),
+            Edit::Removed(i) => TokenChange::Removed(old[i].clone()),
+            Edit::Added(j) => TokenChange::Added(new[j].clone()),
+        })
+        .collect()
+}
+
+fn same_instruction(a: &IlLine, b: &IlLine) -> bool {
+    a.words().eq(b.words())
+}
+
+fn diff_lines<'a>(
+    old: &'a Rung,
+    new: &'a Rung,
+    old_symbols: &HashMap<&str, &str>,
+    new_symbols: &HashMap<&str, &str>,
+) -> Vec<LineChange<'a>> {
+    let edits = diff_slices(&old.il, &new.il, same_instruction);
+    pair_up(edits)
+        .into_iter()
+        .map(|pair| match pair {
+            (Some(i), Some(j)) if old.il[i] == new.il[j] => LineChange::Same(&new.il[j]),
+            (Some(i), Some(j)) => {
+                let (old, new) = (&old.il[i], &new.il[j]);
+                let tokens = diff_tokens(
+                    &tokenize(&old.text, old_symbols),
+                    &tokenize(&new.text, new_symbols),
+                );
+                LineChange::Changed { old, new, tokens }
+            }
+            (Some(i), None) => LineChange::Removed(&old.il[i]),
+            (None, Some(j)) => LineChange::Added(&new.il[j]),
+            (None, None) => unreachable!(),
+        })
+        .collect()
+}
+
+/// Rungs are aligned on their name and instructions, other changes make a rung modified
+fn same_rung(a: &Rung, b: &Rung) -> bool {
+    a.name == b.name
+        && a.il.len() == b.il.len()
+        && a.il.iter().zip(&b.il).all(|(a, b)| same_instruction(a, b))
+}
+
+/// Only the ladder diagram differs, the IL and the other fields are the same
+fn is_ladder_only(old: &Rung, new: &Rung) -> bool {
+    old.ladder != new.ladder
+        && (&old.name, &old.main_comment, &old.label, &old.il)
+            == (&new.name, &new.main_comment, &new.label, &new.il)
+}
+
+/// Compare all fields of two rungs, except the volatile ones
+fn rung_changed(old: &Rung, new: &Rung, volatile: &Volatile) -> bool {
+    (old.ladder_selected != new.ladder_selected && !volatile.is_volatile("IsLadderSelected"))
+        || (
+            &old.name,
+            &old.main_comment,
+            &old.label,
+            &old.il,
+            &old.ladder,
+        ) != (
+            &new.name,
+            &new.main_comment,
+            &new.label,
+            &new.il,
+            &new.ladder,
+        )
+}
+
+fn diff_rungs<'a>(
+    old: &'a Pou,
+    new: &'a Pou,
+    (old_symbols, new_symbols): (&HashMap<&str, &str>, &HashMap<&str, &str>),
+    volatile: &Volatile,
+    volatile_changes: &mut Vec<String>,
+) -> Vec<RungDiff<'a>> {
+    let edits = diff_slices(&old.rungs, &new.rungs, same_rung);
+    let mut rungs = Vec::new();
+    for pair in pair_up(edits) {
+        let rung = match pair {
+            (Some(i), Some(j)) => {
+                let (o, n) = (&old.rungs[i], &new.rungs[j]);
+                if o.ladder_selected != n.ladder_selected
+                    && volatile.is_volatile("IsLadderSelected")
+                {
+                    volatile_changes.push(format!("{} > IsLadderSelected", new.rung_context(j)));
+                }
+                if !rung_changed(o, n, volatile) {
+                    continue;
+                }
+                RungDiff {
+                    context: new.rung_context(j).to_string(),
+                    old: Some(o),
+                    new: Some(n),
+                    lines: diff_lines(o, n, old_symbols, new_symbols),
+                }
+            }
+            (Some(i), None) => RungDiff {
+                context: old.rung_context(i).to_string(),
+                old: Some(&old.rungs[i]),
+                new: None,
+                lines: Vec::new(),
+            },
+            (None, Some(j)) => RungDiff {
+                context: new.rung_context(j).to_string(),
+                old: None,
+                new: Some(&new.rungs[j]),
+                lines: Vec::new(),
+            },
+            (None, None) => unreachable!(),
+        };
+        rungs.push(rung);
+    }
+    rungs
+}
+
+fn pou_whole(pou: &Pou, added: bool) -> PouDiff<'_> {
+    PouDiff {
+        context: pou.context().to_string(),
+        kind: pou.kind,
+        old: if added { None } else { Some(pou) },
+        new: if added { Some(pou) } else { None },
+        fields: Vec::new(),
+        rungs: (0..pou.rungs.len())
+            .map(|idx| RungDiff {
+                context: pou.rung_context(idx).to_string(),
+                old: if added { None } else { Some(&pou.rungs[idx]) },
+                new: if added { Some(&pou.rungs[idx]) } else { None },
+                lines: Vec::new(),
+            })
+            .collect(),
+    }
+}
+
+fn diff_pous<'a>(
+    old: &'a Project,
+    new: &'a Project,
+    volatile: &Volatile,
+    volatile_changes: &mut Vec<String>,
+) -> Vec<PouDiff<'a>> {
+    let (old_symbols, new_symbols) = (old.symbols(), new.symbols());
+    // POUs are matched on kind, chart and name, repeated names are matched in order.
+    // Transitions aren't matched on their context, which changes with the linked steps.
+    let key = |pou: &'a Pou, seen: &mut HashMap<(PouKind, Option<&'a str>, &'a str), usize>| {
+        let id = (pou.kind, pou.chart.as_deref(), pou.name.as_str());
+        let cnt = seen.entry(id).or_insert(0);
+        *cnt += 1;
+        (id, *cnt)
+    };
+    let mut seen = HashMap::new();
+    let mut old_pous: HashMap<_, &Pou> = old
+        .pous
+        .iter()
+        .map(|pou| (key(pou, &mut seen), pou))
+        .collect();
+    let mut seen = HashMap::new();
+    let mut diffs = Vec::new();
+    for new_pou in &new.pous {
+        match old_pous.remove(&key(new_pou, &mut seen)) {
+            Some(old_pou) => {
+                let rungs = diff_rungs(
+                    old_pou,
+                    new_pou,
+                    (&old_symbols, &new_symbols),
+                    volatile,
+                    volatile_changes,
+                );
+                let mut fields = diff_settings(&old_pou.fields, &new_pou.fields);
+                fields.retain(|c| {
+                    let ignore = volatile.is_volatile_key(c.key);
+                    if ignore {
+                        volatile_changes.push(format!("{} > {}", new_pou.context(), c.key));
+                    }
+                    !ignore
+                });
+                if !rungs.is_empty()
+                    || !fields.is_empty()
+                    || old_pou.comment != new_pou.comment
+                    || old_pou.section != new_pou.section
+                {
+                    diffs.push(PouDiff {
+                        context: new_pou.context().to_string(),
+                        kind: new_pou.kind,
+                        old: Some(old_pou),
+                        new: Some(new_pou),
+                        rungs,
+                        fields,
+                    });
+                }
+            }
+            None => diffs.push(pou_whole(new_pou, true)),
+        }
+    }
+    // Removed POUs, in their original order
+    for old_pou in &old.pous {
+        if old_pous.values().any(|p| std::ptr::eq(*p, old_pou)) {
+            diffs.push(pou_whole(old_pou, false));
+        }
+    }
+    diffs
+}
+
+/// Name of a chart element which doesn't depend on its GUID, e.g. `step Home`
+///
+/// OR forks and junctions have no POU, they are named after the elements they branch from or
+/// join into, e.g. `OR fork after step Home`.
+fn element_name(project: &Project, chart: &GrafcetChart, links: &Links, e: usize) -> String {
+    let element = &chart.elements[e];
+    let pou_name = |e: usize| {
+        let element = &chart.elements[e];
+        let kind = match &element.kind {
+            GrafcetKind::Step => "step",
+            GrafcetKind::Transition => "transition",
+            GrafcetKind::OrFork => "OR fork",
+            GrafcetKind::OrJunction => "OR junction",
+            GrafcetKind::Other(kind) => kind,
+        };
+        match element.pou {
+            Some(pou) => format!("{} {}", kind, project.pous[pou].name),
+            None => kind.to_string(),
+        }
+    };
+    let neighbours = |elements: Vec<usize>| elements.into_iter().map(pou_name).join(", ");
+    match (&element.kind, element.pou) {
+        (_, Some(_)) => pou_name(e),
+        (GrafcetKind::OrFork, None) => format!(
+            "OR fork after {}",
+            neighbours(links.predecessors_of(chart, e))
+        ),
+        (GrafcetKind::OrJunction, None) => format!(
+            "OR junction before {}",
+            neighbours(links.successors_of(chart, e))
+        ),
+        (_, None) => format!(
+            "{} at ({}, {})",
+            pou_name(e),
+            element.row.unwrap_or_default(),
+            element.column.unwrap_or_default()
+        ),
+    }
+}
+
+/// Describe every element of a chart with its resolved links, in sorted order
+fn describe_chart(project: &Project, chart: &GrafcetChart) -> Vec<String> {
+    let links = Links::new(chart);
+    let names = |elements: Vec<usize>| {
+        elements
+            .into_iter()
+            .map(|e| element_name(project, chart, &links, e))
+            .join(", ")
+    };
+    let mut lines: Vec<String> = (0..chart.elements.len())
+        .map(|e| {
+            let element = &chart.elements[e];
+            let mut line = element_name(project, chart, &links, e);
+            if let Some(number) = element.step_number {
+                let _ = write!(line, " #{}", number);
+            }
+            if element.initial {
+                line.push_str(" (initial)");
+            }
+            let _ = write!(
+                line,
+                " from [{}] to [{}]",
+                names(links.predecessors_of(chart, e)),
+                names(links.successors_of(chart, e))
+            );
+            line
+        })
+        .collect();
+    lines.sort_unstable();
+    lines
+}
+
+/// Compare the structure of the Grafcet charts, matched by name
+fn diff_grafcets(old: &Project, new: &Project) -> Vec<GrafcetChange> {
+    let mut charts: BTreeMap<&str, (Vec<String>, Vec<String>)> = BTreeMap::new();
+    for chart in &old.grafcets {
+        charts.entry(&chart.name).or_default().0 = describe_chart(old, chart);
+    }
+    for chart in &new.grafcets {
+        charts.entry(&chart.name).or_default().1 = describe_chart(new, chart);
+    }
+    let mut changes = Vec::new();
+    for (name, (old, new)) in charts {
+        for pair in pair_up(diff_slices(&old, &new, |a, b| a == b)) {
+            if let (Some(i), Some(j)) = pair {
+                if old[i] == new[j] {
+                    continue;
+                }
+            }
+            changes.push(GrafcetChange {
+                chart: name.to_string(),
+                old: pair.0.map(|i| old[i].clone()),
+                new: pair.1.map(|j| new[j].clone()),
+            });
+        }
+    }
+    changes
+}
+
+/// Elements whose text is a GUID
+const GUID_ELEMENTS: &[&str] = &["Id", "From", "To"];
+
+/// Comparison of two whole files, without the volatile elements
+///
+/// GUIDs are compared by their order of appearance, like in the textconv output.
+struct DocumentCompare<'a> {
+    volatile: &'a Volatile,
+    guids: [HashMap<&'a str, usize>; 2],
+}
+
+impl<'a> DocumentCompare<'a> {
+    fn new(volatile: &'a Volatile) -> Self {
+        Self {
+            volatile,
+            guids: Default::default(),
+        }
+    }
+
+    fn guid(&mut self, side: usize, guid: &'a str) -> usize {
+        let guids = &mut self.guids[side];
+        let next = guids.len();
+        *guids.entry(guid).or_insert(next)
+    }
+
+    /// Path of the first element which differs, below the root element
+    fn first_change(&mut self, old: &'a Element, new: &'a Element) -> Option<String> {
+        self.children(old, new, "")
+    }
+
+    fn children(&mut self, old: &'a Element, new: &'a Element, path: &str) -> Option<String> {
+        let volatile = self.volatile;
+        let kept = |e: &'a Element| e.children.iter().filter(|c| !volatile.is_volatile(&c.name));
+        let mut old_children = kept(old);
+        let mut new_children = kept(new);
+        loop {
+            let (o, n) = match (old_children.next(), new_children.next()) {
+                (None, None) => return None,
+                (Some(o), Some(n)) if o.name == n.name => (o, n),
+                (o, n) => {
+                    let name = o.or(n).map_or("", |e| e.name.as_str());
+                    return Some(join_path(path, name));
+                }
+            };
+            let path = join_path(path, &o.name);
+            let same = if o.is_leaf() && n.is_leaf() {
+                match GUID_ELEMENTS.contains(&o.name.as_str()) {
+                    true => self.guid(0, &o.text) == self.guid(1, &n.text),
+                    false => o.text == n.text,
+                }
+            } else {
+                match self.children(o, n, &path) {
+                    Some(change) => return Some(change),
+                    None => true,
+                }
+            };
+            if !same {
+                return Some(path);
+            }
+        }
+    }
+}
+
+fn join_path(path: &str, name: &str) -> String {
+    match path.is_empty() {
+        true => name.to_string(),
+        false => format!("{}/{}", path, name),
+    }
+}
+
+fn diff_objects<'a>(old: &'a [ObjectEntry], new: &'a [ObjectEntry]) -> Vec<ObjectChange<'a>> {
+    let mut by_address: BTreeMap<&str, (Option<&ObjectEntry>, Option<&ObjectEntry>)> =
+        BTreeMap::new();
+    for obj in old {
+        by_address.entry(&obj.address).or_default().0 = Some(obj);
+    }
+    for obj in new {
+        by_address.entry(&obj.address).or_default().1 = Some(obj);
+    }
+    by_address
+        .into_iter()
+        .filter(|(_, (o, n))| o != n)
+        .map(|(address, (old, new))| ObjectChange { address, old, new })
+        .collect()
+}
+
+fn diff_settings<'a>(
+    old: &'a BTreeMap<String, String>,
+    new: &'a BTreeMap<String, String>,
+) -> Vec<SettingChange<'a>> {
+    let mut keys: Vec<&str> = old.keys().chain(new.keys()).map(|k| k.as_str()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    keys.into_iter()
+        .map(|key| SettingChange {
+            key,
+            old: old.get(key).map(|v| v.as_str()),
+            new: new.get(key).map(|v| v.as_str()),
+        })
+        .filter(|c| c.old != c.new)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_token_diff() {
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let old = Project::from_bytes(orig.as_bytes()).unwrap();
+        let new =
+            Project::from_bytes(orig.replacen("AND  %I0.4", "AND  %I0.14", 1).as_bytes()).unwrap();
+        let diff = ProjectDiff::new(&old, &new);
+        assert_eq!(diff.pous.len(), 1);
+        assert!(diff.objects.is_empty() && diff.settings.is_empty());
+
+        let rung = &diff.pous[0].rungs[0];
+        assert_eq!(rung.context, "Validate inputs > Flaps");
+        assert_eq!(rung.changed_lines(), 1);
+        let tokens = match &rung.lines[1] {
+            LineChange::Changed { tokens, .. } => tokens,
+            other => panic!("Unexpected line change {:?}", other),
+        };
+        assert_eq!(
+            render_tokens(tokens, false),
+            "AND [-%I0.4-] [-[FLAPS_OUT]-] {+%I0.14+} {+[DEC_DRIVE_OK]+}"
+        );
+
+        // A changed IL comment is shown on its line
+        let new = Project::from_bytes(
+            orig.replacen(
+                "<InstructionLine>R  %M10</InstructionLine>\n                <Comment />",
+                "<InstructionLine>R  %M10</InstructionLine>\n                <Comment>Valid</Comment>",
+                1,
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let diff = ProjectDiff::new(&old, &new);
+        let rung = &diff.pous[0].rungs[0];
+        assert_eq!(rung.changed_lines(), 1);
+        assert!(diff
+            .render(false)
+            .contains("~ R %M10 [IO_INVALID]\n    comment: \"\" -> \"Valid\"\n"));
+    }
+
+    #[test]
+    fn test_stat() {
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let old = Project::from_bytes(orig.as_bytes()).unwrap();
+        let edited = orig
+            .replacen("AND  %I0.4", "AND  %I0.14", 1)
+            .replace("<Symbol>IO_INVALID</Symbol>", "<Symbol>IO_FAULT</Symbol>")
+            .replace("<TimersMax>255</TimersMax>", "<TimersMax>128</TimersMax>");
+        let new = Project::from_bytes(edited.as_bytes()).unwrap();
+
+        let stat = ProjectDiff::new(&old, &new).stat();
+        assert_eq!(
+            stat.pous,
+            // The renamed symbol is shown in the ladder diagram of three more rungs
+            vec![PouStat {
+                context: "Validate inputs".to_string(),
+                rungs_modified: 1,
+                ladder_only: 3,
+                lines_changed: 1,
+                ..Default::default()
+            }]
+        );
+        assert_eq!((stat.symbols, stat.configuration), (1, 1));
+    }
+
+    #[test]
+    fn test_metadata_only() {
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let old = Project::from_bytes(orig.as_bytes()).unwrap();
+        let saved = orig.replace("CLionProjects", "Projects").replacen(
+            "<IsLadderSelected>true",
+            "<IsLadderSelected>false",
+            1,
+        );
+        let new = Project::from_bytes(saved.as_bytes()).unwrap();
+
+        let diff = ProjectDiff::new(&old, &new);
+        assert!(diff.is_metadata_only(), "{:?}", diff);
+        assert_eq!(diff.volatile.len(), 2);
+
+        let diff = ProjectDiff::with_volatile(&old, &new, &Volatile::none());
+        assert_eq!(diff.settings[0].key, "FullName");
+        assert_eq!(diff.pous.len(), 1);
+
+        // Watch lists aren't part of the diff, but they aren't metadata either
+        let traced = saved.replacen("<IsTraced>false", "<IsTraced>true", 1);
+        let new = Project::from_bytes(traced.as_bytes()).unwrap();
+        let diff = ProjectDiff::new(&old, &new);
+        assert!(diff.is_empty() && !diff.is_metadata_only());
+        assert!(diff.render(false).ends_with("IsTraced\n"));
+    }
+
+    #[test]
+    fn test_grafcet_diff() {
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let old = Project::from_bytes(orig.as_bytes()).unwrap();
+        // Make step 2 the initial step instead of step 1, and save it somewhere else
+        let edited = orig
+            .replacen("<IsInitialStep>true", "<IsInitialStep>initial", 1)
+            .replacen("<IsInitialStep>false", "<IsInitialStep>true", 1)
+            .replacen("<IsInitialStep>initial", "<IsInitialStep>false", 1)
+            .replace("CLionProjects", "Projects");
+        let new = Project::from_bytes(edited.as_bytes()).unwrap();
+
+        let diff = ProjectDiff::new(&old, &new);
+        assert!(!diff.is_metadata_only());
+        assert!(diff.pous.is_empty());
+        let initial = |c: Option<&String>| c.unwrap().contains("(initial)");
+        assert_eq!(diff.grafcets.len(), 2, "{:?}", diff.grafcets);
+        assert!(diff
+            .grafcets
+            .iter()
+            .any(|c| initial(c.old.as_ref()) && !initial(c.new.as_ref())));
+        assert!(diff.grafcets[0].old.as_ref().unwrap().starts_with("step "));
+        assert_eq!(diff.stat().grafcet, 2);
+    }
+
+    #[test]
+    fn test_diff_slices() {
+        let edits = diff_slices(b"abcd", b"acde", |a, b| a == b);
+        assert_eq!(
+            edits,
+            vec![
+                Edit::Same(0, 0),
+                Edit::Removed(1),
+                Edit::Same(2, 1),
+                Edit::Same(3, 2),
+                Edit::Added(3)
+            ]
+        );
+    }
+}
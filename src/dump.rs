@@ -0,0 +1,218 @@
+//! Dump of the parsed project as JSON, for scripts which shouldn't parse the project file
+//!
+//! The document follows the project model, with `schema_version` increased on every
+//! incompatible change of the layout. The Grafcet links are resolved to indices of the
+//! chart elements, and the settings are split into the hardware configuration, the
+//! communication configuration and the rest. Stored password hashes are redacted.
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::grafcet::Links;
+use crate::project::{GrafcetChart, GrafcetKind, ObjectEntry, Pou, Project, WatchList};
+
+/// Version of the layout of the JSON document
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Setting path elements of the communication configuration, e.g. `EthernetConfiguration`
+const COMMUNICATION: &[&str] = &[
+    "EthernetConfiguration",
+    "SerialLineConfiguration",
+    "SerialLineIoScannerConfiguration",
+    "ModemConfigurations",
+    "RemoteIpAddresses",
+];
+
+/// Value of settings ending with `Password`, when a hash is stored
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Serialize)]
+pub struct Dump<'a> {
+    pub schema_version: u32,
+    /// Name and version of the program which wrote the document, e.g. `plc-diff 0.1.0`
+    pub generator: String,
+    pub name: &'a str,
+    /// All POUs in file order, including the Grafcet step and transition POUs
+    pub pous: Vec<PouDump<'a>>,
+    pub grafcets: Vec<ChartDump>,
+    /// Every symbol table and hardware configuration entry with an address, in file order
+    pub objects: &'a [ObjectEntry],
+    pub watch_lists: &'a [WatchList],
+    /// Settings under `HardwareConfiguration`, other than communication, by element path
+    pub hardware: BTreeMap<&'a str, &'a str>,
+    /// Ethernet, serial line, modem and remote address settings, by element path
+    pub communication: BTreeMap<&'a str, &'a str>,
+    /// Every other setting, by element path
+    pub settings: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PouDump<'a> {
+    /// The name used in diffs and reports, e.g. `Operational states > Home`
+    pub context: String,
+    #[serde(flatten)]
+    pub pou: &'a Pou,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChartDump {
+    pub name: String,
+    pub section: Option<u32>,
+    pub elements: Vec<ElementDump>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ElementDump {
+    pub id: String,
+    /// `step`, `transition`, `or_fork`, `or_junction`, or the element name of others
+    pub kind: String,
+    pub initial: bool,
+    pub step_number: Option<u32>,
+    pub row: Option<u32>,
+    pub column: Option<u32>,
+    /// Index into `pous` of the step or transition POU
+    pub pou: Option<usize>,
+    /// Indices into `elements` of the linked elements
+    pub predecessors: Vec<usize>,
+    pub successors: Vec<usize>,
+}
+
+impl ChartDump {
+    fn new(chart: &GrafcetChart) -> Self {
+        let links = Links::new(chart);
+        let elements = chart
+            .elements
+            .iter()
+            .enumerate()
+            .map(|(e, element)| ElementDump {
+                id: element.node.id.to_string(),
+                kind: match &element.kind {
+                    GrafcetKind::Step => "step".to_string(),
+                    GrafcetKind::Transition => "transition".to_string(),
+                    GrafcetKind::OrFork => "or_fork".to_string(),
+                    GrafcetKind::OrJunction => "or_junction".to_string(),
+                    GrafcetKind::Other(kind) => kind.clone(),
+                },
+                initial: element.initial,
+                step_number: element.step_number,
+                row: element.row,
+                column: element.column,
+                pou: element.pou,
+                predecessors: links.predecessors_of(chart, e),
+                successors: links.successors_of(chart, e),
+            })
+            .collect();
+        Self {
+            name: chart.name.clone(),
+            section: chart.section,
+            elements,
+        }
+    }
+}
+
+impl<'a> Dump<'a> {
+    pub fn new(project: &'a Project) -> Self {
+        let mut hardware = BTreeMap::new();
+        let mut communication = BTreeMap::new();
+        let mut settings = BTreeMap::new();
+        for (key, value) in &project.settings {
+            let value = match key.ends_with("Password") && !value.is_empty() {
+                true => REDACTED,
+                false => value.as_str(),
+            };
+            let key = key.as_str();
+            if key.split('/').any(|k| COMMUNICATION.contains(&k)) {
+                communication.insert(key, value);
+            } else if key.starts_with("HardwareConfiguration/") {
+                hardware.insert(key, value);
+            } else {
+                settings.insert(key, value);
+            }
+        }
+        Self {
+            schema_version: SCHEMA_VERSION,
+            generator: format!("plc-diff {}", env!("CARGO_PKG_VERSION")),
+            name: &project.name,
+            pous: project
+                .pous
+                .iter()
+                .map(|pou| PouDump {
+                    context: pou.context().to_string(),
+                    pou,
+                })
+                .collect(),
+            grafcets: project.grafcets.iter().map(ChartDump::new).collect(),
+            objects: &project.objects,
+            watch_lists: &project.watch_lists,
+            hardware,
+            communication,
+            settings,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("The dump is always serializable")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn test_dump() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let json: Value = serde_json::from_str(&Dump::new(&project).to_json()).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["name"], "M221 IO-box 2020 v2");
+
+        let pou = &json["pous"][0];
+        assert_eq!(pou["context"], "Validate inputs");
+        assert_eq!(pou["kind"], "program");
+        assert_eq!(pou["rungs"][1]["name"], "Flaps");
+        assert_eq!(pou["rungs"][1]["il"][0]["text"], "LD  %I0.5");
+
+        // Step 1 follows a transition and is followed by one
+        let elements = json["grafcets"][0]["elements"].as_array().unwrap();
+        let step = &elements[0];
+        assert_eq!(step["kind"], "step");
+        assert_eq!(step["initial"], true);
+        assert_eq!(step["id"], "8bff0fc0-0ad4-40a4-a4c7-c6a5c1df96b7");
+        let next = step["successors"][0].as_u64().unwrap() as usize;
+        assert_eq!(elements[next]["kind"], "transition");
+        assert_eq!(elements[next]["predecessors"][0], 0);
+        let pou = elements[next]["pou"].as_u64().unwrap() as usize;
+        assert_eq!(
+            json["pous"][pou]["context"],
+            "Lock and unlock->[Stow lock released]->Home"
+        );
+
+        let flaps = json["objects"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|o| o["address"] == "%I0.5")
+            .unwrap();
+        assert_eq!(flaps["symbol"], "FLAPS_IN");
+        assert_eq!(
+            json["communication"]["HardwareConfiguration/Plc/Cpu/EthernetConfiguration/IpAddress"],
+            "192.168.10.158"
+        );
+        assert_eq!(
+            json["hardware"]["HardwareConfiguration/Plc/Cpu/Name"],
+            "IOBox2020"
+        );
+        assert_eq!(
+            json["settings"]["GlobalProperties/ApplicationProtection/Password"],
+            REDACTED
+        );
+        assert_eq!(
+            json["settings"]["GlobalProperties/ProjectProtection/Password"],
+            ""
+        );
+    }
+}
@@ -0,0 +1,483 @@
+//! Boolean equations reconstructed from the IL of a rung
+//!
+//! The IL is evaluated symbolically, with the accumulator holding a `BoolExpr` instead of a
+//! value, so `LD %I0.5 / AND %I0.4 / S %M10` becomes `SET %M10 IF %I0.5 AND %I0.4`.
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Not;
+
+use crate::il::{
+    parse_rung, Expr, Instruction, LogicOp, Modifier, Operand, Span, Statement, StoreOp, UnaryOp,
+};
+use crate::project::Rung;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BoolExpr {
+    Const(bool),
+    /// A bit address or function block pin, e.g. `%I0.4` or `%TM0.Q`
+    Var(String),
+    /// Rising edge of a bit, `LDR`
+    Rising(String),
+    /// Falling edge of a bit, `LDF`
+    Falling(String),
+    /// Comparison block, which is kept as an opaque condition
+    Compare(Expr),
+    Not(Box<BoolExpr>),
+    And(Vec<BoolExpr>),
+    Or(Vec<BoolExpr>),
+    Xor(Box<BoolExpr>, Box<BoolExpr>),
+}
+
+impl Not for BoolExpr {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        match self {
+            Self::Const(b) => Self::Const(!b),
+            Self::Not(e) => *e,
+            e => Self::Not(Box::new(e)),
+        }
+    }
+}
+
+impl BoolExpr {
+    pub fn and(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Const(true), e) | (e, Self::Const(true)) => e,
+            (Self::Const(false), _) | (_, Self::Const(false)) => Self::Const(false),
+            (Self::And(mut a), Self::And(b)) => {
+                a.extend(b);
+                Self::And(a)
+            }
+            (Self::And(mut a), e) => {
+                a.push(e);
+                Self::And(a)
+            }
+            (e, Self::And(mut b)) => {
+                b.insert(0, e);
+                Self::And(b)
+            }
+            (a, b) => Self::And(vec![a, b]),
+        }
+    }
+
+    pub fn or(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Const(false), e) | (e, Self::Const(false)) => e,
+            (Self::Const(true), _) | (_, Self::Const(true)) => Self::Const(true),
+            (Self::Or(mut a), Self::Or(b)) => {
+                a.extend(b);
+                Self::Or(a)
+            }
+            (Self::Or(mut a), e) => {
+                a.push(e);
+                Self::Or(a)
+            }
+            (e, Self::Or(mut b)) => {
+                b.insert(0, e);
+                Self::Or(b)
+            }
+            (a, b) => Self::Or(vec![a, b]),
+        }
+    }
+
+    pub fn xor(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Const(false), e) | (e, Self::Const(false)) => e,
+            (Self::Const(true), e) | (e, Self::Const(true)) => !e,
+            (a, b) => Self::Xor(Box::new(a), Box::new(b)),
+        }
+    }
+
+    /// Evaluate with the value of each variable, edge and comparison given by `value`
+    pub fn eval(&self, value: &mut impl FnMut(&BoolExpr) -> bool) -> bool {
+        match self {
+            Self::Const(b) => *b,
+            Self::Not(e) => !e.eval(value),
+            Self::And(es) => es.iter().all(|e| e.eval(value)),
+            Self::Or(es) => es.iter().any(|e| e.eval(value)),
+            Self::Xor(a, b) => a.eval(value) != b.eval(value),
+            atom => value(atom),
+        }
+    }
+
+    /// The variables, edges and comparisons of the expression, without duplicates
+    pub fn atoms(&self) -> Vec<&BoolExpr> {
+        let mut atoms = Vec::new();
+        self.collect_atoms(&mut atoms);
+        atoms
+    }
+
+    fn collect_atoms<'a>(&'a self, atoms: &mut Vec<&'a BoolExpr>) {
+        match self {
+            Self::Const(_) => {}
+            Self::Not(e) => e.collect_atoms(atoms),
+            Self::And(es) | Self::Or(es) => es.iter().for_each(|e| e.collect_atoms(atoms)),
+            Self::Xor(a, b) => {
+                a.collect_atoms(atoms);
+                b.collect_atoms(atoms);
+            }
+            atom if !atoms.contains(&atom) => atoms.push(atom),
+            _ => {}
+        }
+    }
+
+    /// Render with addresses replaced by `name`, e.g. a symbol lookup
+    pub fn render(&self, name: &dyn Fn(&str) -> String) -> String {
+        // Parenthesise operands of lower precedence: OR < XOR < AND < NOT
+        let level = |e: &BoolExpr| match e {
+            Self::Or(_) => 0,
+            Self::Xor(..) => 1,
+            Self::And(_) => 2,
+            _ => 3,
+        };
+        let inner = |e: &BoolExpr, min: usize| {
+            if level(e) < min {
+                format!("({})", e.render(name))
+            } else {
+                e.render(name)
+            }
+        };
+        let join = |es: &[BoolExpr], op: &str, min: usize| {
+            es.iter()
+                .map(|e| inner(e, min))
+                .collect::<Vec<_>>()
+                .join(op)
+        };
+        match self {
+            Self::Const(b) => (*b as u8).to_string(),
+            Self::Var(v) => name(v),
+            Self::Rising(v) => format!("RE({})", name(v)),
+            Self::Falling(v) => format!("FE({})", name(v)),
+            Self::Compare(e) => format!("[{}]", render_expr(e, name)),
+            Self::Not(e) => format!("NOT {}", inner(e, 3)),
+            Self::And(es) => join(es, " AND ", 3),
+            Self::Or(es) => join(es, " OR ", 1),
+            Self::Xor(a, b) => format!("{} XOR {}", inner(a, 2), inner(b, 2)),
+        }
+    }
+}
+
+impl Display for BoolExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(&|a| a.to_string()))
+    }
+}
+
+/// Render an operation block expression with addresses replaced by `name`
+pub fn render_expr(expr: &Expr, name: &dyn Fn(&str) -> String) -> String {
+    match expr {
+        Expr::Operand(Operand::Address(a)) => name(a),
+        Expr::Operand(Operand::Compare(e)) => format!("[{}]", render_expr(e, name)),
+        Expr::Operand(o) => o.to_string(),
+        Expr::Unary(op, e) => {
+            let e = match **e {
+                Expr::Binary(..) => format!("({})", render_expr(e, name)),
+                _ => render_expr(e, name),
+            };
+            match op {
+                UnaryOp::Neg => format!("-{}", e),
+                UnaryOp::Not => format!("NOT {}", e),
+            }
+        }
+        Expr::Binary(l, op, r) => {
+            let side = |e: &Expr| match e {
+                Expr::Binary(..) => format!("({})", render_expr(e, name)),
+                _ => render_expr(e, name),
+            };
+            format!("{} {} {}", side(l), op, side(r))
+        }
+        Expr::Call(f, args) => {
+            let args: Vec<String> = args.iter().map(|a| render_expr(a, name)).collect();
+            format!("{}({})", f, args.join(", "))
+        }
+    }
+}
+
+/// What happens when the condition of an equation is true
+#[derive(Clone, Debug, PartialEq)]
+pub enum Effect {
+    /// `ST`, the bit gets the value of the condition
+    Assign(String),
+    /// `S`
+    Set(String),
+    /// `R`
+    Reset(String),
+    /// Operation block, `[ dest := expr ]`
+    Operation(Operand, Expr),
+    /// User function call, `UDF4`
+    Call(String),
+    /// `ENDT`, the transition condition
+    Transition,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Equation {
+    pub effect: Effect,
+    pub condition: BoolExpr,
+    /// The IL line of the store or operation
+    pub span: Span,
+}
+
+impl Equation {
+    pub fn render(&self, name: &dyn Fn(&str) -> String) -> String {
+        let condition = self.condition.render(name);
+        let only_if = |action: String| match self.condition {
+            BoolExpr::Const(true) => action,
+            _ => format!("{} IF {}", action, condition),
+        };
+        match &self.effect {
+            Effect::Assign(bit) => format!("{} := {}", name(bit), condition),
+            Effect::Set(bit) => only_if(format!("SET {}", name(bit))),
+            Effect::Reset(bit) => only_if(format!("RESET {}", name(bit))),
+            Effect::Operation(dest, expr) => {
+                let dest = match dest {
+                    Operand::Address(a) => name(a),
+                    d => d.to_string(),
+                };
+                only_if(format!("{} := {}", dest, render_expr(expr, name)))
+            }
+            Effect::Call(udf) => only_if(format!("CALL {}", udf)),
+            Effect::Transition => format!("TRANSITION := {}", condition),
+        }
+    }
+}
+
+impl Display for Equation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(&|a| a.to_string()))
+    }
+}
+
+/// Symbolic evaluation state of a rung
+#[derive(Default)]
+struct Evaluator {
+    acc: Option<BoolExpr>,
+    parens: Vec<(LogicOp, BoolExpr)>,
+    stack: Vec<BoolExpr>,
+    /// Instance of the enclosing `BLK` structure, for its pins
+    block: Option<String>,
+    equations: Vec<Equation>,
+}
+
+impl Evaluator {
+    /// The accumulator, which is true before the first load of a rung
+    fn acc(&self) -> BoolExpr {
+        self.acc.clone().unwrap_or(BoolExpr::Const(true))
+    }
+
+    fn bit(&self, operand: &Operand) -> Option<String> {
+        match (operand, &self.block) {
+            (Operand::Address(a), _) => Some(a.clone()),
+            (Operand::Name(pin), Some(block)) => Some(format!("{}.{}", block, pin)),
+            _ => None,
+        }
+    }
+
+    fn atom(&self, operand: &Operand, modifier: Modifier) -> Option<BoolExpr> {
+        let atom = match operand {
+            Operand::Integer(0) => BoolExpr::Const(false),
+            Operand::Integer(1) => BoolExpr::Const(true),
+            Operand::Compare(e) => BoolExpr::Compare((**e).clone()),
+            o => BoolExpr::Var(self.bit(o)?),
+        };
+        Some(match (modifier, atom) {
+            (Modifier::None, atom) => atom,
+            (Modifier::Not, atom) => !atom,
+            (Modifier::Rising, BoolExpr::Var(v)) => BoolExpr::Rising(v),
+            (Modifier::Falling, BoolExpr::Var(v)) => BoolExpr::Falling(v),
+            _ => return None,
+        })
+    }
+
+    fn combine(acc: BoolExpr, op: LogicOp, value: BoolExpr) -> BoolExpr {
+        match op {
+            LogicOp::Ld => value,
+            LogicOp::And => acc.and(value),
+            LogicOp::Or => acc.or(value),
+            LogicOp::Xor => acc.xor(value),
+        }
+    }
+
+    fn push(&mut self, effect: Effect, condition: BoolExpr, span: Span) {
+        self.equations.push(Equation {
+            effect,
+            condition,
+            span,
+        });
+    }
+
+    /// Evaluate one statement, `None` if it can't be expressed as an equation
+    fn step(&mut self, statement: &Statement) -> Option<()> {
+        let span = statement.span;
+        match &statement.instruction {
+            Instruction::Logic {
+                op,
+                modifier,
+                operand,
+            } => {
+                let value = self.atom(operand, *modifier)?;
+                self.acc = Some(Self::combine(self.acc(), *op, value));
+            }
+            Instruction::Open {
+                op,
+                modifier,
+                operand,
+            } => {
+                self.parens.push((*op, self.acc()));
+                self.acc = match operand {
+                    Some(operand) => Some(self.atom(operand, *modifier)?),
+                    None => None,
+                };
+            }
+            Instruction::Close => {
+                let (op, saved) = self.parens.pop()?;
+                self.acc = Some(Self::combine(saved, op, self.acc()));
+            }
+            Instruction::Not => self.acc = Some(!self.acc()),
+            Instruction::Mps => self.stack.push(self.acc()),
+            Instruction::Mrd => self.acc = Some(self.stack.last()?.clone()),
+            Instruction::Mpp => self.acc = Some(self.stack.pop()?),
+            Instruction::Store { op, operand } => {
+                let bit = self.bit(operand)?;
+                let acc = self.acc();
+                match op {
+                    StoreOp::St => self.push(Effect::Assign(bit), acc, span),
+                    StoreOp::Stn => self.push(Effect::Assign(bit), !acc, span),
+                    StoreOp::Set => self.push(Effect::Set(bit), acc, span),
+                    StoreOp::Reset => self.push(Effect::Reset(bit), acc, span),
+                }
+            }
+            Instruction::Blk(instance) => self.block = Some(instance.clone()),
+            Instruction::FbInput(pin) => {
+                let bit = format!("{}.{}", self.block.as_ref()?, pin);
+                self.push(Effect::Assign(bit), self.acc(), span);
+            }
+            Instruction::OutBlk => {}
+            Instruction::EndBlk => self.block = None,
+            Instruction::BlkUdfb(_) | Instruction::EndBlkUdfb(_) => {}
+            Instruction::CallUdf(udf) => self.push(Effect::Call(udf.clone()), self.acc(), span),
+            Instruction::Operation { dest, expr } => {
+                let effect = Effect::Operation(dest.clone(), expr.clone());
+                self.push(effect, self.acc(), span)
+            }
+            Instruction::EndTransition => self.push(Effect::Transition, self.acc(), span),
+            Instruction::Jump { .. }
+            | Instruction::Label(_)
+            | Instruction::Call { .. }
+            | Instruction::Return(_)
+            | Instruction::End(_) => return None,
+        }
+        Some(())
+    }
+}
+
+/// The equations of parsed IL, `None` if it uses constructs like jumps which can't be
+/// expressed as equations
+pub fn equations(statements: &[Statement]) -> Option<Vec<Equation>> {
+    let mut evaluator = Evaluator::default();
+    for statement in statements {
+        evaluator.step(statement)?;
+    }
+    Some(evaluator.equations)
+}
+
+/// The equations of a rung with symbol names, `None` if the IL can't be converted
+pub fn rung_equations(rung: &Rung, symbols: &HashMap<&str, &str>) -> Option<Vec<String>> {
+    let statements = parse_rung(rung).ok()?;
+    let equations = equations(&statements)?;
+    let name = |address: &str| symbol_name(address, symbols);
+    Some(equations.iter().map(|e| e.render(&name)).collect())
+}
+
+/// Symbol name of an address, with the pin or bit of symbols like `MW_CMD:X15` or
+/// `COMMAND_HEARTBEAT.Q`
+pub fn symbol_name(address: &str, symbols: &HashMap<&str, &str>) -> String {
+    if let Some(symbol) = symbols.get(address) {
+        return symbol.to_string();
+    }
+    let split = address.find(':').or_else(|| {
+        address
+            .rfind('.')
+            .filter(|&i| address[i + 1..].starts_with(char::is_alphabetic))
+    });
+    if let Some(i) = split {
+        if let Some(symbol) = symbols.get(&address[..i]) {
+            return format!("{}{}", symbol, &address[i..]);
+        }
+    }
+    address.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+    use crate::il::parse_line;
+    use crate::project::Project;
+
+    fn text(il: &[&str]) -> Vec<String> {
+        let statements: Vec<Statement> = il
+            .iter()
+            .enumerate()
+            .map(|(i, l)| parse_line(l, i).unwrap())
+            .collect();
+        equations(&statements)
+            .unwrap()
+            .iter()
+            .map(Equation::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_equations() {
+        assert_eq!(
+            text(&[
+                "LD  %I0.0",
+                "AND  %I0.2",
+                "OR(  %I0.1",
+                "AND  %I0.3",
+                ")",
+                "S  %M10"
+            ]),
+            ["SET %M10 IF %I0.0 AND %I0.2 OR %I0.1 AND %I0.3"]
+        );
+        assert_eq!(
+            text(&["LD  %I0.6", "AND(  %I0.7", "ORN  %I0.13", ")", "STN  %Q0.1"]),
+            ["%Q0.1 := NOT (%I0.6 AND (%I0.7 OR NOT %I0.13))"]
+        );
+        assert_eq!(
+            text(&[
+                "LD  %M1",
+                "MPS",
+                "AND  %I0.1",
+                "ST  %Q0.1",
+                "MPP",
+                "ANDN  %I0.1",
+                "ST  %Q0.2"
+            ]),
+            ["%Q0.1 := %M1 AND %I0.1", "%Q0.2 := %M1 AND NOT %I0.1"]
+        );
+        assert_eq!(text(&["LD  1", "R  %M10"]), ["RESET %M10"]);
+        let statements = [parse_line("JMP  %L1", 0).unwrap()];
+        assert_eq!(equations(&statements), None);
+    }
+
+    #[test]
+    fn test_rung_equations() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let symbols = project.symbols();
+        let timer = rung_equations(&project.pous[2].rungs[1], &symbols).unwrap();
+        assert_eq!(
+            timer,
+            [
+                "COMMAND_HEARTBEAT.IN := RE(%M0) OR FE(%M0)",
+                "MW_CMD := 0 IF NOT COMMAND_HEARTBEAT.Q",
+                "OUT_SERIAL_OK := COMMAND_HEARTBEAT.Q",
+                "MW_CMD := MW_CMD_INPUT IF COMMAND_HEARTBEAT.Q",
+                "SET MW_CMD:X8 IF COMMAND_HEARTBEAT.Q",
+            ]
+        );
+    }
+}
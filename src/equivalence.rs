@@ -0,0 +1,296 @@
+//! Logic equivalence of the rungs matched between two versions of a project
+//!
+//! The IL of both rungs is converted to equations, which are folded into the next value
+//! of every bit the rung writes, e.g. `S %M10` after `LD %I0.5 / AND %I0.4` gives
+//! `%M10 OR %I0.5 AND %I0.4`, and the conditions of the operation blocks, calls and
+//! transitions. Bits written earlier in the rung are substituted into later reads. The
+//! formulas are then compared for every assignment of their inputs. Comparison blocks
+//! are opaque inputs, so e.g. `[%MW0 > 2]` and `[2 < %MW0]` are different inputs, and
+//! the order of operation blocks is not compared.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter};
+
+use crate::diff::ProjectDiff;
+use crate::equation::{equations, render_expr, symbol_name, BoolExpr, Effect};
+use crate::il::parse_rung;
+use crate::project::{Project, Rung};
+
+/// Largest number of inputs of a rung pair which is checked, with `2^n` assignments
+pub const MAX_INPUTS: usize = 20;
+
+/// The formulas of a rung, by written bit or by effect, e.g. `%MW0 := %MW0 + 1`
+type Outputs = BTreeMap<String, BoolExpr>;
+
+/// Replace the variables with a value in `values`
+fn substitute(expr: &BoolExpr, values: &HashMap<String, BoolExpr>) -> BoolExpr {
+    match expr {
+        BoolExpr::Var(v) => values.get(v).cloned().unwrap_or_else(|| expr.clone()),
+        BoolExpr::Not(e) => !substitute(e, values),
+        BoolExpr::And(es) => es
+            .iter()
+            .map(|e| substitute(e, values))
+            .fold(BoolExpr::Const(true), BoolExpr::and),
+        BoolExpr::Or(es) => es
+            .iter()
+            .map(|e| substitute(e, values))
+            .fold(BoolExpr::Const(false), BoolExpr::or),
+        BoolExpr::Xor(a, b) => substitute(a, values).xor(substitute(b, values)),
+        atom => atom.clone(),
+    }
+}
+
+/// The outputs of a rung, or why it can't be checked
+fn outputs(rung: &Rung) -> Result<Outputs, String> {
+    let statements = parse_rung(rung).map_err(|e| e.to_string())?;
+    let equations = equations(&statements).ok_or("uses jumps, returns or subroutine calls")?;
+    let mut bits = HashMap::new();
+    let mut outputs = Outputs::new();
+    let name = |a: &str| a.to_string();
+    for equation in equations {
+        let condition = substitute(&equation.condition, &bits);
+        let (bit, value) = match &equation.effect {
+            Effect::Assign(bit) => (bit, condition),
+            Effect::Set(bit) => (bit, BoolExpr::Var(bit.clone()).or(condition)),
+            Effect::Reset(bit) => (bit, BoolExpr::Var(bit.clone()).and(!condition)),
+            effect => {
+                let mut key = match effect {
+                    Effect::Operation(dest, expr) => {
+                        format!("{} := {}", dest, render_expr(expr, &name))
+                    }
+                    Effect::Call(udf) => format!("CALL {}", udf),
+                    _ => "TRANSITION".to_string(),
+                };
+                // Repeated effects are numbered in order
+                let base = key.clone();
+                let mut n = 1;
+                while outputs.contains_key(&key) {
+                    n += 1;
+                    key = format!("{} #{}", base, n);
+                }
+                outputs.insert(key, condition);
+                continue;
+            }
+        };
+        let value = substitute(&value, &bits);
+        bits.insert(bit.clone(), value.clone());
+        outputs.insert(bit.clone(), value);
+    }
+    Ok(outputs)
+}
+
+/// Input values where the rungs differ, and the first output which differs
+#[derive(Clone, Debug, PartialEq)]
+pub struct Counterexample {
+    pub inputs: Vec<(BoolExpr, bool)>,
+    pub output: String,
+    pub old: bool,
+    pub new: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    Equivalent,
+    Different(Counterexample),
+    /// The rungs couldn't be compared, with the reason
+    Unknown(String),
+}
+
+/// The unchanged value of an output which one of the rungs doesn't have: a bit keeps its
+/// value and an operation block isn't executed
+fn unchanged(output: &str) -> BoolExpr {
+    if output.starts_with('%') && !output.contains(' ') {
+        BoolExpr::Var(output.to_string())
+    } else {
+        BoolExpr::Const(false)
+    }
+}
+
+/// Compare the outputs of two rungs for every assignment of their inputs
+fn compare(old: &Outputs, new: &Outputs) -> Verdict {
+    let mut pairs = Vec::new();
+    for name in old
+        .keys()
+        .chain(new.keys().filter(|k| !old.contains_key(*k)))
+    {
+        let side = |outputs: &Outputs| {
+            outputs
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| unchanged(name))
+        };
+        let (o, n) = (side(old), side(new));
+        if o != n {
+            pairs.push((name, o, n));
+        }
+    }
+    let mut inputs: Vec<&BoolExpr> = Vec::new();
+    for (_, o, n) in &pairs {
+        for atom in o.atoms().into_iter().chain(n.atoms()) {
+            if !inputs.contains(&atom) {
+                inputs.push(atom);
+            }
+        }
+    }
+    if inputs.len() > MAX_INPUTS {
+        return Verdict::Unknown(format!("{} inputs, more than {}", inputs.len(), MAX_INPUTS));
+    }
+    for assignment in 0..1u64 << inputs.len() {
+        let value_of = |atom: &BoolExpr| {
+            let i = inputs.iter().position(|a| *a == atom).unwrap_or_default();
+            assignment & 1 << i != 0
+        };
+        // An edge can only be seen with the new value of its bit
+        let possible = inputs.iter().all(|atom| match atom {
+            BoolExpr::Rising(v) | BoolExpr::Falling(v) if value_of(atom) => {
+                let bit = BoolExpr::Var(v.clone());
+                !inputs.contains(&&bit) || value_of(&bit) == matches!(atom, BoolExpr::Rising(_))
+            }
+            _ => true,
+        });
+        if !possible {
+            continue;
+        }
+        for (name, o, n) in &pairs {
+            let (o, n) = (o.eval(&mut |a| value_of(a)), n.eval(&mut |a| value_of(a)));
+            if o != n {
+                return Verdict::Different(Counterexample {
+                    inputs: inputs.iter().map(|a| ((*a).clone(), value_of(a))).collect(),
+                    output: name.to_string(),
+                    old: o,
+                    new: n,
+                });
+            }
+        }
+    }
+    Verdict::Equivalent
+}
+
+/// Equivalence of two versions of a rung
+pub fn check_rungs(old: &Rung, new: &Rung) -> Verdict {
+    match (outputs(old), outputs(new)) {
+        (Ok(old), Ok(new)) => compare(&old, &new),
+        (Err(e), _) => Verdict::Unknown(format!("old rung {}", e)),
+        (_, Err(e)) => Verdict::Unknown(format!("new rung {}", e)),
+    }
+}
+
+/// The result of one matched rung pair, with symbol names for rendering
+#[derive(Clone, Debug)]
+pub struct RungCheck<'a> {
+    pub context: String,
+    pub verdict: Verdict,
+    symbols: HashMap<&'a str, &'a str>,
+}
+
+impl Display for RungCheck<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = |a: &str| symbol_name(a, &self.symbols);
+        match &self.verdict {
+            Verdict::Equivalent => write!(f, "{}: equivalent", self.context),
+            Verdict::Unknown(reason) => write!(f, "{}: not checked, {}", self.context, reason),
+            Verdict::Different(c) => {
+                let inputs: Vec<String> = c
+                    .inputs
+                    .iter()
+                    .map(|(atom, value)| format!("{}={}", atom.render(&name), *value as u8))
+                    .collect();
+                let output = match c.output.split_once(' ') {
+                    Some((dest, rest)) => format!("{} {}", name(dest), rest),
+                    None => name(&c.output),
+                };
+                write!(
+                    f,
+                    "{}: differs, {} is {} in the old and {} in the new version",
+                    self.context, output, c.old as u8, c.new as u8
+                )?;
+                if !inputs.is_empty() {
+                    write!(f, " with {}", inputs.join(" "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Check the equivalence of every changed rung which is matched between the versions
+pub fn check<'a>(old: &'a Project, new: &'a Project) -> Vec<RungCheck<'a>> {
+    let symbols = new.symbols();
+    let diff = ProjectDiff::new(old, new);
+    diff.pous
+        .iter()
+        .flat_map(|pou| &pou.rungs)
+        .filter_map(|rung| {
+            Some(RungCheck {
+                context: rung.context.clone(),
+                verdict: check_rungs(rung.old?, rung.new?),
+                symbols: symbols.clone(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let old = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let mut new = old.clone();
+        // Reordered contacts
+        new.pous[0].rungs[1].il[0].text = "LD  %I0.4".to_string();
+        new.pous[0].rungs[1].il[1].text = "AND  %I0.5".to_string();
+        // An OR instead of an AND
+        let stow = &mut new.pous[0].rungs[2].il;
+        let and = stow.iter().position(|l| l.text.starts_with("AND")).unwrap();
+        stow[and].text = stow[and].text.replacen("AND", "OR", 1);
+        let checks = check(&old, &new);
+        assert_eq!(checks.len(), 2);
+        assert_eq!(checks[0].to_string(), "Validate inputs > Flaps: equivalent");
+        assert!(
+            checks[1].to_string().starts_with(
+                "Validate inputs > Stow locks: differs, IO_INVALID is 0 in the old and 1 in the new version with "
+            ),
+            "{}",
+            checks[1]
+        );
+
+        let mps = Rung::from_il(&[
+            "LD %I0.0",
+            "MPS",
+            "AND %I0.1",
+            "ST %Q0.0",
+            "MPP",
+            "ANDN %I0.1",
+            "ST %M0",
+            "LD %M0",
+            "ST %Q0.1",
+        ]);
+        let flat = Rung::from_il(&[
+            "LD %I0.1",
+            "AND %I0.0",
+            "ST %Q0.0",
+            "LDN %I0.1",
+            "AND %I0.0",
+            "ST %M0",
+            "ST %Q0.1",
+        ]);
+        assert_eq!(check_rungs(&mps, &flat), Verdict::Equivalent);
+        let edge = Rung::from_il(&["LDR %I0.0", "ST %Q0.0"]);
+        let level = Rung::from_il(&["LD %I0.0", "ST %Q0.0"]);
+        match check_rungs(&edge, &level) {
+            Verdict::Different(c) => {
+                assert_eq!(c.output, "%Q0.0");
+                assert_eq!((c.old, c.new), (false, true));
+            }
+            v => panic!("{:?}", v),
+        }
+        let jump = Rung::from_il(&["LD %I0.0", "JMPC %L1"]);
+        assert_eq!(
+            check_rungs(&jump, &level),
+            Verdict::Unknown("old rung uses jumps, returns or subroutine calls".to_string())
+        );
+    }
+}
@@ -1,13 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::mem::take;
 
 use anyhow::bail;
 use quick_xml::events::Event;
 
+use crate::project::GrafcetChart;
 use crate::{CurrentTag, Guid, VisitProcessing, VisitResult, XmlNodeVisitor};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct GrafcetNode {
     pub id: Guid,
     pub from: Vec<Guid>,
@@ -52,13 +53,15 @@ pub struct GrafcetTracer {
 }
 
 impl GrafcetTracer {
-    pub fn get_unique_link(&self, id: &Guid) -> &Guid {
-        let curr = &self.nodes[id];
-        assert!(curr.to.len() == 1 || curr.from.len() == 1);
+    /// The only node linked to or from `id`, if there is one
+    pub fn get_unique_link(&self, id: &Guid) -> Option<&Guid> {
+        let curr = self.nodes.get(id)?;
         if curr.to.len() == 1 {
-            &curr.to[0]
+            Some(&curr.to[0])
+        } else if curr.from.len() == 1 {
+            Some(&curr.from[0])
         } else {
-            &curr.from[0]
+            None
         }
     }
     pub fn get_current_node(&self, cnt: &GrafcetCounter) -> &GrafcetNode {
@@ -84,11 +87,6 @@ impl XmlNodeVisitor for GrafcetTracer {
                     bail!("Failed to generate grafcet trace {:?}", self.new_node);
                 }
                 if self.counter.process_current_tag(current) {
-                    assert!(
-                        (self.new_node.1.from.len() == 1) || (self.new_node.1.to.len() == 1),
-                        "{:?}",
-                        self.new_node
-                    );
                     let (_depth, node) = take(&mut self.new_node);
                     self.sequence.push(node.id.clone());
                     self.nodes.insert(node.id.clone(), node);
@@ -100,3 +98,57 @@ impl XmlNodeVisitor for GrafcetTracer {
         Ok(VisitProcessing::Continue(event))
     }
 }
+
+/// The links between the elements of a chart in both directions, without links to unknown
+/// elements
+///
+/// Links may be given on either end, so an element follows another if it is listed in
+/// its `To`, or lists the other in its `From`.
+#[derive(Debug, Default)]
+pub struct Links<'a> {
+    pub successors: HashMap<&'a Guid, HashSet<&'a Guid>>,
+    pub predecessors: HashMap<&'a Guid, HashSet<&'a Guid>>,
+}
+
+impl<'a> Links<'a> {
+    pub fn new(chart: &'a GrafcetChart) -> Self {
+        let ids: HashSet<&Guid> = chart.elements.iter().map(|e| &e.node.id).collect();
+        let mut links = Self::default();
+        for e in &chart.elements {
+            let id = &e.node.id;
+            for to in e.node.to.iter().filter(|to| ids.contains(to)) {
+                links.successors.entry(id).or_default().insert(to);
+                links.predecessors.entry(to).or_default().insert(id);
+            }
+            for from in e.node.from.iter().filter(|from| ids.contains(from)) {
+                links.successors.entry(from).or_default().insert(id);
+                links.predecessors.entry(id).or_default().insert(from);
+            }
+        }
+        links
+    }
+
+    /// Indices of the elements of `chart` which follow element `e`, in chart order
+    pub fn successors_of(&self, chart: &GrafcetChart, e: usize) -> Vec<usize> {
+        indices(chart, self.successors.get(&chart.elements[e].node.id))
+    }
+
+    /// Indices of the elements of `chart` which come before element `e`, in chart order
+    pub fn predecessors_of(&self, chart: &GrafcetChart, e: usize) -> Vec<usize> {
+        indices(chart, self.predecessors.get(&chart.elements[e].node.id))
+    }
+}
+
+fn indices(chart: &GrafcetChart, ids: Option<&HashSet<&Guid>>) -> Vec<usize> {
+    let ids = match ids {
+        Some(ids) => ids,
+        None => return Vec::new(),
+    };
+    chart
+        .elements
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| ids.contains(&e.node.id))
+        .map(|(i, _)| i)
+        .collect()
+}
@@ -0,0 +1,383 @@
+//! Self-contained HTML rendering of a `ProjectDiff`
+use std::fmt::Write;
+
+use crate::diff::{LineChange, ProjectDiff, RungDiff, TokenChange, TokenKind};
+use crate::project::{IlLine, LadderEntity, ObjectEntry, Pou, Project, Rung};
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 1em 2em; }
+summary { cursor: pointer; font-weight: bold; padding: 0.2em 0; }
+h3 { font-size: 100%; margin: 1em 0 0.3em 0; }
+table { border-collapse: collapse; margin-bottom: 0.5em; }
+td, th { border: 1px solid #ccc; padding: 0.1em 0.5em; vertical-align: top; }
+table.il { width: 100%; table-layout: fixed; font-family: monospace; white-space: pre; }
+table.ladder td { font-family: monospace; font-size: 80%; text-align: center; white-space: pre; }
+.removed, del { background: #fdd; }
+.added, ins { background: #dfd; }
+.changed { background: #ffd; }
+.sym { color: #666; }
+.meta { color: #666; font-style: italic; }
+";
+
+pub struct HtmlOptions {
+    /// Render the ladder diagrams of the changed rungs
+    pub ladder: bool,
+}
+
+pub(crate) fn escape(txt: &str) -> String {
+    let mut out = String::with_capacity(txt.len());
+    for c in txt.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn tokens_html(tokens: &[TokenChange], removed: bool) -> String {
+    tokens
+        .iter()
+        .filter_map(|change| {
+            let (token, tag) = match change {
+                TokenChange::Same(t) => (t, None),
+                TokenChange::Removed(t) if removed => (t, Some("del")),
+                TokenChange::Added(t) if !removed => (t, Some("ins")),
+                _ => return None,
+            };
+            let text = match token.kind {
+                TokenKind::Symbol => {
+                    format!("<span class=\"sym\">[{}]</span>", escape(&token.text))
+                }
+                _ => escape(&token.text),
+            };
+            Some(match tag {
+                Some(tag) => format!("<{0}>{1}</{0}>", tag, text),
+                None => text,
+            })
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn il_table(out: &mut String, rung: &RungDiff) {
+    out.push_str("<table class=\"il\"><tr><th>Old</th><th>New</th></tr>\n");
+    let row = |out: &mut String, class: &str, old: &str, new: &str| {
+        let _ = writeln!(
+            out,
+            "<tr class=\"{}\"><td>{}</td><td>{}</td></tr>",
+            class, old, new
+        );
+    };
+    match (rung.old, rung.new) {
+        (Some(old), None) => {
+            for line in &old.il {
+                row(out, "removed", &escape(&line.text), "");
+            }
+        }
+        (None, Some(new)) => {
+            for line in &new.il {
+                row(out, "added", "", &escape(&line.text));
+            }
+        }
+        _ => {
+            for line in &rung.lines {
+                match line {
+                    LineChange::Same(l) => row(out, "", &escape(&l.text), &escape(&l.text)),
+                    LineChange::Removed(l) => row(out, "removed", &escape(&l.text), ""),
+                    LineChange::Added(l) => row(out, "added", "", &escape(&l.text)),
+                    LineChange::Changed { old, new, tokens } => {
+                        let comment = |line: &IlLine| {
+                            if old.comment == new.comment {
+                                return String::new();
+                            }
+                            format!(
+                                " <span class=\"meta\">(* {} *)</span>",
+                                escape(&line.comment)
+                            )
+                        };
+                        row(
+                            out,
+                            "changed",
+                            &(tokens_html(tokens, true) + &comment(old)),
+                            &(tokens_html(tokens, false) + &comment(new)),
+                        )
+                    }
+                }
+            }
+        }
+    }
+    out.push_str("</table>\n");
+}
+
+/// Short text drawing of a ladder element
+fn ladder_cell(entity: &LadderEntity) -> String {
+    let symbol = match entity.element_type.as_str() {
+        "Line" => "───",
+        "VerticalLine" => "│",
+        "NormalContact" => "─┤ ├─",
+        "NegatedContact" => "─┤/├─",
+        "RisingEdge" => "─┤P├─",
+        "FallingEdge" => "─┤N├─",
+        "Coil" => "─( )",
+        "NegativeCoil" => "─(/)",
+        "SetCoil" => "─(S)",
+        "ResetCoil" => "─(R)",
+        "Not" => "─NOT─",
+        "EndT" => "─(#)",
+        "Open" => "",
+        other => other,
+    };
+    let mut cell = escape(symbol);
+    for txt in &[
+        &entity.descriptor,
+        &entity.symbol,
+        entity
+            .fields
+            .get("OperationExpression")
+            .unwrap_or(&String::new()),
+    ] {
+        if !txt.is_empty() {
+            cell.push('\n');
+            cell.push_str(&escape(txt));
+        }
+    }
+    cell
+}
+
+fn ladder_table(out: &mut String, rung: &Rung) {
+    let rows = rung.ladder.iter().map(|e| e.row + 1).max().unwrap_or(0);
+    let cols = rung.ladder.iter().map(|e| e.column + 1).max().unwrap_or(0);
+    out.push_str("<table class=\"ladder\">\n");
+    for row in 0..rows {
+        out.push_str("<tr>");
+        for col in 0..cols {
+            let cell = rung
+                .ladder
+                .iter()
+                .filter(|e| e.row == row && e.column == col)
+                .map(ladder_cell)
+                .collect::<Vec<_>>()
+                .join("\n");
+            let _ = write!(out, "<td>{}</td>", cell);
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</table>\n");
+}
+
+fn symbol_table(out: &mut String, project: &Project) {
+    out.push_str("<table><tr><th>Address</th><th>Symbol</th><th>Comment</th></tr>\n");
+    for obj in project.objects.iter().filter(|o| !o.symbol.is_empty()) {
+        let _ = writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&obj.address),
+            escape(&obj.symbol),
+            escape(&obj.comment)
+        );
+    }
+    out.push_str("</table>\n");
+}
+
+/// Table of changed fields, as (field, old value, new value)
+fn changes_table(out: &mut String, changes: &[(&str, String, String)]) {
+    if changes.is_empty() {
+        return;
+    }
+    out.push_str("<table>\n<tr><th>Field</th><th>Old</th><th>New</th></tr>\n");
+    for (field, old, new) in changes {
+        let _ = writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(field),
+            escape(old),
+            escape(new)
+        );
+    }
+    out.push_str("</table>\n");
+}
+
+/// Render the diff between `old` and `new` as a single HTML page, without external resources
+pub fn render(diff: &ProjectDiff, old: &Project, new: &Project, options: &HtmlOptions) -> String {
+    let mut out = String::new();
+    let title = escape(&format!("{} -> {}", old.name, new.name));
+    let _ = writeln!(
+        out,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head><body>\n<h1>{}</h1>",
+        title, STYLE, title
+    );
+    let _ = writeln!(out, "<pre>{}</pre>", escape(&diff.stat().to_string()));
+    if diff.is_metadata_only() {
+        let _ = writeln!(
+            out,
+            "<p class=\"meta\">metadata-only change: {}</p>",
+            escape(&diff.volatile.join(", "))
+        );
+    }
+    if let (true, Some(path)) = (diff.is_empty(), &diff.first_change) {
+        let _ = writeln!(out, "<p class=\"meta\">other change: {}</p>", escape(path));
+    }
+
+    for pou in &diff.pous {
+        let status = match (pou.old, pou.new) {
+            (None, _) => " (added)",
+            (_, None) => " (removed)",
+            _ => "",
+        };
+        let _ = writeln!(
+            out,
+            "<details open><summary>{} {}{}</summary>",
+            pou.kind,
+            escape(&pou.context),
+            status
+        );
+        let mut changes = Vec::new();
+        if let (Some(old), Some(new)) = (pou.old, pou.new) {
+            if old.comment != new.comment {
+                changes.push(("comment", old.comment.clone(), new.comment.clone()));
+            }
+            if old.section != new.section {
+                let section = |p: &Pou| p.section.map_or("-".to_string(), |s| s.to_string());
+                changes.push(("section", section(old), section(new)));
+            }
+        }
+        for field in &pou.fields {
+            let value = |v: Option<&str>| v.unwrap_or("-").to_string();
+            changes.push((field.key, value(field.old), value(field.new)));
+        }
+        changes_table(&mut out, &changes);
+        for rung in &pou.rungs {
+            let _ = writeln!(out, "<h3>{}</h3>", escape(&rung.context));
+            let mut changes = Vec::new();
+            if let (Some(old), Some(new)) = (rung.old, rung.new) {
+                if old.name != new.name {
+                    changes.push(("name", old.name.clone(), new.name.clone()));
+                }
+                if old.main_comment != new.main_comment {
+                    let comment = (old.main_comment.clone(), new.main_comment.clone());
+                    changes.push(("comment", comment.0, comment.1));
+                }
+            }
+            match rung.new.or(rung.old) {
+                Some(r) if changes.is_empty() && !r.main_comment.is_empty() => {
+                    let _ = writeln!(out, "<p class=\"meta\">{}</p>", escape(&r.main_comment));
+                }
+                _ => changes_table(&mut out, &changes),
+            }
+            il_table(&mut out, rung);
+            if options.ladder {
+                out.push_str("<table><tr>");
+                for side in &[rung.old, rung.new] {
+                    out.push_str("<td>");
+                    if let Some(r) = side {
+                        ladder_table(&mut out, r);
+                    }
+                    out.push_str("</td>");
+                }
+                out.push_str("</tr></table>\n");
+            }
+        }
+        out.push_str("</details>\n");
+    }
+
+    if !diff.grafcets.is_empty() {
+        out.push_str("<details open><summary>Grafcet changes</summary>\n<table>\n");
+        out.push_str("<tr><th>Chart</th><th>Old</th><th>New</th></tr>\n");
+        for change in &diff.grafcets {
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&change.chart),
+                escape(change.old.as_deref().unwrap_or("")),
+                escape(change.new.as_deref().unwrap_or(""))
+            );
+        }
+        out.push_str("</table></details>\n");
+    }
+
+    if !diff.objects.is_empty() {
+        out.push_str("<details open><summary>Symbol and object changes</summary>\n<table>\n");
+        out.push_str("<tr><th>Address</th><th>Old symbol</th><th>New symbol</th><th>Old comment</th><th>New comment</th></tr>\n");
+        for obj in &diff.objects {
+            let field = |o: Option<&ObjectEntry>, f: fn(&ObjectEntry) -> &str| {
+                o.map_or_else(|| "<i>none</i>".to_string(), |o| escape(f(o)))
+            };
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(obj.address),
+                field(obj.old, |o| &o.symbol),
+                field(obj.new, |o| &o.symbol),
+                field(obj.old, |o| &o.comment),
+                field(obj.new, |o| &o.comment)
+            );
+        }
+        out.push_str("</table></details>\n");
+    }
+
+    if !diff.settings.is_empty() {
+        out.push_str("<details open><summary>Configuration changes</summary>\n<table>\n");
+        out.push_str("<tr><th>Setting</th><th>Old</th><th>New</th></tr>\n");
+        for setting in &diff.settings {
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(setting.key),
+                escape(setting.old.unwrap_or("")),
+                escape(setting.new.unwrap_or(""))
+            );
+        }
+        out.push_str("</table></details>\n");
+    }
+
+    out.push_str("<details><summary>Symbol table</summary>\n");
+    symbol_table(&mut out, new);
+    out.push_str("</details>\n</body></html>\n");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_html_report() {
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let old = Project::from_bytes(orig.as_bytes()).unwrap();
+        let new =
+            Project::from_bytes(orig.replacen("AND  %I0.4", "AND  %I0.14", 1).as_bytes()).unwrap();
+        let diff = ProjectDiff::new(&old, &new);
+        let html = render(&diff, &old, &new, &HtmlOptions { ladder: true });
+
+        assert!(html.contains("<summary>POU Validate inputs</summary>"));
+        assert!(html.contains(
+            "<td>AND <del>%I0.4</del> <del><span class=\"sym\">[FLAPS_OUT]</span></del></td>"
+        ));
+        assert!(html.contains("─┤ ├─\n%I0.5"));
+        assert!(!html.contains("<script") && !html.contains("http"));
+
+        // Changed POU comments and fields, and rung names
+        let new = Project::from_bytes(
+            orig.replacen("reasonable and consistent", "consistent", 1)
+                .replacen("<Name>Flaps</Name>", "<Name>Flap switches</Name>", 1)
+                .replacen("<Name>RELEASED</Name>", "<Name>RELEASE_DONE</Name>", 1)
+                .as_bytes(),
+        )
+        .unwrap();
+        let diff = ProjectDiff::new(&old, &new);
+        let html = render(&diff, &old, &new, &HtmlOptions { ladder: false });
+        assert!(html.contains(
+            "<tr><td>comment</td><td>Check that the IO is reasonable and consistent</td><td>Check that the IO is consistent</td></tr>"
+        ));
+        assert!(html.contains("<tr><td>name</td><td>Flaps</td><td>Flap switches</td></tr>"));
+        assert!(html.contains(
+            "<tr><td>Inputs/UserDefineFunctionBlockInput[0]/Name</td><td>RELEASED</td><td>RELEASE_DONE</td></tr>"
+        ));
+    }
+}
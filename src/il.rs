@@ -0,0 +1,919 @@
+//! Parser for the Machine Expert - Basic instruction list (IL) language
+//!
+//! Each `InstructionLine` of a rung is parsed into a `Statement`, the rung as a whole is a
+//! flat list of statements in execution order. Nesting of parentheses, `MPS`/`MPP` and
+//! function block structures is checked by `parse_rung`, but kept flat in the result since
+//! that is how the controller executes it.
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use crate::project::Rung;
+
+/// Location of a statement or parse error, as a line index in the rung and a byte range in
+/// the line
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {} column {}: {}",
+            self.span.line + 1,
+            self.span.start + 1,
+            self.message
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum LogicOp {
+    Ld,
+    And,
+    Or,
+    Xor,
+}
+
+/// Modifier of a load or logic instruction, `LDN`, `LDR` or `LDF`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Modifier {
+    #[default]
+    None,
+    /// `N`, use the inverted operand
+    Not,
+    /// `R`, true on the rising edge of the operand
+    Rising,
+    /// `F`, true on the falling edge of the operand
+    Falling,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum StoreOp {
+    /// `ST`, assign the accumulator
+    St,
+    /// `STN`, assign the inverted accumulator
+    Stn,
+    /// `S`, set if the accumulator is true
+    Set,
+    /// `R`, reset if the accumulator is true
+    Reset,
+}
+
+/// Condition of jumps, subroutine calls and returns, e.g. `JMP`, `JMPC` and `JMPCN`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Condition {
+    Always,
+    /// `C`, only if the accumulator is true
+    IfTrue,
+    /// `CN`, only if the accumulator is false
+    IfFalse,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Compare(CompareOp),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    /// An object address, e.g. `%I0.4`, `%QWM0:X15`, `%Q0.0:16` or `%UDF4.RETW0`
+    Address(String),
+    /// Integer constant, `1`, `-5` or `16#3FFF`
+    Integer(i64),
+    Float(f64),
+    /// Function block pin inside a `BLK` structure, e.g. `Q`, or a symbol name
+    Name(String),
+    /// Comparison block, `[ %MW0 > 10 ]`
+    Compare(Box<Expr>),
+}
+
+/// Expression of an operation or comparison block
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Operand(Operand),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+    /// Function call, e.g. `ABS(%MW0)`
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instruction {
+    /// `LD`, `AND`, `OR` and `XOR`, with modifiers
+    Logic {
+        op: LogicOp,
+        modifier: Modifier,
+        operand: Operand,
+    },
+    /// Start of a parenthesised expression, `AND( %I0.7` or `OR(N %I0.13`
+    Open {
+        op: LogicOp,
+        modifier: Modifier,
+        operand: Option<Operand>,
+    },
+    /// `)`
+    Close,
+    Store {
+        op: StoreOp,
+        operand: Operand,
+    },
+    /// `N`, invert the accumulator
+    Not,
+    /// `MPS`, push the accumulator
+    Mps,
+    /// `MRD`, read the top of the stack
+    Mrd,
+    /// `MPP`, pop the stack
+    Mpp,
+    /// `BLK %TM0`, start of a function block structure
+    Blk(String),
+    /// Function block input set from the accumulator, e.g. `IN` or `R`
+    FbInput(String),
+    /// `OUT_BLK`, start of the output section of a function block structure
+    OutBlk,
+    EndBlk,
+    /// `BLK_UDFB 135`, start of a user function block structure
+    BlkUdfb(u32),
+    EndBlkUdfb(u32),
+    /// User function call, `UDF4`
+    CallUdf(String),
+    /// Operation block, `[ %MW0 := %MW1 + 1 ]`
+    Operation {
+        dest: Operand,
+        expr: Expr,
+    },
+    /// `JMP %L1`
+    Jump {
+        condition: Condition,
+        label: String,
+    },
+    /// `%L1:`
+    Label(String),
+    /// Subroutine call, `CALL %SR1`
+    Call {
+        condition: Condition,
+        target: String,
+    },
+    /// `RET`
+    Return(Condition),
+    /// `END`
+    End(Condition),
+    /// `ENDT`, end of a transition condition
+    EndTransition,
+}
+
+/// One parsed IL line
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub instruction: Instruction,
+    pub span: Span,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum TokenKind {
+    Word,
+    /// Punctuation and operators, `[`, `:=`, `<=`, `+`, ...
+    Symbol,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Token<'a> {
+    kind: TokenKind,
+    text: &'a str,
+    start: usize,
+    end: usize,
+}
+
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    const SYMBOLS: &[&str] = &[
+        ":=", "<=", ">=", "<>", "[", "]", "(", ")", ",", "<", ">", "=", "+", "-", "*", "/",
+    ];
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let rest = &text[pos..];
+        let c = rest.chars().next().unwrap_or_default();
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        }
+        let (kind, len) = match SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+            Some(s) => (TokenKind::Symbol, s.len()),
+            None => {
+                // Words keep ':' unless it starts ":=", for "%QWM0:X15" and "%L1:"
+                let end = rest
+                    .char_indices()
+                    .find(|&(i, c)| {
+                        c.is_whitespace()
+                            || "[](),<>=+-*/".contains(c)
+                            || rest[i..].starts_with(":=")
+                    })
+                    .map_or(rest.len(), |(i, _)| i);
+                (TokenKind::Word, end)
+            }
+        };
+        tokens.push(Token {
+            kind,
+            text: &rest[..len],
+            start: pos,
+            end: pos + len,
+        });
+        pos += len;
+    }
+    tokens
+}
+
+/// Recursive descent parser over the tokens of one line
+struct LineParser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    line: usize,
+    len: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn new(text: &'a str, line: usize) -> Self {
+        Self {
+            tokens: tokenize(text),
+            pos: 0,
+            line,
+            len: text.len(),
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> ParseResult<T> {
+        let span = match self.tokens.get(self.pos) {
+            Some(t) => Span {
+                line: self.line,
+                start: t.start,
+                end: t.end,
+            },
+            None => Span {
+                line: self.line,
+                start: self.len,
+                end: self.len,
+            },
+        };
+        Err(ParseError {
+            span,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.peek()
+            .is_some_and(|t| t.text.eq_ignore_ascii_case(text))
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, text: &str) -> ParseResult<()> {
+        if self.peek_is(text) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(format!("expected '{}'", text))
+        }
+    }
+
+    fn end(&self) -> ParseResult<()> {
+        match self.peek() {
+            None => Ok(()),
+            Some(t) => self.error(format!("unexpected '{}'", t.text)),
+        }
+    }
+
+    /// Next token, if it directly follows the previous one without whitespace
+    fn adjacent(&self) -> Option<&Token<'a>> {
+        let prev = self.tokens.get(self.pos.checked_sub(1)?)?;
+        self.peek().filter(|t| t.start == prev.end)
+    }
+
+    fn instruction(&mut self) -> ParseResult<Instruction> {
+        let first = match self.next() {
+            Some(t) => t,
+            None => return self.error("empty line"),
+        };
+        if first.text == "[" {
+            let dest = self.operand()?;
+            self.expect(":=")?;
+            let expr = self.expr()?;
+            self.expect("]")?;
+            self.end()?;
+            return Ok(Instruction::Operation { dest, expr });
+        }
+        if first.text == ")" {
+            self.end()?;
+            return Ok(Instruction::Close);
+        }
+        if first.kind != TokenKind::Word {
+            self.pos -= 1;
+            return self.error(format!("unexpected '{}'", first.text));
+        }
+        let opcode = first.text.to_ascii_uppercase();
+
+        if let Some(label) = opcode.strip_suffix(':').filter(|l| l.starts_with('%')) {
+            self.end()?;
+            return Ok(Instruction::Label(label.to_string()));
+        }
+
+        if let Some(op) =
+            logic_op(&opcode).filter(|_| self.adjacent().is_some_and(|t| t.text == "("))
+        {
+            self.pos += 1;
+            let modifier = match self.adjacent().map(|t| t.text.to_ascii_uppercase()) {
+                Some(m) if m.len() == 1 && modifier(&m).is_some() => {
+                    self.pos += 1;
+                    modifier(&m).unwrap_or_default()
+                }
+                _ => Modifier::None,
+            };
+            let operand = match self.peek() {
+                None => None,
+                Some(_) => Some(self.operand()?),
+            };
+            self.end()?;
+            return Ok(Instruction::Open {
+                op,
+                modifier,
+                operand,
+            });
+        }
+
+        let instruction = match opcode.as_str() {
+            "ST" | "STN" | "S" | "R" if self.peek().is_some() => Instruction::Store {
+                op: match opcode.as_str() {
+                    "ST" => StoreOp::St,
+                    "STN" => StoreOp::Stn,
+                    "S" => StoreOp::Set,
+                    _ => StoreOp::Reset,
+                },
+                operand: self.operand()?,
+            },
+            "N" | "NOT" => Instruction::Not,
+            "MPS" => Instruction::Mps,
+            "MRD" => Instruction::Mrd,
+            "MPP" => Instruction::Mpp,
+            "BLK" => Instruction::Blk(self.word("function block instance")?),
+            "OUT_BLK" => Instruction::OutBlk,
+            "END_BLK" => Instruction::EndBlk,
+            "BLK_UDFB" => Instruction::BlkUdfb(self.number()?),
+            "END_BLK_UDFB" => Instruction::EndBlkUdfb(self.number()?),
+            "ENDT" => Instruction::EndTransition,
+            udf if udf.starts_with("UDF")
+                && udf.len() > 3
+                && udf[3..].chars().all(|c| c.is_ascii_digit()) =>
+            {
+                Instruction::CallUdf(first.text.to_string())
+            }
+            pin if FB_INPUTS.contains(&pin) => Instruction::FbInput(pin.to_string()),
+            _ => {
+                if let Some(op) = logic_op(&opcode) {
+                    self.logic(op, Modifier::None)?
+                } else if let Some((op, m)) = split_modifier(&opcode) {
+                    self.logic(op, m)?
+                } else if let Some(condition) = conditional(&opcode, "JMP") {
+                    Instruction::Jump {
+                        condition,
+                        label: self.word("label")?,
+                    }
+                } else if let Some(condition) = conditional(&opcode, "CALL") {
+                    Instruction::Call {
+                        condition,
+                        target: self.word("subroutine")?,
+                    }
+                } else if let Some(condition) = conditional(&opcode, "RET") {
+                    Instruction::Return(condition)
+                } else if let Some(condition) = conditional(&opcode, "END") {
+                    Instruction::End(condition)
+                } else {
+                    self.pos -= 1;
+                    return self.error(format!("unknown instruction '{}'", first.text));
+                }
+            }
+        };
+        self.end()?;
+        Ok(instruction)
+    }
+
+    fn logic(&mut self, op: LogicOp, modifier: Modifier) -> ParseResult<Instruction> {
+        Ok(Instruction::Logic {
+            op,
+            modifier,
+            operand: self.operand()?,
+        })
+    }
+
+    fn word(&mut self, what: &str) -> ParseResult<String> {
+        match self.next() {
+            Some(t) if t.kind == TokenKind::Word => Ok(t.text.to_string()),
+            _ => {
+                self.pos -= 1;
+                self.error(format!("expected {}", what))
+            }
+        }
+    }
+
+    fn number(&mut self) -> ParseResult<u32> {
+        match self.peek().and_then(|t| t.text.parse().ok()) {
+            Some(n) => {
+                self.pos += 1;
+                Ok(n)
+            }
+            None => self.error("expected a number"),
+        }
+    }
+
+    fn operand(&mut self) -> ParseResult<Operand> {
+        let token = match self.next() {
+            Some(t) => t,
+            None => return self.error("expected an operand"),
+        };
+        if token.text == "[" {
+            let expr = self.expr()?;
+            self.expect("]")?;
+            return Ok(Operand::Compare(Box::new(expr)));
+        }
+        match parse_operand(token.text) {
+            Some(operand) if token.kind == TokenKind::Word => Ok(operand),
+            _ => {
+                self.pos -= 1;
+                self.error(format!("invalid operand '{}'", token.text))
+            }
+        }
+    }
+
+    /// Binary operators by precedence, lowest first
+    fn binary_op(&self, level: usize) -> Option<BinaryOp> {
+        let text = self.peek()?.text.to_ascii_uppercase();
+        let op = match (level, text.as_str()) {
+            (0, "OR") => BinaryOp::Or,
+            (1, "XOR") => BinaryOp::Xor,
+            (2, "AND") => BinaryOp::And,
+            (3, "=") => BinaryOp::Compare(CompareOp::Eq),
+            (3, "<>") => BinaryOp::Compare(CompareOp::Ne),
+            (3, "<") => BinaryOp::Compare(CompareOp::Lt),
+            (3, "<=") => BinaryOp::Compare(CompareOp::Le),
+            (3, ">") => BinaryOp::Compare(CompareOp::Gt),
+            (3, ">=") => BinaryOp::Compare(CompareOp::Ge),
+            (4, "+") => BinaryOp::Add,
+            (4, "-") => BinaryOp::Sub,
+            (5, "*") => BinaryOp::Mul,
+            (5, "/") => BinaryOp::Div,
+            (5, "REM") => BinaryOp::Rem,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn expr(&mut self) -> ParseResult<Expr> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> ParseResult<Expr> {
+        if level > 5 {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self.binary_op(level) {
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(Box::new(left), op, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> ParseResult<Expr> {
+        if self.peek_is("-") {
+            self.pos += 1;
+            return Ok(match self.unary()? {
+                Expr::Operand(Operand::Integer(i)) => Expr::Operand(Operand::Integer(-i)),
+                Expr::Operand(Operand::Float(x)) => Expr::Operand(Operand::Float(-x)),
+                e => Expr::Unary(UnaryOp::Neg, Box::new(e)),
+            });
+        }
+        if self.peek_is("NOT") {
+            self.pos += 1;
+            return Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?)));
+        }
+        if self.peek_is("(") {
+            self.pos += 1;
+            let expr = self.expr()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        let operand = self.operand()?;
+        match operand {
+            Operand::Name(name) if self.peek_is("(") => {
+                self.pos += 1;
+                let mut args = Vec::new();
+                while !self.peek_is(")") {
+                    args.push(self.expr()?);
+                    if !self.peek_is(")") {
+                        self.expect(",")?;
+                    }
+                }
+                self.pos += 1;
+                Ok(Expr::Call(name, args))
+            }
+            operand => Ok(Expr::Operand(operand)),
+        }
+    }
+}
+
+/// Function block inputs which are set from the accumulator inside a `BLK` structure
+pub const FB_INPUTS: &[&str] = &["IN", "R", "S", "CU", "CD", "U", "D", "I", "O"];
+
+fn logic_op(opcode: &str) -> Option<LogicOp> {
+    Some(match opcode {
+        "LD" => LogicOp::Ld,
+        "AND" => LogicOp::And,
+        "OR" => LogicOp::Or,
+        "XOR" => LogicOp::Xor,
+        _ => return None,
+    })
+}
+
+fn modifier(m: &str) -> Option<Modifier> {
+    Some(match m {
+        "N" => Modifier::Not,
+        "R" => Modifier::Rising,
+        "F" => Modifier::Falling,
+        _ => return None,
+    })
+}
+
+/// Split opcodes like `ANDN` into the operator and modifier
+fn split_modifier(opcode: &str) -> Option<(LogicOp, Modifier)> {
+    let (op, m) = opcode.split_at(opcode.len().checked_sub(1)?);
+    Some((logic_op(op)?, modifier(m)?))
+}
+
+/// Parse `JMP`, `JMPC` and `JMPCN` style opcodes
+fn conditional(opcode: &str, base: &str) -> Option<Condition> {
+    Some(match opcode.strip_prefix(base)? {
+        "" => Condition::Always,
+        "C" => Condition::IfTrue,
+        "CN" => Condition::IfFalse,
+        _ => return None,
+    })
+}
+
+fn parse_operand(text: &str) -> Option<Operand> {
+    if text.starts_with('%') {
+        return Some(Operand::Address(text.to_string()));
+    }
+    if let Some((base, digits)) = text.split_once('#') {
+        let radix = base.parse().ok().filter(|r| [2, 8, 16].contains(r))?;
+        return i64::from_str_radix(&digits.replace('_', ""), radix)
+            .ok()
+            .map(Operand::Integer);
+    }
+    if text.starts_with(|c: char| c.is_ascii_digit()) {
+        return match text.parse() {
+            Ok(i) => Some(Operand::Integer(i)),
+            Err(_) => text.parse().ok().map(Operand::Float),
+        };
+    }
+    if text
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+    {
+        return Some(Operand::Name(text.to_string()));
+    }
+    None
+}
+
+/// Parse one IL line, `line` is the index used in the spans
+pub fn parse_line(text: &str, line: usize) -> ParseResult<Statement> {
+    let mut parser = LineParser::new(text, line);
+    let instruction = parser.instruction()?;
+    let start = text.len() - text.trim_start().len();
+    Ok(Statement {
+        instruction,
+        span: Span {
+            line,
+            start,
+            end: text.trim_end().len(),
+        },
+    })
+}
+
+/// Parse the IL of a rung and check that parentheses, `MPS`/`MPP` and function block
+/// structures are balanced
+pub fn parse_rung(rung: &Rung) -> ParseResult<Vec<Statement>> {
+    let statements = rung
+        .il
+        .iter()
+        .enumerate()
+        .map(|(idx, line)| parse_line(&line.text, idx))
+        .collect::<ParseResult<Vec<_>>>()?;
+    check_structure(&statements)?;
+    Ok(statements)
+}
+
+fn check_structure(statements: &[Statement]) -> ParseResult<()> {
+    let mut parens = Vec::new();
+    let mut stack = Vec::new();
+    let mut blocks: Vec<(&Statement, bool)> = Vec::new();
+    let error = |statement: &Statement, message: &str| {
+        Err(ParseError {
+            span: statement.span,
+            message: message.to_string(),
+        })
+    };
+    for statement in statements {
+        match &statement.instruction {
+            Instruction::Open { .. } => parens.push(statement),
+            Instruction::Close if parens.pop().is_none() => {
+                return error(statement, "')' without matching '('");
+            }
+            Instruction::Mps => stack.push(statement),
+            Instruction::Mrd if stack.is_empty() => return error(statement, "MRD without MPS"),
+            Instruction::Mpp if stack.pop().is_none() => {
+                return error(statement, "MPP without MPS")
+            }
+            Instruction::Blk(_) | Instruction::BlkUdfb(_) => blocks.push((statement, false)),
+            Instruction::OutBlk => match blocks.last_mut() {
+                Some((s, out)) if matches!(s.instruction, Instruction::Blk(_)) && !*out => {
+                    *out = true
+                }
+                _ => return error(statement, "OUT_BLK outside of BLK"),
+            },
+            Instruction::EndBlk => match blocks.pop() {
+                Some((s, _)) if matches!(s.instruction, Instruction::Blk(_)) => {}
+                _ => return error(statement, "END_BLK without BLK"),
+            },
+            Instruction::EndBlkUdfb(n) => match blocks.pop() {
+                Some((s, _)) if s.instruction == Instruction::BlkUdfb(*n) => {}
+                _ => return error(statement, "END_BLK_UDFB without matching BLK_UDFB"),
+            },
+            _ => {}
+        }
+    }
+    if let Some(open) = parens.pop() {
+        return error(open, "'(' is never closed");
+    }
+    if let Some(mps) = stack.pop() {
+        return error(mps, "MPS without MPP");
+    }
+    if let Some((block, _)) = blocks.pop() {
+        return error(block, "function block structure is never ended");
+    }
+    Ok(())
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address(a) | Self::Name(a) => write!(f, "{}", a),
+            Self::Integer(i) => write!(f, "{}", i),
+            Self::Float(x) => write!(f, "{:?}", x),
+            Self::Compare(expr) => write!(f, "[ {} ]", expr),
+        }
+    }
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "REM",
+            Self::And => "AND",
+            Self::Or => "OR",
+            Self::Xor => "XOR",
+            Self::Compare(CompareOp::Eq) => "=",
+            Self::Compare(CompareOp::Ne) => "<>",
+            Self::Compare(CompareOp::Lt) => "<",
+            Self::Compare(CompareOp::Le) => "<=",
+            Self::Compare(CompareOp::Gt) => ">",
+            Self::Compare(CompareOp::Ge) => ">=",
+        })
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Operand(o) => write!(f, "{}", o),
+            Self::Unary(UnaryOp::Neg, e) => write!(f, "-{}", e),
+            Self::Unary(UnaryOp::Not, e) => write!(f, "NOT {}", e),
+            Self::Binary(l, op, r) => {
+                // Parenthesise nested binary expressions, precedence is not tracked here
+                let side = |e: &Expr| match e {
+                    Expr::Binary(..) => format!("({})", e),
+                    _ => e.to_string(),
+                };
+                write!(f, "{} {} {}", side(l), op, side(r))
+            }
+            Self::Call(name, args) => {
+                let args: Vec<String> = args.iter().map(Expr::to_string).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let op = |op: &LogicOp| match op {
+            LogicOp::Ld => "LD",
+            LogicOp::And => "AND",
+            LogicOp::Or => "OR",
+            LogicOp::Xor => "XOR",
+        };
+        let m = |m: &Modifier| match m {
+            Modifier::None => "",
+            Modifier::Not => "N",
+            Modifier::Rising => "R",
+            Modifier::Falling => "F",
+        };
+        let c = |c: &Condition| match c {
+            Condition::Always => "",
+            Condition::IfTrue => "C",
+            Condition::IfFalse => "CN",
+        };
+        match self {
+            Self::Logic {
+                op: o,
+                modifier,
+                operand,
+            } => write!(f, "{}{}  {}", op(o), m(modifier), operand),
+            Self::Open {
+                op: o,
+                modifier,
+                operand,
+            } => {
+                write!(f, "{}({}", op(o), m(modifier))?;
+                match operand {
+                    Some(operand) => write!(f, "  {}", operand),
+                    None => Ok(()),
+                }
+            }
+            Self::Close => f.write_str(")"),
+            Self::Store { op, operand } => {
+                let op = match op {
+                    StoreOp::St => "ST",
+                    StoreOp::Stn => "STN",
+                    StoreOp::Set => "S",
+                    StoreOp::Reset => "R",
+                };
+                write!(f, "{}  {}", op, operand)
+            }
+            Self::Not => f.write_str("N"),
+            Self::Mps => f.write_str("MPS"),
+            Self::Mrd => f.write_str("MRD"),
+            Self::Mpp => f.write_str("MPP"),
+            Self::Blk(instance) => write!(f, "BLK  {}", instance),
+            Self::FbInput(pin) => f.write_str(pin),
+            Self::OutBlk => f.write_str("OUT_BLK"),
+            Self::EndBlk => f.write_str("END_BLK"),
+            Self::BlkUdfb(n) => write!(f, "BLK_UDFB {}", n),
+            Self::EndBlkUdfb(n) => write!(f, "END_BLK_UDFB {}", n),
+            Self::CallUdf(udf) => f.write_str(udf),
+            Self::Operation { dest, expr } => write!(f, "[ {} := {} ]", dest, expr),
+            Self::Jump { condition, label } => write!(f, "JMP{}  {}", c(condition), label),
+            Self::Label(label) => write!(f, "{}:", label),
+            Self::Call { condition, target } => write!(f, "CALL{}  {}", c(condition), target),
+            Self::Return(condition) => write!(f, "RET{}", c(condition)),
+            Self::End(condition) => write!(f, "END{}", c(condition)),
+            Self::EndTransition => f.write_str("ENDT"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+    use crate::project::Project;
+
+    fn parse(text: &str) -> Instruction {
+        parse_line(text, 0).unwrap().instruction
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse("OR(N  %I0.13"),
+            Instruction::Open {
+                op: LogicOp::Or,
+                modifier: Modifier::Not,
+                operand: Some(Operand::Address("%I0.13".into())),
+            }
+        );
+        assert_eq!(
+            parse("LDR  %M0"),
+            Instruction::Logic {
+                op: LogicOp::Ld,
+                modifier: Modifier::Rising,
+                operand: Operand::Address("%M0".into()),
+            }
+        );
+        assert_eq!(parse("R"), Instruction::FbInput("R".into()));
+        assert_eq!(
+            parse("R  %QWM0:X15"),
+            Instruction::Store {
+                op: StoreOp::Reset,
+                operand: Operand::Address("%QWM0:X15".into()),
+            }
+        );
+        assert_eq!(
+            parse("[ %QWM6 := %SW18 AND 16#3FFF ]"),
+            Instruction::Operation {
+                dest: Operand::Address("%QWM6".into()),
+                expr: Expr::Binary(
+                    Box::new(Expr::Operand(Operand::Address("%SW18".into()))),
+                    BinaryOp::And,
+                    Box::new(Expr::Operand(Operand::Integer(0x3fff))),
+                ),
+            }
+        );
+        assert_eq!(
+            parse("AND [%MW0 + 2 * %MW1 >= -5]").to_string(),
+            "AND  [ (%MW0 + (2 * %MW1)) >= -5 ]"
+        );
+        assert_eq!(parse("%L3:"), Instruction::Label("%L3".into()));
+        assert_eq!(parse("JMPCN %L3").to_string(), "JMPCN  %L3");
+
+        let err = parse_line("LD  %I0.1 %I0.2", 4).unwrap_err();
+        assert_eq!(err.to_string(), "line 5 column 11: unexpected '%I0.2'");
+        assert!(parse_line("FOO  %I0.1", 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_project() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        for (_, _, rung) in project.rungs() {
+            let statements = parse_rung(rung).unwrap();
+            assert_eq!(statements.len(), rung.il.len());
+        }
+
+        let mut rung = project.pous[2].rungs[1].clone();
+        rung.il.retain(|l| l.text.trim() != "END_BLK");
+        assert_eq!(
+            parse_rung(&rung).unwrap_err().message,
+            "function block structure is never ended"
+        );
+        let error = |il: &[&str]| parse_rung(&Rung::from_il(il)).unwrap_err().to_string();
+        assert_eq!(error(&["MPP"]), "line 1 column 1: MPP without MPS");
+        assert_eq!(
+            error(&["LD %I0.0", "AND( %I0.1", "ST %Q0.0"]),
+            "line 2 column 1: '(' is never closed"
+        );
+        assert_eq!(
+            error(&["LD %I0.0", "MPS", "ST %Q0.0"]),
+            "line 2 column 1: MPS without MPP"
+        );
+    }
+}
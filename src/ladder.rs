@@ -0,0 +1,208 @@
+//! Consistency of the ladder diagram and the IL of rungs
+//!
+//! The editor stores both views of a rung, but only shows the one selected with
+//! `IsLadderSelected`, so a hand edited or corrupted file can have diverging content which
+//! is never seen.
+use std::collections::{BTreeMap, HashMap};
+
+use crate::equation::symbol_name;
+use crate::il::{parse_line, parse_rung, Instruction, LogicOp, Modifier, Operand, StoreOp};
+use crate::lint::Finding;
+use crate::project::{LadderEntity, Project, Rung};
+
+/// Ladder elements which only draw the connections
+const WIRING: &[&str] = &["Line", "VerticalLine", "Open", "Not", "EndT"];
+
+/// Operation or comparison expression in the normalised form of the IL parser
+fn normalise(expression: &str, compare: bool) -> String {
+    let line = if compare {
+        format!("LD [ {} ]", expression)
+    } else {
+        format!("[ {} ]", expression)
+    };
+    match parse_line(&line, 0).map(|s| s.instruction) {
+        Ok(Instruction::Logic { operand, .. }) => operand.to_string(),
+        Ok(instruction) => instruction.to_string(),
+        Err(_) => expression.split_whitespace().collect::<Vec<_>>().join(" "),
+    }
+}
+
+/// Description of a ladder element, in the same form as `il_element`
+fn ladder_element(entity: &LadderEntity) -> Option<String> {
+    let expression = entity.fields.get("OperationExpression");
+    let kind = match entity.element_type.as_str() {
+        t if WIRING.contains(&t) => return None,
+        "NormalContact" => "contact",
+        "NegatedContact" => "negated contact",
+        "RisingEdge" => "rising edge contact",
+        "FallingEdge" => "falling edge contact",
+        "Xor" => "XOR contact",
+        "Coil" => "coil",
+        "NegativeCoil" => "negated coil",
+        "SetCoil" => "set coil",
+        "ResetCoil" => "reset coil",
+        "Operation" => return Some(format!("operation {}", normalise(expression?, false))),
+        // The IL has the number of the block instead of the instance
+        "UserDefineFunctionBlock" => return Some("user function block".to_string()),
+        _ if !entity.descriptor.is_empty() => "function block",
+        _ => return Some(format!("comparison {}", normalise(expression?, true))),
+    };
+    Some(format!("{} {}", kind, entity.descriptor))
+}
+
+/// Description of the ladder element of an IL instruction
+fn il_element(instruction: &Instruction) -> Option<String> {
+    let contact = |op: &LogicOp, modifier: &Modifier, operand: &Operand| {
+        let kind = match (op, modifier) {
+            (LogicOp::Xor, _) => "XOR contact",
+            (_, Modifier::None) => "contact",
+            (_, Modifier::Not) => "negated contact",
+            (_, Modifier::Rising) => "rising edge contact",
+            (_, Modifier::Falling) => "falling edge contact",
+        };
+        match operand {
+            Operand::Address(a) => Some(format!("{} {}", kind, a)),
+            Operand::Compare(_) => Some(format!("comparison {}", operand)),
+            _ => None,
+        }
+    };
+    match instruction {
+        Instruction::Logic {
+            op,
+            modifier,
+            operand,
+        } => contact(op, modifier, operand),
+        Instruction::Open {
+            op,
+            modifier,
+            operand: Some(operand),
+        } => contact(op, modifier, operand),
+        Instruction::Store {
+            op,
+            operand: Operand::Address(a),
+        } => {
+            let kind = match op {
+                StoreOp::St => "coil",
+                StoreOp::Stn => "negated coil",
+                StoreOp::Set => "set coil",
+                StoreOp::Reset => "reset coil",
+            };
+            Some(format!("{} {}", kind, a))
+        }
+        Instruction::Blk(instance) => Some(format!("function block {}", instance)),
+        Instruction::BlkUdfb(_) => Some("user function block".to_string()),
+        Instruction::Operation { .. } => Some(format!("operation {}", instruction)),
+        _ => None,
+    }
+}
+
+fn count(items: impl Iterator<Item = String>) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for item in items {
+        *counts.entry(item).or_default() += 1;
+    }
+    counts
+}
+
+/// Differences between the ladder and IL of one rung
+pub fn check_rung(rung: &Rung, symbols: &HashMap<&str, &str>) -> Vec<String> {
+    if rung.ladder.is_empty() {
+        return Vec::new();
+    }
+    let statements = match parse_rung(rung) {
+        Ok(statements) => statements,
+        Err(e) => return vec![format!("the IL can't be parsed, {}", e)],
+    };
+    let ladder = count(rung.ladder.iter().filter_map(ladder_element));
+    // The parameters and results of user function blocks, `%UDF4.PARAMW0:X1`, are the
+    // pins of the block in the ladder
+    let mut udfb = false;
+    let il = count(statements.iter().filter_map(|s| {
+        let element = il_element(&s.instruction).filter(|e| !udfb || !e.contains("%UDF"));
+        match s.instruction {
+            Instruction::BlkUdfb(_) => udfb = true,
+            Instruction::EndBlkUdfb(_) => udfb = false,
+            _ => {}
+        }
+        element
+    }));
+    let mut problems = Vec::new();
+    let mut missing = |from: &BTreeMap<String, usize>, to: &BTreeMap<String, usize>, names| {
+        let (from_name, to_name) = names;
+        for (element, n) in from {
+            if to.get(element).is_none_or(|m| m < n) {
+                problems.push(format!(
+                    "{} {} is missing from the {}",
+                    from_name, element, to_name
+                ));
+            }
+        }
+    };
+    missing(&ladder, &il, ("ladder", "IL"));
+    missing(&il, &ladder, ("IL", "ladder"));
+
+    for entity in &rung.ladder {
+        let table = symbols.get(entity.descriptor.as_str());
+        if !entity.symbol.is_empty() && table.is_some_and(|s| *s != entity.symbol) {
+            problems.push(format!(
+                "ladder symbol {} of {} differs from the symbol table, {}",
+                entity.symbol,
+                entity.descriptor,
+                symbol_name(&entity.descriptor, symbols)
+            ));
+        }
+    }
+    problems
+}
+
+/// Rungs where the ladder diagram and the IL differ
+pub fn check(project: &Project) -> Vec<Finding> {
+    let symbols = project.symbols();
+    let mut findings = Vec::new();
+    for (p, r, rung) in project.rungs() {
+        let shown = if rung.ladder_selected {
+            "the ladder is shown"
+        } else {
+            "the IL is shown"
+        };
+        for problem in check_rung(rung, &symbols) {
+            findings.push(Finding {
+                lint: "ladder-il",
+                location: project.pous[p].rung_context(r).to_string(),
+                message: format!("{} ({})", problem, shown),
+            });
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_ladder_consistency() {
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let project = Project::from_bytes(orig.as_bytes()).unwrap();
+        assert_eq!(check(&project), vec![]);
+
+        let edited = orig.replacen("AND  %I0.4", "ANDN  %I0.4", 1).replacen(
+            "[ %QWM0 := 0 ]",
+            "[ %QWM0 := 1 ]",
+            1,
+        );
+        let project = Project::from_bytes(edited.as_bytes()).unwrap();
+        let messages: Vec<String> = check(&project).into_iter().map(|f| f.message).collect();
+        assert_eq!(
+            messages,
+            [
+                "ladder contact %I0.4 is missing from the IL (the ladder is shown)",
+                "IL negated contact %I0.4 is missing from the ladder (the ladder is shown)",
+                "ladder operation [ %QWM0 := 0 ] is missing from the IL (the ladder is shown)",
+                "IL operation [ %QWM0 := 1 ] is missing from the ladder (the ladder is shown)",
+            ]
+        );
+    }
+}
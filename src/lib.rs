@@ -1,10 +1,33 @@
+pub mod blocks;
+pub mod chart;
+pub mod coverage;
+pub mod dead;
+pub mod diff;
+pub mod dump;
+pub mod equation;
+pub mod equivalence;
 pub mod grafcet;
+pub mod html;
+pub mod il;
+pub mod ladder;
+pub mod lint;
+pub mod names;
+pub mod plcopen;
+pub mod project;
+pub mod scenario;
+pub mod sim;
+pub mod st;
+pub mod timers;
+pub mod tree;
+pub mod volatile;
+pub mod xref;
 
 use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::fmt::{self, Debug, Display, Formatter};
 use std::hash::Hash;
+use std::io::BufRead;
 use std::path::Path;
 
 use anyhow::{Context, Error as AnyError, Result};
@@ -12,7 +35,7 @@ use arrayvec::ArrayVec;
 use quick_xml::events::{BytesText, Event};
 use quick_xml::Reader;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
 pub enum CurrentTag {
     Address,
     Id,
@@ -30,15 +53,10 @@ pub enum CurrentTag {
     RungEntity,
     Symbol,
     Other,
+    #[default]
     None,
 }
 
-impl Default for CurrentTag {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
 impl From<&[u8]> for CurrentTag {
     fn from(tag: &[u8]) -> Self {
         match tag {
@@ -90,9 +108,18 @@ impl TryFrom<&BytesText<'_>> for Guid {
     }
 }
 
-impl<'a> Borrow<[u8]> for Guid {
+impl TryFrom<&str> for Guid {
+    type Error = AnyError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(Self(ArrayVec::try_from(value.as_bytes()).with_context(
+            || format!("GUID didn't fit into array {:?}", value),
+        )?))
+    }
+}
+
+impl Borrow<[u8]> for Guid {
     fn borrow(&self) -> &[u8] {
-        &*(self.0)
+        &self.0
     }
 }
 
@@ -126,25 +153,30 @@ impl Default for GuidMap {
 }
 
 pub fn process_file(smbp_file: &Path, visitors: &mut [&mut dyn XmlNodeVisitor]) -> Result<()> {
-    let mut reader =
-        Reader::from_file(smbp_file).context("Failed to create xml reader from path")?;
+    let reader = Reader::from_file(smbp_file).context("Failed to create xml reader from path")?;
+    process_reader(reader, visitors)
+}
 
+/// Run the visitors over a project file which is already in memory
+pub fn process_bytes(smbp: &[u8], visitors: &mut [&mut dyn XmlNodeVisitor]) -> Result<()> {
+    process_reader(Reader::from_reader(smbp), visitors)
+}
+
+pub fn process_reader<B: BufRead>(
+    mut reader: Reader<B>,
+    visitors: &mut [&mut dyn XmlNodeVisitor],
+) -> Result<()> {
     let mut read_buf = Vec::new();
     let mut current_tag = Default::default();
     loop {
-        let mut ev = reader.read_event(&mut read_buf)?;
+        let ev = reader.read_event(&mut read_buf)?;
         match &ev {
             Event::Start(start) => current_tag = start.local_name().into(),
             Event::End(end) => current_tag = end.local_name().into(),
             _ => {}
         };
         let orig = ev.clone();
-        for visitor in &mut *visitors {
-            ev = match visitor.visit(ev, current_tag)? {
-                VisitProcessing::Continue(event) => event,
-                VisitProcessing::NextNode => break,
-            };
-        }
+        visit_node(visitors, ev, current_tag)?;
         if matches!(orig, Event::End(_)) {
             current_tag = CurrentTag::None;
         }
@@ -157,6 +189,24 @@ pub fn process_file(smbp_file: &Path, visitors: &mut [&mut dyn XmlNodeVisitor])
     Ok(())
 }
 
+fn visit_node(
+    visitors: &mut [&mut dyn XmlNodeVisitor],
+    mut ev: Event,
+    current_tag: CurrentTag,
+) -> Result<()> {
+    for idx in 0..visitors.len() {
+        ev = match visitors[idx].visit(ev, current_tag)? {
+            VisitProcessing::Continue(event) => event,
+            VisitProcessing::NextNode => break,
+            VisitProcessing::Prepend(held, held_tag, event) => {
+                visit_node(&mut visitors[idx + 1..], held, held_tag)?;
+                event
+            }
+        };
+    }
+    Ok(())
+}
+
 pub trait XmlNodeVisitor {
     fn visit<'a>(&mut self, event: Event<'a>, current: CurrentTag) -> VisitResult<'a>;
 }
@@ -175,6 +225,9 @@ pub enum VisitProcessing<'a> {
     Continue(Event<'a>),
     /// Skip all remaining visitors and read in the next node
     NextNode,
+    /// Let the next visitors process a node which was held back by returning `NextNode` for
+    /// it, with its current tag at the time, before the node
+    Prepend(Event<'static>, CurrentTag, Event<'a>),
 }
 
 pub type VisitResult<'a> = Result<VisitProcessing<'a>>;
@@ -198,7 +251,7 @@ mod test {
         let mut counter = NodeCounter(0);
 
         process_file(
-            &Path::new("tests/orig.smbp"),
+            Path::new("tests/orig.smbp"),
             &mut [
                 // Node visitors
                 &mut counter,
@@ -0,0 +1,586 @@
+//! Checks for common mistakes and dead code in a project
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+use anyhow::{bail, Result};
+
+use crate::il::StoreOp;
+use crate::project::{PouKind, Project};
+use crate::sim::{resolve, udfb_scope};
+use crate::xref::{address_has_prefix, cmp_address, references, Access, WriteOp};
+
+/// A problem found by a lint
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Finding {
+    pub lint: &'static str,
+    /// Where the problem is, e.g. a rung context or a symbol table entry
+    pub location: String,
+    pub message: String,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} [{}]", self.location, self.message, self.lint)
+    }
+}
+
+/// Settings for the configurable lints
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LintConfig {
+    pub documentation: DocPolicy,
+}
+
+pub type Lint = fn(&Project, &LintConfig) -> Vec<Finding>;
+
+/// All lints, by name
+pub const LINTS: &[(&str, Lint)] = &[
+    ("unused-symbols", |p, _| unused_symbols(p)),
+    ("multiple-writes", |p, _| multiple_writes(p)),
+    ("out-of-range", |p, _| out_of_range(p)),
+    ("grafcet", |p, _| crate::chart::check(p)),
+    ("documentation", |p, c| c.documentation.check(p)),
+    ("ladder-il", |p, _| crate::ladder::check(p)),
+    ("dead-logic", |p, _| crate::dead::check(p)),
+];
+
+/// Run the named lints, or all of them if `names` is empty
+pub fn run(project: &Project, names: &[&str], config: &LintConfig) -> Vec<Finding> {
+    LINTS
+        .iter()
+        .filter(|(name, _)| names.is_empty() || names.contains(name))
+        .flat_map(|(_, lint)| lint(project, config))
+        .collect()
+}
+
+/// Symbol tables with predefined entries, which are not expected to be used
+pub const PREDEFINED_TABLES: &[&str] = &["SystemBits", "SystemWords"];
+
+/// The object an address belongs to: `%QWM0:X15` is part of `%QWM0`, `%TM0.Q` of `%TM0`
+pub fn base_address(address: &str) -> &str {
+    let address = address.split(':').next().unwrap_or(address);
+    match address.rsplit_once('.') {
+        Some((base, pin)) if pin.starts_with(|c: char| c.is_ascii_alphabetic()) => base,
+        _ => address,
+    }
+}
+
+/// The longest bit string, `%M0:32`
+const MAX_BIT_STRING: u32 = 32;
+
+/// Every address used by the IL, the ladder diagrams or the watch lists
+pub fn used_addresses(project: &Project) -> HashSet<String> {
+    used_addresses_by(project, |_| true)
+}
+
+/// Like `used_addresses`, with only the IL references with an `access` matching `filter`
+///
+/// Ladder elements and watch lists always count. The bits of a bit string, `%M0:8`, are
+/// used with the string.
+pub fn used_addresses_by(project: &Project, filter: impl Fn(Access) -> bool) -> HashSet<String> {
+    let mut used: HashSet<String> = references(project)
+        .into_iter()
+        .filter(|r| filter(r.access))
+        .map(|r| r.address)
+        .collect();
+    for (_, _, rung) in project.rungs() {
+        used.extend(rung.ladder.iter().map(|l| l.descriptor.clone()));
+    }
+    for list in &project.watch_lists {
+        used.extend(list.addresses.iter().cloned());
+    }
+    let mut members = Vec::new();
+    for address in &used {
+        members.push(base_address(address).to_string());
+        if let Some(("M", first, count)) = split_address(address) {
+            let count = count
+                .strip_prefix(':')
+                .and_then(|c| c.parse::<u32>().ok())
+                .unwrap_or_default()
+                .min(MAX_BIT_STRING);
+            members.extend((first..first.saturating_add(count)).map(|m| format!("%M{}", m)));
+        }
+    }
+    used.extend(members);
+    used
+}
+
+/// Symbols and comments on addresses which are never used
+pub fn unused_symbols(project: &Project) -> Vec<Finding> {
+    let used = used_addresses(project);
+    project
+        .objects
+        .iter()
+        .filter(|o| !PREDEFINED_TABLES.contains(&o.table.as_str()))
+        .filter(|o| !o.symbol.is_empty() || !o.comment.is_empty())
+        .filter(|o| !used.contains(&o.address) && !used.contains(base_address(&o.address)))
+        .map(|o| Finding {
+            lint: "unused-symbols",
+            location: format!("{} {}", o.table, o.address),
+            message: if o.symbol.is_empty() {
+                format!("commented address is never used ({:?})", o.comment)
+            } else {
+                format!("symbol {} is never used", o.symbol)
+            },
+        })
+        .collect()
+}
+
+/// Bits where a write from more than one rung is most likely a mistake
+fn is_output_bit(address: &str) -> bool {
+    address_has_prefix(address, "%Q") || address_has_prefix(address, "%M") || address.contains(":X")
+}
+
+/// Parameters and return values of user functions and function blocks, e.g. `%UDF2.PARAMW0:X1`
+///
+/// Callers store each parameter before every call, so these are not double coils.
+fn is_udf_pin(address: &str) -> bool {
+    address
+        .strip_prefix("%UDF")
+        .map(|rest| rest.trim_start_matches(|c: char| c.is_ascii_digit()))
+        .is_some_and(|rest| rest.starts_with(".PARAMW") || rest.starts_with(".RETW"))
+}
+
+/// Outputs, memory bits and word bits assigned in more than one rung or Grafcet step
+///
+/// Bits which are only set and reset with `S` and `R` are latches and not reported. A bit
+/// which is assigned with `ST`, `STN` or `:=` is reported if any other rung writes it,
+/// with one finding for each writing line. The local names of user function blocks are
+/// resolved to their instance addresses, and function parameters are ignored.
+pub fn multiple_writes(project: &Project) -> Vec<Finding> {
+    let symbols = project.symbols();
+    let scopes: HashMap<usize, _> = (0..project.pous.len())
+        .filter(|p| project.pous[*p].kind == PouKind::FunctionBlock)
+        .enumerate()
+        .map(|(n, p)| (p, udfb_scope(&project.pous[p].fields, n)))
+        .collect();
+    let mut writes: BTreeMap<String, Vec<(usize, usize, usize, WriteOp)>> = BTreeMap::new();
+    for r in references(project) {
+        if r.access != Access::Write || !is_output_bit(&r.address) {
+            continue;
+        }
+        let op = match r.write {
+            Some(op) => op,
+            None => continue,
+        };
+        let address = match scopes.get(&r.pou) {
+            Some(scope) => resolve(scope, &r.address),
+            None => r.address,
+        };
+        if is_udf_pin(&address) {
+            continue;
+        }
+        writes
+            .entry(address)
+            .or_default()
+            .push((r.pou, r.rung, r.line, op));
+    }
+    let mut addresses: Vec<_> = writes.into_iter().collect();
+    addresses.sort_by(|a, b| cmp_address(&a.0, &b.0));
+
+    let mut findings = Vec::new();
+    for (address, writes) in addresses {
+        let mut rungs: Vec<_> = writes.iter().map(|(pou, rung, ..)| (pou, rung)).collect();
+        rungs.dedup();
+        let assigned = writes.iter().any(|w| !is_set_reset(w.3));
+        if rungs.len() < 2 || !assigned {
+            continue;
+        }
+        let name = match symbols.get(address.as_str()) {
+            Some(symbol) => format!("{} [{}]", address, symbol),
+            None => address.to_string(),
+        };
+        for &(pou, rung, line, op) in &writes {
+            let kind = match is_set_reset(op) {
+                true => "set/reset",
+                false => "assigned",
+            };
+            findings.push(Finding {
+                lint: "multiple-writes",
+                location: format!(
+                    "{} (line {})",
+                    project.pous[pou].rung_context(rung),
+                    line + 1
+                ),
+                message: format!(
+                    "{} {} with {}, written in {} rungs",
+                    name,
+                    kind,
+                    op,
+                    rungs.len()
+                ),
+            });
+        }
+    }
+    findings
+}
+
+/// Whether a write only sets or resets the bit, with `S` or `R`
+fn is_set_reset(op: WriteOp) -> bool {
+    matches!(
+        op,
+        WriteOp::Store(StoreOp::Set) | WriteOp::Store(StoreOp::Reset)
+    )
+}
+
+/// Settings key prefix of the controller limits
+const TECHNICAL_CONFIGURATION: &str = "HardwareConfiguration/Plc/Cpu/TechnicalConfiguration/";
+
+/// Object types which are numbered up to a controller limit, as (type, limit, size in objects)
+const LIMITS: &[(&str, &str, u32)] = &[
+    ("M", "InternalBitsMax", 1),
+    ("MW", "MemoryWordsMax", 1),
+    ("MD", "MemoryWordsMax", 2),
+    ("MF", "MemoryWordsMax", 2),
+    ("KW", "MemoryConstantWordsMax", 1),
+    ("KD", "MemoryConstantWordsMax", 2),
+    ("KF", "MemoryConstantWordsMax", 2),
+    ("TM", "TimersMax", 1),
+    ("C", "CountersMax", 1),
+    ("X", "GrafcetBitsMax", 1),
+];
+
+/// Symbol tables of the I/O channels of the controller and its extension modules
+const HARDWARE_TABLES: &[&str] = &[
+    "DigitalInputs",
+    "DigitalOutputs",
+    "AnalogInputs",
+    "AnalogOutputs",
+];
+
+/// Split an address like `%MW12:X3` into its type and number, `("MW", 12, ":X3")`
+fn split_address(address: &str) -> Option<(&str, u32, &str)> {
+    let address = address.strip_prefix('%')?;
+    let digits = address.find(|c: char| c.is_ascii_digit())?;
+    let (kind, rest) = address.split_at(digits);
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    Some((kind, rest[..end].parse().ok()?, &rest[end..]))
+}
+
+/// Check one address against the limits and hardware, returning the problem
+fn check_address(project: &Project, address: &str) -> Option<String> {
+    let (kind, number, rest) = split_address(address)?;
+    if let Some((_, limit, size)) = LIMITS.iter().find(|(k, ..)| *k == kind) {
+        let max: u32 = project
+            .settings
+            .get(&format!("{}{}", TECHNICAL_CONFIGURATION, limit))?
+            .parse()
+            .ok()?;
+        return (number.checked_add(*size).is_none_or(|end| end > max))
+            .then(|| format!("{} is out of range, {} is {}", address, limit, max));
+    }
+    if !matches!(kind, "I" | "Q" | "IW" | "QW") || !rest.starts_with('.') {
+        return None;
+    }
+    // A range of bits, "%Q0.0:16", must be present up to the last bit
+    let (first, count) = match address.split_once(':') {
+        Some((first, count)) => (first, count.parse().unwrap_or(1)),
+        None => (address, 1),
+    };
+    let (module, channel) = first.rsplit_once('.')?;
+    let channel: u32 = channel.parse().ok()?;
+    let present = |address: &str| {
+        project
+            .objects
+            .iter()
+            .any(|o| o.address == address && HARDWARE_TABLES.contains(&o.table.as_str()))
+    };
+    let end = match channel.checked_add(count.max(1)) {
+        Some(end) => end,
+        None => return Some(format!("{} is out of range", address)),
+    };
+    (channel..end)
+        .map(|c| format!("{}.{}", module, c))
+        .find(|a| !present(a))
+        .map(|missing| format!("{} is not present on the configured hardware", missing))
+}
+
+/// References to objects beyond the controller limits or to I/O channels which are not
+/// present on the controller or its extension modules
+pub fn out_of_range(project: &Project) -> Vec<Finding> {
+    references(project)
+        .into_iter()
+        .filter_map(|r| {
+            let message = check_address(project, &r.address)?;
+            Some(Finding {
+                lint: "out-of-range",
+                location: format!(
+                    "{} (line {})",
+                    project.pous[r.pou].rung_context(r.rung),
+                    r.line + 1
+                ),
+                message,
+            })
+        })
+        .collect()
+}
+
+/// Match `text` against a glob pattern, where `*` matches any text and `?` any character
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // Position after the last `*`, and the text position it is currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((after, matched)) => {
+                    star = Some((after, matched + 1));
+                    p = after;
+                    t = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Documentation rules of the coding standard
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DocPolicy {
+    /// Every rung needs a name or a main comment
+    pub rung_comment: bool,
+    /// Every program, function and function block needs a comment
+    pub pou_comment: bool,
+    /// Glob pattern for the names of named rungs
+    pub rung_name: Option<String>,
+    /// Glob pattern for the names of programs, functions and function blocks
+    pub pou_name: Option<String>,
+    /// Maximum number of IL lines in a rung
+    pub max_lines: Option<usize>,
+}
+
+impl Default for DocPolicy {
+    fn default() -> Self {
+        Self {
+            rung_comment: true,
+            pou_comment: true,
+            rung_name: None,
+            pou_name: None,
+            max_lines: None,
+        }
+    }
+}
+
+impl DocPolicy {
+    /// Parse a comma separated list of rules, e.g. `rung-comment,max-lines=20,pou-name=FB_*`
+    ///
+    /// Only the listed rules are checked.
+    pub fn parse(rules: &str) -> Result<Self> {
+        let mut policy = Self {
+            rung_comment: false,
+            pou_comment: false,
+            ..Self::default()
+        };
+        for rule in rules.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            match rule.split_once('=') {
+                None if rule == "rung-comment" => policy.rung_comment = true,
+                None if rule == "pou-comment" => policy.pou_comment = true,
+                Some(("rung-name", pattern)) => policy.rung_name = Some(pattern.to_string()),
+                Some(("pou-name", pattern)) => policy.pou_name = Some(pattern.to_string()),
+                Some(("max-lines", max)) => match max.parse() {
+                    Ok(max) => policy.max_lines = Some(max),
+                    Err(_) => bail!("Invalid max-lines {:?}", max),
+                },
+                _ => bail!("Unknown documentation rule {:?}", rule),
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Report the rungs and POUs which break the rules
+    pub fn check(&self, project: &Project) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        let mut warn = |location: String, message: String| {
+            findings.push(Finding {
+                lint: "documentation",
+                location,
+                message,
+            })
+        };
+        for pou in &project.pous {
+            // Grafcet steps and transitions are named and documented by the chart
+            let standalone = !matches!(pou.kind, PouKind::Step | PouKind::Transition);
+            if standalone && self.pou_comment && pou.comment.trim().is_empty() {
+                warn(
+                    pou.context().to_string(),
+                    format!("{} has no comment", pou.kind),
+                );
+            }
+            if let Some(pattern) = self.pou_name.as_ref().filter(|_| standalone) {
+                if !glob_match(pattern, &pou.name) {
+                    warn(
+                        pou.context().to_string(),
+                        format!("{} name doesn't match {:?}", pou.kind, pattern),
+                    );
+                }
+            }
+            for (idx, rung) in pou.rungs.iter().enumerate() {
+                let name = rung.name.trim();
+                if self.rung_comment && name.is_empty() && rung.main_comment.trim().is_empty() {
+                    warn(
+                        pou.rung_context(idx).to_string(),
+                        "rung has neither a name nor a main comment".to_string(),
+                    );
+                }
+                if let Some(pattern) = &self.rung_name {
+                    if !name.is_empty() && !glob_match(pattern, name) {
+                        warn(
+                            pou.rung_context(idx).to_string(),
+                            format!("rung name doesn't match {:?}", pattern),
+                        );
+                    }
+                }
+                if let Some(max) = self.max_lines.filter(|max| rung.il.len() > *max) {
+                    warn(
+                        pou.rung_context(idx).to_string(),
+                        format!("rung has {} IL lines, the limit is {}", rung.il.len(), max),
+                    );
+                }
+            }
+        }
+        findings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_unused_symbols() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let findings = unused_symbols(&project);
+        assert!(findings.iter().all(|f| !f.location.ends_with(" %M10")));
+        assert!(findings
+            .iter()
+            .all(|f| !f.location.starts_with("SystemBits")));
+
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let project =
+            Project::from_bytes(orig.replace("<Address>%M10<", "<Address>%M11<").as_bytes())
+                .unwrap();
+        let findings = unused_symbols(&project);
+        assert!(findings.contains(&Finding {
+            lint: "unused-symbols",
+            location: "MemoryBits %M11".to_string(),
+            message: "symbol IO_INVALID is never used".to_string(),
+        }));
+        assert_eq!(base_address("%QWM0:X15"), "%QWM0");
+        assert_eq!(base_address("%TM0.Q"), "%TM0");
+        assert_eq!(base_address("%I0.4"), "%I0.4");
+    }
+
+    #[test]
+    fn test_multiple_writes() {
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let project = Project::from_bytes(orig.as_bytes()).unwrap();
+        // %M10 is only set and reset, the %CHANGED_CMD locals of the drive function blocks are
+        // separate words, and the %UDF2.PARAMW0 bits are stored before every call
+        assert_eq!(multiple_writes(&project), vec![]);
+        assert!(is_udf_pin("%UDF2.PARAMW0:X8") && is_udf_pin("%UDF12.RETW0:X0"));
+        assert!(!is_udf_pin("%UDF2.VARW0:X0"));
+
+        let project =
+            Project::from_bytes(orig.replacen("R  %M10", "ST  %M10", 1).as_bytes()).unwrap();
+        let findings = multiple_writes(&project);
+        assert!(findings.contains(&Finding {
+            lint: "multiple-writes",
+            location: "Validate inputs (line 2)".to_string(),
+            message: "%M10 [IO_INVALID] assigned with ST, written in 4 rungs".to_string(),
+        }));
+        assert!(findings.contains(&Finding {
+            lint: "multiple-writes",
+            location: "Validate inputs > Flaps (line 3)".to_string(),
+            message: "%M10 [IO_INVALID] set/reset with S, written in 4 rungs".to_string(),
+        }));
+
+        // An operation without spaces around the brackets
+        let project =
+            Project::from_bytes(orig.replacen("R  %M10", "[%M10 := 1]", 1).as_bytes()).unwrap();
+        assert!(multiple_writes(&project).contains(&Finding {
+            lint: "multiple-writes",
+            location: "Validate inputs (line 2)".to_string(),
+            message: "%M10 [IO_INVALID] assigned with :=, written in 4 rungs".to_string(),
+        }));
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let project = Project::from_bytes(orig.as_bytes()).unwrap();
+        assert_eq!(out_of_range(&project), vec![]);
+
+        let project = Project::from_bytes(
+            orig.replacen("AND  %I0.4", "AND  %I0.40", 1)
+                .replacen("S  %M10", "S  %M1024", 1)
+                .as_bytes(),
+        )
+        .unwrap();
+        let findings = out_of_range(&project);
+        assert_eq!(findings.len(), 2);
+        assert_eq!(
+            findings[0].to_string(),
+            "Validate inputs > Flaps (line 2): %I0.40 is not present on the configured hardware [out-of-range]"
+        );
+        assert_eq!(
+            findings[1].message,
+            "%M1024 is out of range, InternalBitsMax is 1024"
+        );
+        assert_eq!(split_address("%MW12:X3"), Some(("MW", 12, ":X3")));
+        assert_eq!(check_address(&project, "%Q0.0:16"), None);
+        assert!(check_address(&project, "%Q0.8:16").is_some());
+        // The last object or channel doesn't fit in a u32
+        assert_eq!(
+            check_address(&project, "%MD4294967295").unwrap(),
+            "%MD4294967295 is out of range, MemoryWordsMax is 8000"
+        );
+        assert_eq!(
+            check_address(&project, "%Q0.4294967295:2").unwrap(),
+            "%Q0.4294967295:2 is out of range"
+        );
+    }
+
+    #[test]
+    fn test_documentation() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let findings = DocPolicy::default().check(&project);
+        assert!(findings.contains(&Finding {
+            lint: "documentation",
+            location: "Validate inputs".to_string(),
+            message: "rung has neither a name nor a main comment".to_string(),
+        }));
+        assert!(findings
+            .iter()
+            .all(|f| f.location != "Validate inputs > Flaps"));
+
+        let policy = DocPolicy::parse("max-lines=2,rung-name=F*").unwrap();
+        let findings = policy.check(&project);
+        assert!(findings.contains(&Finding {
+            lint: "documentation",
+            location: "Validate inputs > Flaps".to_string(),
+            message: "rung has 3 IL lines, the limit is 2".to_string(),
+        }));
+        assert!(findings.contains(&Finding {
+            lint: "documentation",
+            location: "Modbus command input > Heartbeat timer".to_string(),
+            message: "rung name doesn't match \"F*\"".to_string(),
+        }));
+        assert!(DocPolicy::parse("rung-comments").is_err());
+        assert!(glob_match("*timer", "Heartbeat timer") && glob_match("?a*", "Ha"));
+        assert!(!glob_match("*timer?", "Heartbeat timer"));
+    }
+}
@@ -0,0 +1,128 @@
+//! Names of rungs and Grafcet nodes, as used in the textconv diff headers and in reports
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+
+use itertools::Itertools;
+use quick_xml::events::Event;
+
+use crate::grafcet::{GrafcetNode, GrafcetTracer};
+use crate::{CurrentTag, Guid, VisitProcessing, VisitResult, XmlNodeVisitor};
+
+/// Collects the names of the rungs and Grafcet nodes
+///
+/// A rung is named after its enclosing elements, e.g. `Operational states > Home > Decl drive`,
+/// and a Grafcet node after its step or transition.
+#[derive(Debug, Default)]
+pub struct NameTracker {
+    rungs: Vec<String>,
+    ids: HashMap<Guid, String>,
+    names: Vec<(usize, String)>,
+    new_id: Guid,
+    depth: usize,
+}
+
+impl NameTracker {
+    /// Names of all rungs, in file order
+    pub fn rungs(&self) -> &[String] {
+        &self.rungs
+    }
+
+    /// Name of a Grafcet node, "?" if it can't be traced to a named node
+    ///
+    /// Unnamed nodes, like forks and junctions, are named after the node they link to. A
+    /// cycle of unnamed nodes is reported by `plc-diff lint`.
+    pub fn node<'a>(&'a self, grc: &'a GrafcetTracer, id: &'a Guid) -> &'a str {
+        let mut visited = HashSet::new();
+        let mut id = id;
+        while visited.insert(id) {
+            if let Some(name) = self.ids.get(id) {
+                return name;
+            }
+            id = match grc.get_unique_link(id) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        "?"
+    }
+
+    /// Name of a transition node between the steps it links, e.g.
+    /// `Lock and unlock->[Stow lock released]->Home`
+    pub fn transition(&self, grc: &GrafcetTracer, node: &GrafcetNode) -> String {
+        match node.uniq_triple() {
+            Some((from, id, to)) => format!(
+                "{}->[{}]->{}",
+                self.node(grc, from),
+                self.node(grc, id),
+                self.node(grc, to)
+            ),
+            // Broken links are reported by `plc-diff lint`
+            None => format!("?->[{}]->?", self.node(grc, &node.id)),
+        }
+    }
+
+    fn mk_rung_name(&self) -> String {
+        self.names
+            .iter()
+            .skip(1) // Skip the project name
+            .take_while(|(depth, _)| depth <= &(self.depth + 2))
+            .map(|(_, name)| name.as_str())
+            .join(" > ")
+    }
+    fn latest_name(&self) -> String {
+        self.names
+            .last()
+            .map_or_else(String::new, |(_, name)| name.clone())
+    }
+    fn remove_old_names(&mut self) {
+        while self
+            .names
+            .last()
+            .is_some_and(|(depth, _)| depth >= &self.depth)
+        {
+            self.names.pop();
+        }
+    }
+}
+
+impl XmlNodeVisitor for NameTracker {
+    fn visit<'a>(&mut self, event: Event<'a>, current: CurrentTag) -> VisitResult<'a> {
+        match &event {
+            Event::Text(txt) => match current {
+                CurrentTag::Id => self.new_id = txt.try_into()?,
+                CurrentTag::Name => {
+                    self.remove_old_names();
+                    self.names
+                        .push((self.depth, std::str::from_utf8(txt)?.to_string()));
+                }
+                _ => {}
+            },
+            Event::Start(_) => self.depth += 1,
+            Event::End(_) => {
+                match current {
+                    CurrentTag::RungEntity => {
+                        let name = self.mk_rung_name();
+                        self.rungs.push(name);
+                    }
+                    CurrentTag::GrafcetNodeStep => {
+                        let name = self
+                            .names
+                            .iter()
+                            .find(|&&(depth, _)| depth > self.depth)
+                            .map_or_else(String::new, |(_, name)| name.clone());
+                        self.ids.insert(self.new_id.clone(), name);
+                    }
+                    CurrentTag::GrafcetTransition => {
+                        let name = self.latest_name();
+                        self.ids.insert(self.new_id.clone(), name);
+                        self.remove_old_names();
+                    }
+                    _ => {}
+                }
+                self.depth -= 1;
+            }
+            _ => {}
+        }
+        Ok(VisitProcessing::Continue(event))
+    }
+}
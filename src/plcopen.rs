@@ -0,0 +1,565 @@
+//! Export of the project as PLCopen TC6 XML, version 2.01
+//!
+//! Programs, user function blocks and user functions become POUs with their IL as the body,
+//! and each Grafcet chart becomes a program with an SFC body. The chart's steps and
+//! transitions are connected as in the GUID links of the chart, the step POUs become the
+//! actions of the chart and the transition POUs its transition conditions. Symbols are
+//! declared as global variables of a configuration, which runs the programs and charts in
+//! one task.
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::grafcet::Links;
+use crate::html::escape;
+use crate::project::{GrafcetChart, GrafcetKind, Pou, PouKind, Project, Rung};
+use crate::st::{
+    comment, declared_objects, identifier, object_type, udfb_variables, unique_name, UNTRANSLATED,
+};
+
+const NAMESPACE: &str = "http://www.plcopen.org/xml/tc6_0201";
+const XHTML: &str = "http://www.w3.org/1999/xhtml";
+
+/// Distance between the rows and columns of a chart in the SFC coordinates
+const ROW_HEIGHT: i64 = 100;
+const COLUMN_WIDTH: i64 = 200;
+
+/// Date and time in UTC for the file header, e.g. `2020-05-01T12:00:00`
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let (days, rest) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // The civil date of a day number, from Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
+/// The type is elementary, the others are function blocks
+fn elementary(name: &str) -> bool {
+    ["BOOL", "INT", "DINT", "REAL"].contains(&name)
+}
+
+/// Writer of indented XML lines
+#[derive(Default)]
+struct Xml {
+    text: String,
+    depth: usize,
+}
+
+impl Xml {
+    fn line(&mut self, line: &str) {
+        let _ = writeln!(self.text, "{:width$}{}", "", line, width = 2 * self.depth);
+    }
+
+    fn open(&mut self, tag: &str) {
+        self.line(tag);
+        self.depth += 1;
+    }
+
+    fn close(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.line(tag);
+    }
+
+    /// Formatted text in an element, e.g. a `documentation`, which is kept as it is
+    fn formatted(&mut self, tag: &str, text: &str) {
+        self.line(&format!(
+            "<{}><xhtml:p>{}</xhtml:p></{}>",
+            tag,
+            escape(text),
+            tag
+        ));
+    }
+
+    fn documentation(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.formatted("documentation", text);
+        }
+    }
+
+    fn data_type(&mut self, name: &str) {
+        match elementary(name) {
+            true => self.line(&format!("<type><{}/></type>", name)),
+            false => self.line(&format!(
+                "<type><derived name=\"{}\"/></type>",
+                escape(name)
+            )),
+        }
+    }
+
+    /// The IL of the rungs, with the rung names and comments as IL comments
+    fn il(&mut self, rungs: &[Rung]) {
+        let mut il = String::new();
+        for rung in rungs {
+            let title = [rung.name.trim(), rung.main_comment.trim()]
+                .iter()
+                .filter(|t| !t.is_empty())
+                .copied()
+                .collect::<Vec<_>>()
+                .join(": ");
+            if !title.is_empty() {
+                let _ = writeln!(il, "(* {} *)", comment(&title));
+            }
+            for line in &rung.il {
+                let _ = match line.comment.trim() {
+                    "" => writeln!(il, "{}", line.text),
+                    c => writeln!(il, "{} (* {} *)", line.text, comment(c)),
+                };
+            }
+            il.push('\n');
+        }
+        self.open("<body>");
+        self.formatted("IL", il.trim_end());
+        self.close("</body>");
+    }
+
+    /// A program, user function block or user function
+    fn pou(&mut self, project: &Project, pou: &Pou, name: &str) {
+        let kind = match pou.kind {
+            PouKind::FunctionBlock => "functionBlock",
+            PouKind::Function => "function",
+            _ => "program",
+        };
+        self.open(&format!(
+            "<pou name=\"{}\" pouType=\"{}\">",
+            escape(name),
+            kind
+        ));
+        if pou.kind == PouKind::Program {
+            self.line("<interface/>");
+        } else {
+            self.open("<interface>");
+        }
+        if pou.kind == PouKind::Function {
+            // The return type isn't part of the project model, so it is left for the importer
+            self.line(&format!(
+                "<!-- {}: the return type isn't stored in the project -->",
+                UNTRANSLATED
+            ));
+            self.line("<returnType><derived name=\"UNKNOWN\"/></returnType>");
+        }
+        if pou.kind == PouKind::FunctionBlock {
+            let n = project
+                .pous
+                .iter()
+                .filter(|p| p.kind == PouKind::FunctionBlock)
+                .position(|p| std::ptr::eq(p, pou))
+                .unwrap_or_default();
+            let variables = udfb_variables(pou, n);
+            let sections = [
+                ("VAR_INPUT", "inputVars"),
+                ("VAR_OUTPUT", "outputVars"),
+                ("VAR", "localVars"),
+            ];
+            for (section, tag) in sections {
+                let variables: Vec<_> = variables.iter().filter(|v| v.0 == section).collect();
+                if variables.is_empty() {
+                    continue;
+                }
+                self.open(&format!("<{}>", tag));
+                for (_, name, kind, address) in variables {
+                    self.open(&format!("<variable name=\"{}\">", escape(name)));
+                    self.data_type(kind);
+                    self.documentation(address);
+                    self.close("</variable>");
+                }
+                self.close(&format!("</{}>", tag));
+            }
+        }
+        if pou.kind != PouKind::Program {
+            self.close("</interface>");
+        }
+        self.il(&pou.rungs);
+        self.documentation(&pou.comment);
+        self.close("</pou>");
+    }
+
+    /// A Grafcet chart as a program with an SFC body, and its step and transition POUs as
+    /// actions and transitions
+    fn chart(&mut self, project: &Project, chart: &GrafcetChart, name: &str) {
+        let elements = &chart.elements;
+        let links = Links::new(chart);
+        // Forks and junctions have no position, they are placed by their step
+        let position = |e: usize| {
+            let element = &elements[e];
+            match (element.row, element.column) {
+                (Some(row), Some(column)) => {
+                    let y = row as i64 * ROW_HEIGHT;
+                    let y = match element.kind {
+                        GrafcetKind::Transition => y + ROW_HEIGHT / 2,
+                        _ => y,
+                    };
+                    (column as i64 * COLUMN_WIDTH, y)
+                }
+                _ => {
+                    let step = |e: &usize| match (elements[*e].row, elements[*e].column) {
+                        (Some(row), Some(column)) => Some((row, column)),
+                        _ => None,
+                    };
+                    let (neighbour, offset) = match element.kind {
+                        GrafcetKind::OrFork => (links.predecessors_of(chart, e), 1),
+                        _ => (links.successors_of(chart, e), -1),
+                    };
+                    let (row, column) = neighbour.iter().find_map(step).unwrap_or_default();
+                    (
+                        column as i64 * COLUMN_WIDTH,
+                        row as i64 * ROW_HEIGHT + offset * ROW_HEIGHT / 4,
+                    )
+                }
+            }
+        };
+
+        let mut names = HashSet::new();
+        let mut actions = Vec::new();
+        let mut transitions = Vec::new();
+        let mut element_names = Vec::new();
+        for element in elements {
+            let pou = element.pou.map(|p| &project.pous[p]);
+            let name = match (&element.kind, pou) {
+                (GrafcetKind::Step | GrafcetKind::Transition, Some(pou)) => {
+                    let name = unique_name(&mut names, &identifier(&pou.name));
+                    match element.kind {
+                        GrafcetKind::Step => actions.push((name.clone(), pou)),
+                        _ => transitions.push((name.clone(), pou)),
+                    }
+                    Some(name)
+                }
+                _ => None,
+            };
+            element_names.push(name);
+        }
+
+        self.open(&format!(
+            "<pou name=\"{}\" pouType=\"program\">",
+            escape(name)
+        ));
+        self.line("<interface/>");
+        for (tag, list) in [("action", &actions), ("transition", &transitions)] {
+            if list.is_empty() {
+                continue;
+            }
+            self.open(&format!("<{}s>", tag));
+            for (name, pou) in list {
+                self.open(&format!("<{} name=\"{}\">", tag, escape(name)));
+                self.il(&pou.rungs);
+                self.documentation(&pou.comment);
+                self.close(&format!("</{}>", tag));
+            }
+            self.close(&format!("</{}s>", tag));
+        }
+        self.open("<body>");
+        self.open("<SFC>");
+        let mut next_id = elements.len() + 1;
+        for (e, element) in elements.iter().enumerate() {
+            let (x, y) = position(e);
+            let id = e + 1;
+            let tag = match &element.kind {
+                GrafcetKind::Step => {
+                    let step = match element.step_number {
+                        Some(n) => format!("Step{}", n),
+                        None => format!("Step_{}", id),
+                    };
+                    self.open(&format!(
+                        "<step localId=\"{}\" name=\"{}\" initialStep=\"{}\">",
+                        id, step, element.initial
+                    ));
+                    "step"
+                }
+                GrafcetKind::Transition => {
+                    self.open(&format!("<transition localId=\"{}\">", id));
+                    "transition"
+                }
+                GrafcetKind::OrFork => {
+                    self.open(&format!("<selectionDivergence localId=\"{}\">", id));
+                    "selectionDivergence"
+                }
+                GrafcetKind::OrJunction => {
+                    self.open(&format!("<selectionConvergence localId=\"{}\">", id));
+                    "selectionConvergence"
+                }
+                GrafcetKind::Other(kind) => {
+                    self.line(&format!(
+                        "<!-- Unsupported Grafcet element {} {} -->",
+                        escape(kind),
+                        element.node.id
+                    ));
+                    continue;
+                }
+            };
+            self.line(&format!("<position x=\"{}\" y=\"{}\"/>", x, y));
+            let predecessors = links.predecessors_of(chart, e);
+            if !predecessors.is_empty() {
+                self.open("<connectionPointIn>");
+                for p in predecessors {
+                    // Branches of a divergence are told apart by their position in it
+                    let branch = match elements[p].kind {
+                        GrafcetKind::OrFork => links
+                            .successors_of(chart, p)
+                            .iter()
+                            .position(|s| *s == e)
+                            .map(|b| format!(" formalParameter=\"{}\"", b + 1)),
+                        _ => None,
+                    };
+                    self.line(&format!(
+                        "<connection refLocalId=\"{}\"{}/>",
+                        p + 1,
+                        branch.unwrap_or_default()
+                    ));
+                }
+                self.close("</connectionPointIn>");
+            }
+            match element.kind {
+                GrafcetKind::OrFork => {
+                    for b in 0..links.successors_of(chart, e).len() {
+                        self.line(&format!(
+                            "<connectionPointOut formalParameter=\"{}\"/>",
+                            b + 1
+                        ));
+                    }
+                }
+                _ => self.line("<connectionPointOut/>"),
+            }
+            match (&element.kind, &element_names[e]) {
+                (GrafcetKind::Step, Some(_)) => {
+                    self.line("<connectionPointOutAction formalParameter=\"\"/>")
+                }
+                (GrafcetKind::Transition, Some(name)) => {
+                    self.line(&format!(
+                        "<condition><reference name=\"{}\"/></condition>",
+                        escape(name)
+                    ));
+                }
+                _ => {}
+            }
+            self.close(&format!("</{}>", tag));
+        }
+        // The action of each step with a POU
+        for (e, element) in elements.iter().enumerate() {
+            let action = match (&element.kind, &element_names[e]) {
+                (GrafcetKind::Step, Some(action)) => action,
+                _ => continue,
+            };
+            let (x, y) = position(e);
+            self.open(&format!("<actionBlock localId=\"{}\">", next_id));
+            next_id += 1;
+            self.line(&format!(
+                "<position x=\"{}\" y=\"{}\"/>",
+                x + COLUMN_WIDTH / 2,
+                y
+            ));
+            self.open("<connectionPointIn>");
+            self.line(&format!("<connection refLocalId=\"{}\"/>", e + 1));
+            self.close("</connectionPointIn>");
+            self.open("<action localId=\"0\" qualifier=\"N\">");
+            self.line(&format!("<reference name=\"{}\"/>", escape(action)));
+            self.close("</action>");
+            self.close("</actionBlock>");
+        }
+        self.close("</SFC>");
+        self.close("</body>");
+
+        self.close("</pou>");
+    }
+
+    /// The global variables and a task which runs the programs, in file order
+    fn instances(&mut self, project: &Project, programs: &[String]) {
+        let setting = |key: &str| project.settings.get(key).filter(|v| !v.is_empty());
+        let resource = setting("HardwareConfiguration/Plc/Cpu/Name")
+            .map_or_else(|| "Resource".to_string(), |n| identifier(n));
+        self.open("<instances>");
+        self.open("<configurations>");
+        self.open("<configuration name=\"Configuration\">");
+        self.open(&format!("<resource name=\"{}\">", escape(&resource)));
+        self.open("<task name=\"MAST\" priority=\"0\">");
+        for program in programs {
+            self.line(&format!(
+                "<pouInstance name=\"{}\" typeName=\"{}\"/>",
+                escape(program),
+                escape(program)
+            ));
+        }
+        self.close("</task>");
+        self.open("<globalVars>");
+        for object in declared_objects(project) {
+            let name = escape(&identifier(&object.symbol));
+            let kind = match object_type(object) {
+                Some(kind) => kind,
+                None => {
+                    self.line(&format!(
+                        "<!-- Unsupported object {} {} -->",
+                        escape(&object.address),
+                        name
+                    ));
+                    continue;
+                }
+            };
+            // Function block instances can't be located, their address is documented
+            let located = elementary(&kind.name);
+            match located {
+                true => self.open(&format!(
+                    "<variable name=\"{}\" address=\"{}\">",
+                    name,
+                    escape(&object.address)
+                )),
+                false => self.open(&format!("<variable name=\"{}\">", name)),
+            }
+            self.data_type(&kind.name);
+            if let Some((member, value)) = &kind.preset {
+                self.line(&format!(
+                    "<initialValue><structValue><value member=\"{}\"><simpleValue value=\"{}\"/></value></structValue></initialValue>",
+                    member,
+                    escape(value)
+                ));
+            }
+            let documentation = match (located, object.comment.is_empty()) {
+                (true, _) => object.comment.clone(),
+                (false, true) => object.address.clone(),
+                (false, false) => format!("{} {}", object.address, object.comment),
+            };
+            self.documentation(&documentation);
+            self.close("</variable>");
+        }
+        self.close("</globalVars>");
+        self.close("</resource>");
+        self.close("</configuration>");
+        self.close("</configurations>");
+        self.close("</instances>");
+    }
+}
+
+/// The project as a PLCopen XML document, created at `created`
+pub fn export(project: &Project, created: SystemTime) -> String {
+    let mut xml = Xml::default();
+    xml.line("<?xml version=\"1.0\" encoding=\"utf-8\"?>");
+    xml.open(&format!(
+        "<project xmlns=\"{}\" xmlns:xhtml=\"{}\">",
+        NAMESPACE, XHTML
+    ));
+    xml.line(&format!(
+        "<fileHeader companyName=\"\" productName=\"plc-diff\" productVersion=\"{}\" creationDateTime=\"{}\"/>",
+        env!("CARGO_PKG_VERSION"),
+        timestamp(created)
+    ));
+    let version = match project.settings.get("ProjectVersion") {
+        Some(version) => format!(" version=\"{}\"", escape(version)),
+        None => String::new(),
+    };
+    xml.open(&format!(
+        "<contentHeader name=\"{}\"{}>",
+        escape(&project.name),
+        version
+    ));
+    xml.open("<coordinateInfo>");
+    for language in ["fbd", "ld", "sfc"] {
+        xml.line(&format!(
+            "<{}><scaling x=\"1\" y=\"1\"/></{}>",
+            language, language
+        ));
+    }
+    xml.close("</coordinateInfo>");
+    xml.close("</contentHeader>");
+    xml.open("<types>");
+    xml.line("<dataTypes/>");
+    xml.open("<pous>");
+    let mut names = HashSet::new();
+    let mut programs = Vec::new();
+    let mut charts = project.grafcets.iter().peekable();
+    for pou in &project.pous {
+        match pou.kind {
+            // Each chart is exported where its first step or transition is
+            PouKind::Step | PouKind::Transition => {
+                if let Some(chart) = charts.next_if(|c| Some(&c.name) == pou.chart.as_ref()) {
+                    let name = unique_name(&mut names, &identifier(&chart.name));
+                    xml.chart(project, chart, &name);
+                    programs.push(name);
+                }
+            }
+            kind => {
+                let name = unique_name(&mut names, &identifier(&pou.name));
+                xml.pou(project, pou, &name);
+                if kind == PouKind::Program {
+                    programs.push(name);
+                }
+            }
+        }
+    }
+    // Charts without steps or transitions
+    for chart in charts {
+        let name = unique_name(&mut names, &identifier(&chart.name));
+        xml.chart(project, chart, &name);
+        programs.push(name);
+    }
+    xml.close("</pous>");
+    xml.close("</types>");
+    xml.instances(project, &programs);
+    xml.close("</project>");
+    xml.text
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::tree::TreeBuilder;
+
+    #[test]
+    fn test_export() {
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_secs(1_588_334_400)),
+            "2020-05-01T12:00:00"
+        );
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let xml = export(&project, UNIX_EPOCH);
+        let expected = [
+            "<contentHeader name=\"M221 IO-box 2020 v2\" version=\"2.6.0.0\">",
+            "<pou name=\"Validate_inputs\" pouType=\"program\">",
+            "<pou name=\"POL_DRIVE\" pouType=\"functionBlock\">\n        <interface>\n          <inputVars>\n            <variable name=\"RUN_POL\">\n              <type><BOOL/></type>\n",
+            "<pou name=\"Operational_states\" pouType=\"program\">",
+            "<step localId=\"1\" name=\"Step1\" initialStep=\"true\">",
+            "<condition><reference name=\"Horizon_switch__below_2\"/></condition>",
+            "<action name=\"Lock_and_unlock\">",
+            "<pouInstance name=\"Operational_states\" typeName=\"Operational_states\"/>",
+            "<variable name=\"FLAPS_IN\" address=\"%I0.5\">",
+            "<variable name=\"COMMAND_HEARTBEAT\">\n              <type><derived name=\"TOF\"/></type>\n              <initialValue><structValue><value member=\"PT\"><simpleValue value=\"T#2500MS\"/></value></structValue></initialValue>",
+        ];
+        for fragment in &expected {
+            assert!(xml.contains(fragment), "{}\n\n{}", fragment, xml);
+        }
+
+        // The return type of user functions isn't known
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let function = orig
+            .replacen("<UserFunctionBlockPou>", "<UserFunctionPou>", 1)
+            .replacen("</UserFunctionBlockPou>", "</UserFunctionPou>", 1);
+        let project = Project::from_bytes(function.as_bytes()).unwrap();
+        let xml = export(&project, UNIX_EPOCH);
+        assert!(xml.contains("<pou name=\"OK_TO_LOCK\" pouType=\"function\">"));
+        assert!(xml.contains("<returnType><derived name=\"UNKNOWN\"/></returnType>"));
+        assert!(!xml.contains("<returnType><INT/>"));
+
+        // The document is well-formed
+        let mut tree = TreeBuilder::new();
+        crate::process_bytes(xml.as_bytes(), &mut [&mut tree]).unwrap();
+        let root = tree.into_root().unwrap();
+        assert_eq!(root.name, "project");
+    }
+}
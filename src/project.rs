@@ -0,0 +1,594 @@
+//! Typed model of a Machine Expert - Basic project (.smbp) file
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::grafcet::{GrafcetNode, GrafcetTracer};
+use crate::names::NameTracker;
+use crate::tree::{Element, TreeBuilder};
+use crate::{process_bytes, process_file, XmlNodeVisitor};
+
+#[derive(Debug, Default, Clone)]
+pub struct Project {
+    pub name: String,
+    /// All POUs in file order, including Grafcet step and transition POUs
+    pub pous: Vec<Pou>,
+    pub grafcets: Vec<GrafcetChart>,
+    /// Every entry with an `Address`, from the symbol tables and the hardware configuration
+    pub objects: Vec<ObjectEntry>,
+    pub watch_lists: Vec<WatchList>,
+    /// Every other leaf value in the file, keyed by its element path
+    pub settings: BTreeMap<String, String>,
+    /// The whole file, for comparisons which don't depend on the model
+    pub document: Element,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PouKind {
+    #[default]
+    Program,
+    Step,
+    Transition,
+    FunctionBlock,
+    Function,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Pou {
+    pub kind: PouKind,
+    pub name: String,
+    /// Name of the Grafcet chart for step and transition POUs
+    pub chart: Option<String>,
+    pub section: Option<u32>,
+    pub step_number: Option<u32>,
+    pub comment: String,
+    pub rungs: Vec<Rung>,
+    /// Remaining leaf values, such as function block inputs and outputs
+    pub fields: BTreeMap<String, String>,
+    /// Name for diff headers and reports, see `Pou::context`
+    #[serde(skip)]
+    pub context: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Rung {
+    /// Name for diff headers and reports, the same as in the textconv output
+    #[serde(skip)]
+    pub context: String,
+    pub name: String,
+    pub main_comment: String,
+    pub label: String,
+    pub ladder_selected: bool,
+    pub il: Vec<IlLine>,
+    pub ladder: Vec<LadderEntity>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct IlLine {
+    pub text: String,
+    pub comment: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct LadderEntity {
+    pub element_type: String,
+    pub element_name: String,
+    pub descriptor: String,
+    pub symbol: String,
+    pub comment: String,
+    pub row: u32,
+    pub column: u32,
+    pub fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum GrafcetKind {
+    Step,
+    Transition,
+    OrFork,
+    OrJunction,
+    Other(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct GrafcetElement {
+    /// Name of a step or transition for reports, the same as the context of its POU
+    pub context: String,
+    pub kind: GrafcetKind,
+    pub node: GrafcetNode,
+    pub initial: bool,
+    pub step_number: Option<u32>,
+    pub row: Option<u32>,
+    pub column: Option<u32>,
+    /// Index into `Project::pous` of the step or transition POU
+    pub pou: Option<usize>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct GrafcetChart {
+    pub name: String,
+    pub section: Option<u32>,
+    pub elements: Vec<GrafcetElement>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ObjectEntry {
+    /// Name of the containing table, e.g. `MemoryBits` or `DigitalInputs`
+    pub table: String,
+    /// Element name of the entry, e.g. `MemoryBit` or `TimerTM`
+    pub kind: String,
+    pub address: String,
+    pub index: Option<u32>,
+    pub symbol: String,
+    pub comment: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct WatchList {
+    pub name: String,
+    pub addresses: Vec<String>,
+}
+
+impl Display for PouKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Program => "POU",
+            Self::Step => "step",
+            Self::Transition => "transition",
+            Self::FunctionBlock => "function block",
+            Self::Function => "function",
+        })
+    }
+}
+
+impl Pou {
+    /// Context string for diff headers and reports, e.g. "Operational states > Home"
+    ///
+    /// Transitions are named after the steps they link, like in the textconv output, e.g.
+    /// "Lock and unlock->[Stow lock released]->Home".
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+
+    /// Context of the rung with index `idx`, the same as the textconv diff header
+    pub fn rung_context(&self, idx: usize) -> &str {
+        &self.rungs[idx].context
+    }
+
+    /// Context of the rung with index `idx`, with the rung number if other rungs of the POU
+    /// have the same context, e.g. "Main rung 3" for unnamed rungs
+    pub fn rung_label(&self, idx: usize) -> String {
+        let context = self.rung_context(idx);
+        match self.rungs.iter().filter(|r| r.context == context).count() {
+            1 => context.to_string(),
+            _ => format!("{} rung {}", context, idx + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Rung {
+    /// An unnamed rung with the IL lines and no ladder diagram
+    pub(crate) fn from_il(il: &[&str]) -> Self {
+        let il = il.iter().map(|text| IlLine {
+            text: text.to_string(),
+            comment: String::new(),
+        });
+        Self {
+            il: il.collect(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+impl Pou {
+    /// A program with unnamed rungs, named like a parsed one
+    pub(crate) fn program(name: &str, rungs: Vec<Rung>) -> Self {
+        let rungs = rungs
+            .into_iter()
+            .map(|rung| Rung {
+                context: name.to_string(),
+                ..rung
+            })
+            .collect();
+        Self {
+            name: name.to_string(),
+            section: Some(1),
+            rungs,
+            context: name.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Rung {
+    pub fn il_text(&self) -> impl Iterator<Item = &str> {
+        self.il.iter().map(|l| l.text.as_str())
+    }
+}
+
+impl IlLine {
+    /// The whitespace separated words of the instruction
+    pub fn words(&self) -> impl Iterator<Item = &str> {
+        self.text.split_ascii_whitespace()
+    }
+}
+
+impl GrafcetChart {
+    pub fn element(&self, id: &crate::Guid) -> Option<&GrafcetElement> {
+        self.elements.iter().find(|e| &e.node.id == id)
+    }
+}
+
+impl Project {
+    pub fn from_file(smbp_file: &Path) -> Result<Self> {
+        Self::parse(|visitors| {
+            process_file(smbp_file, visitors)
+                .with_context(|| format!("Failed to parse {}", smbp_file.display()))
+        })
+    }
+
+    pub fn from_bytes(smbp: &[u8]) -> Result<Self> {
+        Self::parse(|visitors| process_bytes(smbp, visitors).context("Failed to parse project"))
+    }
+
+    fn parse(process: impl FnOnce(&mut [&mut dyn XmlNodeVisitor]) -> Result<()>) -> Result<Self> {
+        let mut tree = TreeBuilder::new();
+        let mut names = NameTracker::default();
+        let mut grafcet = GrafcetTracer::default();
+        process(&mut [&mut tree, &mut names, &mut grafcet])?;
+        let root = tree.into_root().context("Empty project file")?;
+        let mut project = Self {
+            name: root.child_text("Name").to_string(),
+            ..Default::default()
+        };
+        project.collect(&root, "")?;
+        project.add_names(&names, &grafcet)?;
+        project.document = root;
+        Ok(project)
+    }
+
+    /// Name the POUs and rungs like the textconv diff headers
+    fn add_names(&mut self, names: &NameTracker, grafcet: &GrafcetTracer) -> Result<()> {
+        let mut rungs = names.rungs().iter();
+        for pou in &mut self.pous {
+            pou.context = pou.name.clone();
+            for rung in &mut pou.rungs {
+                rung.context = rungs.next().context("Failed to name every rung")?.clone();
+            }
+        }
+        for chart in &mut self.grafcets {
+            for element in &mut chart.elements {
+                element.context = match element.kind {
+                    GrafcetKind::Step => {
+                        format!("{} > {}", chart.name, names.node(grafcet, &element.node.id))
+                    }
+                    GrafcetKind::Transition => names.transition(grafcet, &element.node),
+                    _ => continue,
+                };
+                if let Some(pou) = element.pou {
+                    self.pous[pou].context = element.context.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Map from address to symbol name, for all objects with a symbol
+    pub fn symbols(&self) -> HashMap<&str, &str> {
+        self.objects
+            .iter()
+            .filter(|o| !o.symbol.is_empty())
+            .map(|o| (o.address.as_str(), o.symbol.as_str()))
+            .collect()
+    }
+
+    pub fn object(&self, address: &str) -> Option<&ObjectEntry> {
+        self.objects.iter().find(|o| o.address == address)
+    }
+
+    /// Iterate over every rung as (pou index, rung index, rung)
+    pub fn rungs(&self) -> impl Iterator<Item = (usize, usize, &Rung)> {
+        self.pous.iter().enumerate().flat_map(|(p, pou)| {
+            pou.rungs
+                .iter()
+                .enumerate()
+                .map(move |(r, rung)| (p, r, rung))
+        })
+    }
+
+    fn collect(&mut self, elem: &Element, path: &str) -> Result<()> {
+        for (child, key) in keyed_children(elem, path) {
+            match child.name.as_str() {
+                "GrafcetPou" => self.add_grafcet(child)?,
+                "WatchListEntity" => self.watch_lists.push(WatchList {
+                    name: child.child_text("Name").to_string(),
+                    addresses: child
+                        .find("WatchListItemEntities")
+                        .into_iter()
+                        .flat_map(|items| items.children.iter())
+                        .map(|item| item.child_text("Address").to_string())
+                        .collect(),
+                }),
+                _ if child.child("Rungs").is_some() => {
+                    let kind = match child.name.as_str() {
+                        "UserFunctionBlockPou" => PouKind::FunctionBlock,
+                        "UserFunctionPou" => PouKind::Function,
+                        _ => PouKind::Program,
+                    };
+                    self.pous.push(parse_pou(child, kind, None)?);
+                }
+                _ if child.child("Address").is_some() => {
+                    self.objects.push(parse_object(child, &elem.name))
+                }
+                _ if child.is_leaf() => {
+                    self.settings.insert(key, child.text.clone());
+                }
+                _ => self.collect(child, &key)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn add_grafcet(&mut self, elem: &Element) -> Result<()> {
+        let mut chart = GrafcetChart {
+            name: elem.child_text("Name").to_string(),
+            section: parse_num(elem.child_text("SectionNumber")),
+            elements: Vec::new(),
+        };
+        for node in elem.find("Elements").into_iter().flat_map(|e| &e.children) {
+            let kind = match node.name.as_str() {
+                "GrafcetNodeStep" => GrafcetKind::Step,
+                "GrafcetTransition" => GrafcetKind::Transition,
+                "GrafcetOrFork" => GrafcetKind::OrFork,
+                "GrafcetOrJunction" => GrafcetKind::OrJunction,
+                other => GrafcetKind::Other(other.to_string()),
+            };
+            let pou = match (
+                &kind,
+                node.child("StepPou"),
+                node.child("ProgramOrganizationUnits"),
+            ) {
+                (GrafcetKind::Step, Some(pou), _) => {
+                    Some(parse_pou(pou, PouKind::Step, Some(&chart.name))?)
+                }
+                (GrafcetKind::Transition, _, Some(pou)) => {
+                    let mut pou = parse_pou(pou, PouKind::Transition, Some(&chart.name))?;
+                    if pou.name.is_empty() {
+                        pou.name = match pou.rungs.first() {
+                            Some(rung) if !rung.name.is_empty() => rung.name.clone(),
+                            _ => format!(
+                                "({}, {})",
+                                node.child_text("Row"),
+                                node.child_text("Column")
+                            ),
+                        };
+                    }
+                    Some(pou)
+                }
+                _ => None,
+            };
+            let step_number = pou.as_ref().and_then(|p| p.step_number);
+            let pou = pou.map(|pou| {
+                self.pous.push(pou);
+                self.pous.len() - 1
+            });
+            chart.elements.push(GrafcetElement {
+                context: String::new(),
+                kind,
+                node: GrafcetNode {
+                    id: node.child_text("Id").try_into()?,
+                    from: guids(node, "From")?,
+                    to: guids(node, "To")?,
+                },
+                initial: node.child_text("IsInitialStep") == "true",
+                step_number,
+                row: parse_num(node.child_text("Row")),
+                column: parse_num(node.child_text("Column")),
+                pou,
+            });
+        }
+        self.grafcets.push(chart);
+        Ok(())
+    }
+}
+
+fn guids(node: &Element, name: &str) -> Result<Vec<crate::Guid>> {
+    node.children(name)
+        .map(|c| c.text.as_str().try_into())
+        .collect()
+}
+
+fn parse_num(txt: &str) -> Option<u32> {
+    txt.trim().parse().ok()
+}
+
+/// Pair each child with its settings key, adding an index to repeated element names
+fn keyed_children<'a>(
+    elem: &'a Element,
+    path: &'a str,
+) -> impl Iterator<Item = (&'a Element, String)> + 'a {
+    let mut seen = HashMap::new();
+    elem.children.iter().map(move |child| {
+        let repeated = elem.children(&child.name).nth(1).is_some();
+        let cnt = seen.entry(child.name.as_str()).or_insert(0);
+        let name = if repeated {
+            format!("{}[{}]", child.name, cnt)
+        } else {
+            child.name.clone()
+        };
+        *cnt += 1;
+        let key = if path.is_empty() {
+            name
+        } else {
+            format!("{}/{}", path, name)
+        };
+        (child, key)
+    })
+}
+
+/// Collect the leaf values below `elem`, except for the listed child elements
+fn flatten(elem: &Element, skip: &[&str]) -> BTreeMap<String, String> {
+    fn walk(elem: &Element, path: &str, map: &mut BTreeMap<String, String>) {
+        for (child, key) in keyed_children(elem, path) {
+            if child.is_leaf() {
+                map.insert(key, child.text.clone());
+            } else {
+                walk(child, &key, map);
+            }
+        }
+    }
+    let mut map = BTreeMap::new();
+    for (child, key) in keyed_children(elem, "") {
+        if skip.contains(&child.name.as_str()) {
+            continue;
+        }
+        if child.is_leaf() {
+            map.insert(key, child.text.clone());
+        } else {
+            walk(child, &key, &mut map);
+        }
+    }
+    map
+}
+
+fn parse_pou(elem: &Element, kind: PouKind, chart: Option<&str>) -> Result<Pou> {
+    Ok(Pou {
+        kind,
+        name: elem.child_text("Name").to_string(),
+        chart: chart.map(str::to_string),
+        section: parse_num(elem.child_text("SectionNumber")),
+        step_number: parse_num(elem.child_text("StepNumber")),
+        comment: elem.child_text("Comment").to_string(),
+        rungs: elem
+            .find("Rungs")
+            .into_iter()
+            .flat_map(|r| r.children("RungEntity"))
+            .map(parse_rung)
+            .collect(),
+        fields: flatten(
+            elem,
+            &["Name", "SectionNumber", "StepNumber", "Comment", "Rungs"],
+        ),
+        context: String::new(),
+    })
+}
+
+fn parse_rung(elem: &Element) -> Rung {
+    Rung {
+        context: String::new(),
+        name: elem.child_text("Name").to_string(),
+        main_comment: elem.child_text("MainComment").to_string(),
+        label: elem.child_text("Label").to_string(),
+        ladder_selected: elem.child_text("IsLadderSelected") == "true",
+        il: elem
+            .find("InstructionLines")
+            .into_iter()
+            .flat_map(|il| il.children("InstructionLineEntity"))
+            .map(|line| IlLine {
+                text: line.child_text("InstructionLine").to_string(),
+                comment: line.child_text("Comment").to_string(),
+            })
+            .collect(),
+        ladder: elem
+            .find("LadderElements")
+            .into_iter()
+            .flat_map(|l| l.children("LadderEntity"))
+            .map(|entity| LadderEntity {
+                element_type: entity.child_text("ElementType").to_string(),
+                element_name: entity.child_text("ElementName").to_string(),
+                descriptor: entity.child_text("Descriptor").to_string(),
+                symbol: entity.child_text("Symbol").to_string(),
+                comment: entity.child_text("Comment").to_string(),
+                row: parse_num(entity.child_text("Row")).unwrap_or_default(),
+                column: parse_num(entity.child_text("Column")).unwrap_or_default(),
+                fields: flatten(
+                    entity,
+                    &[
+                        "ElementType",
+                        "ElementName",
+                        "Descriptor",
+                        "Symbol",
+                        "Comment",
+                        "Row",
+                        "Column",
+                    ],
+                ),
+            })
+            .collect(),
+    }
+}
+
+fn parse_object(elem: &Element, table: &str) -> ObjectEntry {
+    ObjectEntry {
+        table: table.to_string(),
+        kind: elem.name.clone(),
+        address: elem.child_text("Address").to_string(),
+        index: parse_num(elem.child_text("Index")),
+        symbol: elem.child_text("Symbol").to_string(),
+        comment: elem.child_text("Comment").to_string(),
+        fields: flatten(elem, &["Address", "Index", "Symbol", "Comment"]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_project_model() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        assert_eq!(project.name, "M221 IO-box 2020 v2");
+
+        let validate = &project.pous[0];
+        assert_eq!(validate.name, "Validate inputs");
+        assert_eq!(validate.rung_context(0), "Validate inputs");
+        assert_eq!(validate.rung_context(1), "Validate inputs > Flaps");
+        assert_eq!(validate.rung_label(0), "Validate inputs");
+        assert_eq!(validate.rung_label(1), "Validate inputs > Flaps");
+        assert_eq!(validate.rungs[1].il[1].text, "AND  %I0.4");
+
+        let chart = &project.grafcets[0];
+        assert_eq!(chart.elements.iter().filter(|e| e.initial).count(), 1);
+        let step = &project.pous[chart.elements[0].pou.unwrap()];
+        assert_eq!(step.context(), "Operational states > Lock and unlock");
+        assert_eq!(
+            step.rung_context(0),
+            "Operational states > Lock and unlock > Stop decl drive"
+        );
+        let transition = project
+            .pous
+            .iter()
+            .find(|p| p.name == "Stow lock released")
+            .unwrap();
+        assert_eq!(
+            transition.context(),
+            "Lock and unlock->[Stow lock released]->Home"
+        );
+        let elements = &project.grafcets[0].elements;
+        let element = elements
+            .iter()
+            .find(|e| {
+                e.pou
+                    .is_some_and(|p| project.pous[p].name == "Stow lock released")
+            })
+            .unwrap();
+        assert_eq!(element.context, transition.context());
+        assert!(elements.iter().all(|e| e.context.is_empty()
+            == matches!(e.kind, GrafcetKind::OrFork | GrafcetKind::OrJunction)));
+
+        assert_eq!(project.symbols()["%M10"], "IO_INVALID");
+        assert_eq!(project.object("%TM0").unwrap().fields["Type"], "TOF");
+        assert_eq!(
+            project.settings["HardwareConfiguration/Plc/Cpu/Reference"],
+            "TM221CE40T"
+        );
+    }
+}
@@ -0,0 +1,385 @@
+//! Scenario tests of the logic, run with the simulator
+//!
+//! A scenario file, in TOML or YAML, is a test suite with a list of scenarios. Each scenario
+//! is a sequence of steps, which set inputs or memory, scan or let time pass, and check the
+//! resulting memory and active Grafcet steps. Objects are given by symbol name or address.
+//! ```toml
+//! [[scenarios]]
+//! name = "Serial OK follows the heartbeat"
+//!
+//! [[scenarios.steps]]
+//! set = { MODBUS_HEARTBEAT = true }
+//! expect = { OUT_SERIAL_OK = true }
+//!
+//! [[scenarios.steps]]
+//! wait = "3s"
+//! expect = { OUT_SERIAL_OK = false }
+//! active = ["Lock and unlock"]
+//! ```
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::coverage::Coverage;
+use crate::html::escape;
+use crate::project::Project;
+use crate::sim::{Simulator, Value};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioFile {
+    pub scenarios: Vec<Scenario>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+/// One step of a scenario, the values are set, then the simulator scans, and then the
+/// expectations are checked
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    #[serde(default)]
+    pub set: BTreeMap<String, ScenarioValue>,
+    /// Number of scans, 1 by default
+    pub scans: Option<u32>,
+    /// Time to scan for instead of a number of scans, e.g. `2.5s` or `100ms`
+    pub wait: Option<String>,
+    #[serde(default)]
+    pub expect: BTreeMap<String, ScenarioValue>,
+    /// Names of the Grafcet steps which should be the only active steps
+    pub active: Option<Vec<String>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ScenarioValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+}
+
+impl ScenarioValue {
+    fn value(self) -> Value {
+        match self {
+            Self::Bool(b) => Value::Int(b as i64),
+            Self::Int(i) => Value::Int(i),
+            Self::Float(f) => Value::Float(f),
+        }
+    }
+
+    /// The memory value matches the expected value
+    fn matches(self, value: Value) -> bool {
+        match self {
+            Self::Bool(b) => value.is_true() == b,
+            Self::Int(i) => value == Value::Int(i),
+            Self::Float(f) => (value.float() - f).abs() <= f.abs().max(1.0) * 1e-6,
+        }
+    }
+}
+
+/// Parse a duration like `2.5s`, `100ms` or `1min`
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("Invalid duration {:?}", text))?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "min" => number * 60.0,
+        _ => bail!("Invalid duration {:?}, use ms, s or min", text),
+    };
+    Duration::try_from_secs_f64(seconds).with_context(|| format!("Invalid duration {:?}", text))
+}
+
+/// Address of a symbol name or address, with any pin or bit suffix like `.Q` or `:X3`
+pub fn address(project: &Project, name: &str) -> Option<String> {
+    if name.starts_with('%') {
+        return Some(name.to_string());
+    }
+    let symbol = |s: &str| {
+        let object = project.objects.iter().find(|o| o.symbol == s)?;
+        Some(object.address.clone())
+    };
+    symbol(name).or_else(|| {
+        let split = name.find(':').or_else(|| name.rfind('.'))?;
+        Some(format!("{}{}", symbol(&name[..split])?, &name[split..]))
+    })
+}
+
+/// Result of a scenario, as a JUnit test case
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TestCase {
+    pub name: String,
+    pub time: Duration,
+    /// Expectations which weren't met
+    pub failures: Vec<String>,
+    /// Problem which stopped the scenario, like an unknown symbol
+    pub error: Option<String>,
+    pub coverage: Coverage,
+}
+
+impl TestCase {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty() && self.error.is_none()
+    }
+}
+
+/// The results of the scenarios of one file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TestSuite {
+    pub name: String,
+    pub cases: Vec<TestCase>,
+}
+
+fn run_steps(
+    sim: &mut Simulator,
+    project: &Project,
+    scenario: &Scenario,
+    failures: &mut Vec<String>,
+) -> Result<()> {
+    let lookup =
+        |name: &str| address(project, name).with_context(|| format!("Unknown symbol {}", name));
+    for (n, step) in scenario.steps.iter().enumerate() {
+        let context = || format!("step {}", n + 1);
+        for (name, value) in &step.set {
+            sim.memory
+                .set(&lookup(name).with_context(context)?, value.value());
+        }
+        match (&step.wait, step.scans) {
+            (Some(_), Some(_)) => bail!("{}: both wait and scans are given", context()),
+            (Some(wait), None) => sim.run_for(parse_duration(wait).with_context(context)?),
+            (None, scans) => (0..scans.unwrap_or(1)).try_for_each(|_| sim.scan()),
+        }
+        .with_context(context)?;
+        for (name, expected) in &step.expect {
+            let value = sim.memory.get(&lookup(name).with_context(context)?);
+            if !expected.matches(value) {
+                failures.push(format!(
+                    "{}: {} is {}, expected {}",
+                    context(),
+                    name,
+                    value,
+                    expected.value()
+                ));
+            }
+        }
+        if let Some(expected) = &step.active {
+            let mut active: Vec<String> = sim
+                .active_steps()
+                .map(|(_, step)| {
+                    step.pou
+                        .map_or_else(String::new, |p| project.pous[p].name.clone())
+                })
+                .collect();
+            let mut expected = expected.clone();
+            active.sort();
+            expected.sort();
+            if active != expected {
+                failures.push(format!(
+                    "{}: active steps are {}, expected {}",
+                    context(),
+                    active.join(", "),
+                    expected.join(", ")
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run a scenario on a new simulator
+pub fn run(project: &Project, scenario: &Scenario) -> TestCase {
+    let start = Instant::now();
+    let mut failures = Vec::new();
+    let mut coverage = Coverage::default();
+    let error = match Simulator::new(project) {
+        Ok(mut sim) => {
+            let result = run_steps(&mut sim, project, scenario, &mut failures);
+            coverage = sim.coverage;
+            result.err()
+        }
+        Err(e) => Some(e),
+    };
+    TestCase {
+        name: scenario.name.clone(),
+        time: start.elapsed(),
+        failures,
+        error: error.map(|e| format!("{:#}", e)),
+        coverage,
+    }
+}
+
+/// Load a TOML or YAML scenario file
+pub fn load(path: &Path) -> Result<ScenarioFile> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let file = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&text).map_err(anyhow::Error::from),
+        Some("yaml" | "yml") => serde_yaml_ng::from_str(&text).map_err(anyhow::Error::from),
+        _ => bail!("Unknown scenario file type {}", path.display()),
+    };
+    file.with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// The scenario files in a directory, or the file itself, in name order
+pub fn scenario_files(path: &Path) -> Result<Vec<std::path::PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    for entry in
+        std::fs::read_dir(path).with_context(|| format!("Failed to read {}", path.display()))?
+    {
+        let file = entry?.path();
+        let ext = file
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        if ["toml", "yaml", "yml"].contains(&ext) {
+            files.push(file);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Report of the test suites in the JUnit XML format
+pub fn junit(suites: &[TestSuite]) -> String {
+    let count = |f: fn(&TestCase) -> bool| -> usize {
+        suites
+            .iter()
+            .flat_map(|s| &s.cases)
+            .filter(|c| f(c))
+            .count()
+    };
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\">",
+        count(|_| true),
+        count(|c| c.error.is_none() && !c.failures.is_empty()),
+        count(|c| c.error.is_some()),
+    );
+    for suite in suites {
+        let failures = suite
+            .cases
+            .iter()
+            .filter(|c| c.error.is_none() && !c.failures.is_empty());
+        let errors = suite.cases.iter().filter(|c| c.error.is_some());
+        let time: Duration = suite.cases.iter().map(|c| c.time).sum();
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">",
+            escape(&suite.name),
+            suite.cases.len(),
+            failures.count(),
+            errors.count(),
+            time.as_secs_f64()
+        );
+        for case in &suite.cases {
+            let _ = write!(
+                xml,
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape(&case.name),
+                escape(&suite.name),
+                case.time.as_secs_f64()
+            );
+            if case.passed() {
+                xml.push_str("/>\n");
+                continue;
+            }
+            xml.push_str(">\n");
+            if let Some(error) = &case.error {
+                let _ = writeln!(xml, "      <error message=\"{}\"/>", escape(error));
+            }
+            if let Some(first) = case.failures.first() {
+                let _ = writeln!(
+                    xml,
+                    "      <failure message=\"{}\">{}</failure>",
+                    escape(first),
+                    escape(&case.failures.join("\n"))
+                );
+            }
+            xml.push_str("    </testcase>\n");
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scenarios() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        assert_eq!(address(&project, "POL_DRIVE_TIMER").unwrap(), "%TM1.Q");
+        assert_eq!(address(&project, "COMMAND_HEARTBEAT.V").unwrap(), "%TM0.V");
+        assert_eq!(address(&project, "MW_CMD_INPUT:X3").unwrap(), "%IWM0:X3");
+        assert_eq!(parse_duration("2.5s").unwrap(), Duration::from_millis(2500));
+        // Too long for a Duration
+        assert!(parse_duration(&format!("{}min", u64::MAX)).is_err());
+
+        let file: ScenarioFile = toml::from_str(
+            r#"
+            [[scenarios]]
+            name = "Heartbeat"
+            [[scenarios.steps]]
+            set = { MODBUS_HEARTBEAT = true, EAST_STOW_RELEASED = true, WEST_STOW_RELEASED = true }
+            expect = { OUT_SERIAL_OK = true, "COMMAND_HEARTBEAT.V" = 0 }
+            [[scenarios.steps]]
+            wait = "3s"
+            expect = { OUT_SERIAL_OK = false }
+            active = ["Above horizon, flaps out"]
+
+            [[scenarios]]
+            name = "Failing"
+            [[scenarios.steps]]
+            scans = 2
+            expect = { IO_INVALID = 1, "%QWM5" = 4 }
+            active = ["Home"]
+            [[scenarios.steps]]
+            expect = { NO_SUCH_SYMBOL = 1 }
+            "#,
+        )
+        .unwrap();
+        let cases: Vec<TestCase> = file.scenarios.iter().map(|s| run(&project, s)).collect();
+        assert!(cases[0].passed(), "{:?}", cases[0]);
+        assert_eq!(
+            cases[1].failures,
+            [
+                "step 1: %QWM5 is 3, expected 4",
+                "step 1: IO_INVALID is 0, expected 1",
+                "step 1: active steps are Lock and unlock, expected Home",
+            ]
+        );
+        assert_eq!(
+            cases[1].error.as_deref(),
+            Some("step 2: Unknown symbol NO_SUCH_SYMBOL")
+        );
+
+        let suites = [TestSuite {
+            name: "heartbeat".to_string(),
+            cases,
+        }];
+        let report = junit(&suites);
+        assert!(report.contains("<testsuites tests=\"2\" failures=\"0\" errors=\"1\">"));
+        assert!(report.contains("<failure message=\"step 1: %QWM5 is 3, expected 4\">"));
+    }
+}
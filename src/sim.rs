@@ -0,0 +1,1079 @@
+//! Offline simulation of the IL of a project
+//!
+//! The memory is a map from address to value, where bits are stored as 0 or 1. Word bits
+//! like `%MW0:X3` and bit strings like `%Q0.0:16` are views of the underlying words and
+//! bits. Programs are executed in section order once per `Simulator::scan`, with the
+//! accumulator, parentheses and `MPS`/`MRD`/`MPP` stack semantics of the controller.
+//!
+//! Grafcet charts are executed at the position of their steps in the section order. The
+//! actions of the active steps are executed first, then the transitions following an active
+//! step are evaluated, in chart order. The transitions which are true fire together at the
+//! end of the chart, where an earlier transition has priority over a later one leaving the
+//! same step. The active steps are also written to their `%X` bits.
+//!
+//! Timers, counters and other function blocks are updated by their `BlockModel` when their
+//! `BLK` structure is executed. The models use a virtual clock, which is advanced by the
+//! cycle time after each scan, or explicitly with `Simulator::advance`.
+//!
+//! User function blocks are executed when their `UDFn` call is reached, with the named
+//! inputs and outputs of the block body mapped to the bits of `%UDFn.PARAMW0` and
+//! `%UDFn.RETW0`, and the local variables to their `%UDFn.VARW` words.
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use crate::blocks::{builtin, BlockModel};
+use crate::coverage::Coverage;
+use crate::grafcet::Links;
+use crate::il::{
+    parse_rung, BinaryOp, CompareOp, Condition, Expr, Instruction, LogicOp, Modifier, Operand,
+    Statement, StoreOp, UnaryOp,
+};
+use crate::project::{GrafcetChart, GrafcetElement, GrafcetKind, PouKind, Project};
+use crate::Guid;
+
+/// Jumps per scan before the scan is aborted, like the watchdog of the controller
+const MAX_JUMPS: usize = 100_000;
+
+/// Cycle time when the project doesn't use a periodic scan
+pub const DEFAULT_CYCLE: Duration = Duration::from_millis(10);
+
+/// System bit which is true during the first scan
+pub const FIRST_SCAN: &str = "%S13";
+/// System bit set on arithmetic errors, like division by zero
+pub const ARITHMETIC_ERROR: &str = "%S18";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+}
+
+impl Value {
+    pub fn int(self) -> i64 {
+        match self {
+            Self::Int(i) => i,
+            Self::Float(f) => f as i64,
+        }
+    }
+
+    pub fn float(self) -> f64 {
+        match self {
+            Self::Int(i) => i as f64,
+            Self::Float(f) => f,
+        }
+    }
+
+    pub fn is_true(self) -> bool {
+        match self {
+            Self::Int(i) => i != 0,
+            Self::Float(f) => f != 0.0,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(i) => write!(f, "{}", i),
+            Self::Float(x) => write!(f, "{:?}", x),
+        }
+    }
+}
+
+/// Object type of an address, e.g. `MW` for `%MW0`, `Q` for `%TM0.Q` and `I` for `%I0.4`
+fn object_type(address: &str) -> &str {
+    address
+        .trim_start_matches('%')
+        .rsplit('.')
+        .map(|s| s.trim_end_matches(|c: char| c.is_ascii_digit()))
+        .find(|s| !s.is_empty())
+        .unwrap_or_default()
+}
+
+/// Truncate a value to the size of the object at `address`
+fn fit(address: &str, value: Value) -> Value {
+    let t = object_type(address);
+    match t {
+        "MF" | "KF" => Value::Float(value.float()),
+        _ if t.ends_with('W') || t.ends_with("WM") => Value::Int(value.int() as i16 as i64),
+        _ if t.len() > 1 && t.ends_with('D') => Value::Int(value.int() as i32 as i64),
+        _ => Value::Int(value.int()),
+    }
+}
+
+/// Split a word bit, `%MW0:X3`, into the word and the bit number
+fn word_bit(address: &str) -> Option<(&str, u32)> {
+    let (word, bit) = address.split_once(":X")?;
+    Some((word, bit.parse().ok().filter(|b| *b < 64)?))
+}
+
+/// The bits of a bit string, `%Q0.0:16` is `%Q0.0` to `%Q0.15`
+fn bit_string(address: &str) -> Option<Vec<String>> {
+    let (first, len) = address.split_once(':')?;
+    let len: usize = len.parse().ok().filter(|l| *l <= 64)?;
+    let digits = first.len() - first.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let (prefix, start) = first.split_at(first.len() - digits);
+    let start: usize = start.parse().ok()?;
+    Some(
+        (start..start + len)
+            .map(|n| format!("{}{}", prefix, n))
+            .collect(),
+    )
+}
+
+/// Values of the addressed objects, everything is zero until it is written
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Memory {
+    values: BTreeMap<String, Value>,
+}
+
+impl Memory {
+    pub fn get(&self, address: &str) -> Value {
+        if let Some((word, bit)) = word_bit(address) {
+            return Value::Int((self.get(word).int() >> bit) & 1);
+        }
+        if let Some(bits) = bit_string(address) {
+            let word = bits
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| self.bit(b))
+                .fold(0, |w, (n, _)| w | 1 << n);
+            return fit(address, Value::Int(word));
+        }
+        match self.values.get(address) {
+            Some(value) => *value,
+            None => fit(address, Value::Int(0)),
+        }
+    }
+
+    pub fn set(&mut self, address: &str, value: Value) {
+        if let Some((word, bit)) = word_bit(address) {
+            let w = self.get(word).int() & !(1 << bit) | (value.is_true() as i64) << bit;
+            return self.set(word, Value::Int(w));
+        }
+        if let Some(bits) = bit_string(address) {
+            let w = value.int();
+            for (n, b) in bits.iter().enumerate() {
+                self.set_bit(b, w >> n & 1 != 0);
+            }
+            return;
+        }
+        self.values.insert(address.to_string(), fit(address, value));
+    }
+
+    pub fn bit(&self, address: &str) -> bool {
+        self.get(address).is_true()
+    }
+
+    pub fn set_bit(&mut self, address: &str, value: bool) {
+        self.set(address, Value::Int(value as i64))
+    }
+
+    /// Every object which has been written, sorted by address
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Value)> {
+        self.values.iter().map(|(a, v)| (a.as_str(), *v))
+    }
+}
+
+/// Mapping from the names used in a user function block body to the global addresses
+pub(crate) type Scope = HashMap<String, String>;
+
+/// Replace the base of an address with its mapping in the scope, keeping any `:X` suffix
+pub(crate) fn resolve(scope: &Scope, address: &str) -> String {
+    let (base, suffix) = address.split_at(address.find(':').unwrap_or(address.len()));
+    match scope.get(base) {
+        Some(mapped) => format!("{}{}", mapped, suffix),
+        None => address.to_string(),
+    }
+}
+
+/// The entries of a list in the POU fields, e.g. `Inputs/UserDefineFunctionBlockInput`,
+/// which is numbered `[0]`, `[1]`, ... when there is more than one entry
+fn field_list<'a>(
+    fields: &'a BTreeMap<String, String>,
+    list: &str,
+) -> Vec<HashMap<&'a str, &'a str>> {
+    let mut entries: BTreeMap<usize, HashMap<&str, &str>> = BTreeMap::new();
+    for (key, value) in fields {
+        let rest = match key.strip_prefix(list) {
+            Some(rest) => rest,
+            None => continue,
+        };
+        let (idx, leaf) = match rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+            Some((idx, leaf)) => (idx.parse().unwrap_or_default(), leaf),
+            None => (0, rest),
+        };
+        if let Some(leaf) = leaf.strip_prefix('/') {
+            entries.entry(idx).or_default().insert(leaf, value);
+        }
+    }
+    entries.into_values().collect()
+}
+
+/// Scope of the body of the user function block `%UDF{number}`
+pub(crate) fn udfb_scope(fields: &BTreeMap<String, String>, number: usize) -> Scope {
+    let mut scope = Scope::new();
+    let pins = [
+        ("Inputs/UserDefineFunctionBlockInput", "PARAMW0"),
+        ("Outputs/UserDefineFunctionBlockOutput", "RETW0"),
+    ];
+    for (list, word) in pins {
+        for entry in field_list(fields, list) {
+            if let (Some(name), Some(idx)) = (entry.get("Name"), entry.get("Index")) {
+                let bit = format!("%UDF{}.{}:X{}", number, word, idx);
+                scope.insert(format!("%{}", name), bit);
+            }
+        }
+    }
+    for entry in field_list(fields, "UserFunctionObjects/UserFunctionObject") {
+        if let (Some(name), Some(address)) = (entry.get("DisplayedAddress"), entry.get("Address")) {
+            scope.insert(name.to_string(), address.to_string());
+        }
+    }
+    scope
+}
+
+/// How execution continues after a rung
+#[derive(Clone, Debug, PartialEq)]
+enum Flow {
+    Next,
+    /// Jump to the label, with the rest of the rung skipped
+    Jump(String),
+    /// `RET`, the rest of the POU is skipped
+    Return,
+    /// `END`, the rest of the scan is skipped
+    End,
+    /// `ENDT` with the value of the transition condition
+    Transition(bool),
+}
+
+/// The boolean state of a rung being executed
+#[derive(Default)]
+struct RungState {
+    acc: bool,
+    parens: Vec<(LogicOp, bool)>,
+    stack: Vec<bool>,
+    /// Instance of the enclosing `BLK` structure, for its pins
+    block: Option<String>,
+    /// The model of the enclosing block has been updated, at `OUT_BLK`
+    updated: bool,
+}
+
+fn combine(acc: bool, op: LogicOp, value: bool) -> bool {
+    match op {
+        LogicOp::Ld => value,
+        LogicOp::And => acc && value,
+        LogicOp::Or => acc || value,
+        LogicOp::Xor => acc ^ value,
+    }
+}
+
+fn holds(condition: Condition, acc: bool) -> bool {
+    match condition {
+        Condition::Always => true,
+        Condition::IfTrue => acc,
+        Condition::IfFalse => !acc,
+    }
+}
+
+/// A program or Grafcet chart, in the section order of the scan
+#[derive(Copy, Clone, Debug)]
+enum Section {
+    Program(usize),
+    Chart(usize),
+}
+
+/// A Grafcet transition, with the steps before and after it as element indices
+#[derive(Clone, Debug)]
+struct ChartTransition {
+    /// Element index of the transition
+    element: usize,
+    pou: Option<usize>,
+    sources: Vec<usize>,
+    targets: Vec<usize>,
+}
+
+/// The steps reached from `id` through OR forks and junctions, following the links in
+/// `links`
+fn linked_steps<'a>(
+    chart: &'a GrafcetChart,
+    links: &HashMap<&'a Guid, HashSet<&'a Guid>>,
+    id: &'a Guid,
+) -> Vec<usize> {
+    let mut steps = BTreeSet::new();
+    let mut seen = HashSet::new();
+    let mut queue = vec![id];
+    while let Some(id) = queue.pop() {
+        for next in links.get(id).into_iter().flatten() {
+            let idx = match chart.elements.iter().position(|e| &e.node.id == *next) {
+                Some(idx) => idx,
+                None => continue,
+            };
+            match chart.elements[idx].kind {
+                GrafcetKind::Step => {
+                    steps.insert(idx);
+                }
+                GrafcetKind::Transition => {}
+                _ if seen.insert(*next) => queue.push(next),
+                _ => {}
+            }
+        }
+    }
+    steps.into_iter().collect()
+}
+
+/// The state of a Grafcet chart
+#[derive(Clone, Debug)]
+struct ChartState {
+    transitions: Vec<ChartTransition>,
+    /// Element indices of the active steps
+    active: BTreeSet<usize>,
+}
+
+impl ChartState {
+    fn new(chart: &GrafcetChart) -> Self {
+        let links = Links::new(chart);
+        let transitions = chart
+            .elements
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.kind == GrafcetKind::Transition)
+            .map(|(element, e)| ChartTransition {
+                element,
+                pou: e.pou,
+                sources: linked_steps(chart, &links.predecessors, &e.node.id),
+                targets: linked_steps(chart, &links.successors, &e.node.id),
+            })
+            .collect();
+        let active = (0..chart.elements.len())
+            .filter(|s| chart.elements[*s].initial)
+            .collect();
+        Self {
+            transitions,
+            active,
+        }
+    }
+}
+
+/// Executes the IL of a project against a `Memory`
+pub struct Simulator<'a> {
+    project: &'a Project,
+    /// The parsed rungs of every POU, in the order of `Project::pous`
+    code: Vec<Vec<Vec<Statement>>>,
+    /// Programs and charts in execution order
+    sections: Vec<Section>,
+    /// The state of every chart, in the order of `Project::grafcets`
+    charts: Vec<ChartState>,
+    /// POU index and scope of the user function blocks, by their `UDF` number
+    udfbs: Vec<(usize, Scope)>,
+    pub memory: Memory,
+    /// Rungs, branches, steps and transitions reached so far
+    pub coverage: Coverage,
+    /// Function block models by instance, e.g. `%TM0`
+    models: BTreeMap<String, Box<dyn BlockModel>>,
+    /// Time of the virtual clock
+    now: Duration,
+    /// Time the clock is advanced by after each scan
+    pub cycle: Duration,
+    /// The user function block being executed, e.g. `UDF2`, empty in programs and charts
+    instance: String,
+    /// Previous values of the operands of `LDR`, `ANDF`, ..., by instance, POU, rung and line
+    edges: HashMap<(String, (usize, usize, usize)), bool>,
+    scans: u64,
+}
+
+impl<'a> Simulator<'a> {
+    pub fn new(project: &'a Project) -> Result<Self> {
+        let mut code = Vec::new();
+        for pou in &project.pous {
+            let rungs = pou.rungs.iter().enumerate().map(|(idx, rung)| {
+                parse_rung(rung).with_context(|| pou.rung_context(idx).to_string())
+            });
+            code.push(rungs.collect::<Result<Vec<_>>>()?);
+        }
+        let mut sections: Vec<(Option<u32>, Section)> = (0..project.pous.len())
+            .filter(|p| project.pous[*p].kind == PouKind::Program)
+            .map(|p| (project.pous[p].section, Section::Program(p)))
+            .collect();
+        for (c, chart) in project.grafcets.iter().enumerate() {
+            // The chart itself has section 0, its steps are numbered in the program order
+            let steps = chart
+                .elements
+                .iter()
+                .filter(|e| e.kind == GrafcetKind::Step);
+            let section = chart
+                .section
+                .filter(|s| *s > 0)
+                .or_else(|| steps.filter_map(|e| project.pous[e.pou?].section).min());
+            sections.push((section, Section::Chart(c)));
+        }
+        sections.sort_by_key(|(section, _)| *section);
+        let udfbs = (0..project.pous.len())
+            .filter(|p| project.pous[*p].kind == PouKind::FunctionBlock)
+            .enumerate()
+            .map(|(n, p)| (p, udfb_scope(&project.pous[p].fields, n)))
+            .collect();
+        let mut memory = Memory::default();
+        let mut models = BTreeMap::new();
+        for object in &project.objects {
+            if let Some(model) = builtin(object) {
+                models.insert(object.address.clone(), model);
+                if let Some(preset) = object.fields.get("Preset").and_then(|p| p.parse().ok()) {
+                    memory.set(&format!("{}.P", object.address), Value::Int(preset));
+                }
+            }
+        }
+        let setting = |name: &str| {
+            project
+                .settings
+                .get(&format!("SoftwareConfiguration/MastTask/{}", name))
+        };
+        let cycle = match setting("PeriodScan") {
+            Some(ms) if setting("UsePeriodScanMode").is_some_and(|p| p == "true") => {
+                Duration::from_millis(ms.parse().context("Invalid MAST task period")?)
+            }
+            _ => DEFAULT_CYCLE,
+        };
+        Ok(Self {
+            project,
+            code,
+            sections: sections.into_iter().map(|(_, s)| s).collect(),
+            charts: project.grafcets.iter().map(ChartState::new).collect(),
+            udfbs,
+            memory,
+            coverage: Coverage::default(),
+            models,
+            now: Duration::ZERO,
+            cycle,
+            instance: String::new(),
+            edges: HashMap::new(),
+            scans: 0,
+        })
+    }
+
+    /// Number of completed scans
+    pub fn scans(&self) -> u64 {
+        self.scans
+    }
+
+    /// Time of the virtual clock
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Advance the virtual clock without scanning
+    pub fn advance(&mut self, time: Duration) {
+        self.now += time;
+    }
+
+    /// Scan until the clock has advanced by `time`
+    pub fn run_for(&mut self, time: Duration) -> Result<()> {
+        let end = self.now + time;
+        while self.now < end && !self.cycle.is_zero() {
+            self.scan()?;
+        }
+        self.now = self.now.max(end);
+        Ok(())
+    }
+
+    /// Use `model` for the function block `instance`, e.g. `%TM0`, instead of the built-in
+    /// model, if any
+    pub fn set_model(&mut self, instance: &str, model: Box<dyn BlockModel>) {
+        self.models.insert(instance.to_string(), model);
+    }
+
+    /// The active steps of every chart
+    pub fn active_steps(
+        &self,
+    ) -> impl Iterator<Item = (&'a GrafcetChart, &'a GrafcetElement)> + '_ {
+        let charts = self.project.grafcets.iter().zip(&self.charts);
+        charts.flat_map(|(chart, state)| {
+            state
+                .active
+                .iter()
+                .map(move |s| (chart, &chart.elements[*s]))
+        })
+    }
+
+    /// Execute the programs and charts once, in section order
+    pub fn scan(&mut self) -> Result<()> {
+        self.memory.set_bit(FIRST_SCAN, self.scans == 0);
+        for c in 0..self.charts.len() {
+            self.write_steps(c);
+        }
+        for section in self.sections.clone() {
+            let flow = match section {
+                Section::Program(p) => self.run_pou(p, &Scope::new())?,
+                Section::Chart(c) => self.run_chart(c)?,
+            };
+            if flow == Flow::End {
+                break;
+            }
+        }
+        self.scans += 1;
+        self.now += self.cycle;
+        Ok(())
+    }
+
+    /// Set the `%X` bits of the steps of a chart
+    fn write_steps(&mut self, c: usize) {
+        let chart = &self.project.grafcets[c];
+        for (idx, step) in chart.elements.iter().enumerate() {
+            if let (GrafcetKind::Step, Some(n)) = (&step.kind, step.step_number) {
+                let active = self.charts[c].active.contains(&idx);
+                self.memory.set_bit(&format!("%X{}", n), active);
+            }
+        }
+    }
+
+    /// Execute the active steps of a chart and fire the transitions which are true
+    fn run_chart(&mut self, c: usize) -> Result<Flow> {
+        let chart = &self.project.grafcets[c];
+        let active = self.charts[c].active.clone();
+        for s in &active {
+            self.coverage.step(c, *s);
+        }
+        for pou in active.iter().filter_map(|s| chart.elements[*s].pou) {
+            if self.run_pou(pou, &Scope::new())? == Flow::End {
+                return Ok(Flow::End);
+            }
+        }
+        let mut fired = Vec::new();
+        for (t, transition) in self.charts[c].transitions.clone().into_iter().enumerate() {
+            let pou = match transition.pou {
+                Some(pou) if transition.sources.iter().any(|s| active.contains(s)) => pou,
+                _ => continue,
+            };
+            match self.run_pou(pou, &Scope::new())? {
+                Flow::Transition(true) => fired.push(t),
+                Flow::End => return Ok(Flow::End),
+                _ => {}
+            }
+        }
+        let state = &mut self.charts[c];
+        let mut left = BTreeSet::new();
+        for t in fired {
+            let transition = &state.transitions[t];
+            let sources: Vec<usize> = transition
+                .sources
+                .iter()
+                .copied()
+                .filter(|s| active.contains(s) && !left.contains(s))
+                .collect();
+            if sources.is_empty() {
+                continue;
+            }
+            for s in sources {
+                state.active.remove(&s);
+                left.insert(s);
+            }
+            self.coverage.transition(c, transition.element);
+            state.active.extend(&transition.targets);
+        }
+        self.write_steps(c);
+        Ok(Flow::Next)
+    }
+
+    /// Execute the rungs of a POU, `Flow::End` if it ended the scan
+    fn run_pou(&mut self, pou: usize, scope: &Scope) -> Result<Flow> {
+        let (mut rung, mut line) = (0, 0);
+        let mut jumps = 0;
+        while rung < self.code[pou].len() {
+            let flow = self
+                .run_rung(pou, rung, line, scope)
+                .with_context(|| self.project.pous[pou].rung_context(rung).to_string())?;
+            match flow {
+                Flow::Next => (rung, line) = (rung + 1, 0),
+                Flow::Jump(label) => {
+                    jumps += 1;
+                    if jumps > MAX_JUMPS {
+                        bail!("Watchdog, more than {} jumps in one scan", MAX_JUMPS);
+                    }
+                    (rung, line) = self
+                        .label(pou, &label)
+                        .with_context(|| format!("Jump to unknown label {}", label))?;
+                }
+                Flow::Return => break,
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Rung and line of a label in a POU
+    fn label(&self, pou: usize, label: &str) -> Option<(usize, usize)> {
+        self.code[pou].iter().enumerate().find_map(|(r, rung)| {
+            let l = rung
+                .iter()
+                .position(|s| s.instruction == Instruction::Label(label.to_string()))?;
+            Some((r, l))
+        })
+    }
+
+    fn run_rung(&mut self, pou: usize, rung: usize, start: usize, scope: &Scope) -> Result<Flow> {
+        let mut state = RungState {
+            acc: true,
+            ..Default::default()
+        };
+        let mut flow = Flow::Next;
+        for line in start..self.code[pou][rung].len() {
+            let instruction = self.code[pou][rung][line].instruction.clone();
+            flow = self
+                .execute(&instruction, &mut state, (pou, rung, line), scope)
+                .with_context(|| format!("line {}: {}", line + 1, instruction))?;
+            if flow != Flow::Next {
+                break;
+            }
+        }
+        self.coverage.rung(pou, rung, state.acc);
+        Ok(flow)
+    }
+
+    /// Global address of an operand, with the pins of `BLK` structures and the names of user
+    /// function block bodies resolved
+    fn address(&self, operand: &Operand, state: &RungState, scope: &Scope) -> Result<String> {
+        match (operand, &state.block) {
+            (Operand::Address(a), _) => Ok(resolve(scope, a)),
+            (Operand::Name(pin), Some(block)) => Ok(format!("{}.{}", block, pin)),
+            _ => bail!("{} is not an address", operand),
+        }
+    }
+
+    fn bit(
+        &mut self,
+        operand: &Operand,
+        modifier: Modifier,
+        state: &RungState,
+        key: (usize, usize, usize),
+        scope: &Scope,
+    ) -> Result<bool> {
+        let value = match operand {
+            Operand::Integer(i) => *i != 0,
+            Operand::Compare(e) => self.eval(e, state, scope)?.is_true(),
+            o => self.memory.bit(&self.address(o, state, scope)?),
+        };
+        Ok(match modifier {
+            Modifier::None => value,
+            Modifier::Not => !value,
+            Modifier::Rising => {
+                let edge = (self.instance.clone(), key);
+                value && !self.edges.insert(edge, value).unwrap_or(false)
+            }
+            Modifier::Falling => {
+                let edge = (self.instance.clone(), key);
+                !value && self.edges.insert(edge, value).unwrap_or(false)
+            }
+        })
+    }
+
+    fn execute(
+        &mut self,
+        instruction: &Instruction,
+        state: &mut RungState,
+        key: (usize, usize, usize),
+        scope: &Scope,
+    ) -> Result<Flow> {
+        if let Instruction::Jump { condition, .. }
+        | Instruction::Call { condition, .. }
+        | Instruction::Return(condition)
+        | Instruction::End(condition) = instruction
+        {
+            if *condition != Condition::Always {
+                self.coverage.branch(key, holds(*condition, state.acc));
+            }
+        }
+        match instruction {
+            Instruction::Logic {
+                op,
+                modifier,
+                operand,
+            } => {
+                let value = self.bit(operand, *modifier, state, key, scope)?;
+                state.acc = combine(state.acc, *op, value);
+            }
+            Instruction::Open {
+                op,
+                modifier,
+                operand,
+            } => {
+                state.parens.push((*op, state.acc));
+                state.acc = match operand {
+                    Some(operand) => self.bit(operand, *modifier, state, key, scope)?,
+                    None => true,
+                };
+            }
+            Instruction::Close => {
+                let (op, saved) = state.parens.pop().context("')' without '('")?;
+                state.acc = combine(saved, op, state.acc);
+            }
+            Instruction::Not => state.acc = !state.acc,
+            Instruction::Mps => state.stack.push(state.acc),
+            Instruction::Mrd => state.acc = *state.stack.last().context("MRD without MPS")?,
+            Instruction::Mpp => state.acc = state.stack.pop().context("MPP without MPS")?,
+            Instruction::Store { op, operand } => {
+                let address = self.address(operand, state, scope)?;
+                match op {
+                    StoreOp::St => self.memory.set_bit(&address, state.acc),
+                    StoreOp::Stn => self.memory.set_bit(&address, !state.acc),
+                    StoreOp::Set if state.acc => self.memory.set_bit(&address, true),
+                    StoreOp::Reset if state.acc => self.memory.set_bit(&address, false),
+                    StoreOp::Set | StoreOp::Reset => {}
+                }
+            }
+            Instruction::Blk(instance) => {
+                state.block = Some(resolve(scope, instance));
+                state.updated = false;
+            }
+            Instruction::FbInput(pin) => {
+                let block = state.block.as_ref().context("Input outside of BLK")?;
+                self.memory
+                    .set_bit(&format!("{}.{}", block, pin), state.acc);
+            }
+            Instruction::OutBlk => {
+                self.update_block(state);
+                state.updated = true;
+            }
+            Instruction::EndBlk => {
+                if !state.updated {
+                    self.update_block(state);
+                }
+                state.block = None;
+            }
+            Instruction::BlkUdfb(_) | Instruction::EndBlkUdfb(_) => {}
+            Instruction::CallUdf(udf) if state.acc => {
+                let (pou, scope) = udf
+                    .strip_prefix("UDF")
+                    .and_then(|n| n.parse().ok())
+                    .and_then(|n: usize| self.udfbs.get(n))
+                    .cloned()
+                    .with_context(|| format!("Unknown user function block {}", udf))?;
+                let caller = std::mem::replace(&mut self.instance, udf.clone());
+                let flow = self.run_pou(pou, &scope);
+                self.instance = caller;
+                if flow? == Flow::End {
+                    return Ok(Flow::End);
+                }
+            }
+            Instruction::CallUdf(_) => {}
+            Instruction::Operation { dest, expr } if state.acc => {
+                let value = self.eval(expr, state, scope)?;
+                let dest = self.address(dest, state, scope)?;
+                self.memory.set(&dest, value);
+            }
+            Instruction::Operation { .. } => {}
+            Instruction::Jump { condition, label } if holds(*condition, state.acc) => {
+                return Ok(Flow::Jump(label.clone()))
+            }
+            Instruction::Jump { .. } | Instruction::Label(_) => {}
+            // Subroutines aren't simulated, the calls are listed in the coverage report
+            Instruction::Call { condition, .. } if holds(*condition, state.acc) => {
+                self.coverage.skipped_call(key)
+            }
+            Instruction::Call { .. } => {}
+            Instruction::Return(condition) if holds(*condition, state.acc) => {
+                return Ok(Flow::Return)
+            }
+            Instruction::End(condition) if holds(*condition, state.acc) => return Ok(Flow::End),
+            Instruction::Return(_) | Instruction::End(_) => {}
+            Instruction::EndTransition => return Ok(Flow::Transition(state.acc)),
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Update the outputs of the enclosing `BLK` structure with its model
+    fn update_block(&mut self, state: &RungState) {
+        let block = state.block.as_deref().unwrap_or_default();
+        if let Some(model) = self.models.get_mut(block) {
+            model.update(block, &mut self.memory, self.now);
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr, state: &RungState, scope: &Scope) -> Result<Value> {
+        Ok(match expr {
+            Expr::Operand(Operand::Integer(i)) => Value::Int(*i),
+            Expr::Operand(Operand::Float(f)) => Value::Float(*f),
+            Expr::Operand(Operand::Compare(e)) => self.eval(e, state, scope)?,
+            Expr::Operand(o) => self.memory.get(&self.address(o, state, scope)?),
+            Expr::Unary(op, e) => match (op, self.eval(e, state, scope)?) {
+                (UnaryOp::Neg, Value::Int(i)) => Value::Int(i.wrapping_neg()),
+                (UnaryOp::Neg, Value::Float(f)) => Value::Float(-f),
+                (UnaryOp::Not, v) => Value::Int(!v.int()),
+            },
+            Expr::Binary(l, op, r) => {
+                let l = self.eval(l, state, scope)?;
+                let r = self.eval(r, state, scope)?;
+                self.binary(l, *op, r)
+            }
+            Expr::Call(f, args) => {
+                let args = args
+                    .iter()
+                    .map(|a| self.eval(a, state, scope))
+                    .collect::<Result<Vec<_>>>()?;
+                function(f, &args)?
+            }
+        })
+    }
+
+    fn binary(&mut self, l: Value, op: BinaryOp, r: Value) -> Value {
+        let compare = |op: CompareOp, ordering: Option<std::cmp::Ordering>| {
+            use std::cmp::Ordering::*;
+            let result = match (op, ordering) {
+                (CompareOp::Eq, o) => o == Some(Equal),
+                (CompareOp::Ne, o) => o != Some(Equal),
+                (CompareOp::Lt, o) => o == Some(Less),
+                (CompareOp::Le, o) => matches!(o, Some(Less | Equal)),
+                (CompareOp::Gt, o) => o == Some(Greater),
+                (CompareOp::Ge, o) => matches!(o, Some(Greater | Equal)),
+            };
+            Value::Int(result as i64)
+        };
+        match (l, r) {
+            (Value::Int(a), Value::Int(b)) => match op {
+                BinaryOp::Add => Value::Int(a.wrapping_add(b)),
+                BinaryOp::Sub => Value::Int(a.wrapping_sub(b)),
+                BinaryOp::Mul => Value::Int(a.wrapping_mul(b)),
+                BinaryOp::Div | BinaryOp::Rem if b == 0 => {
+                    self.memory.set_bit(ARITHMETIC_ERROR, true);
+                    Value::Int(0)
+                }
+                BinaryOp::Div => Value::Int(a.wrapping_div(b)),
+                BinaryOp::Rem => Value::Int(a.wrapping_rem(b)),
+                BinaryOp::And => Value::Int(a & b),
+                BinaryOp::Or => Value::Int(a | b),
+                BinaryOp::Xor => Value::Int(a ^ b),
+                BinaryOp::Compare(c) => compare(c, Some(a.cmp(&b))),
+            },
+            (l, r) => {
+                let (a, b) = (l.float(), r.float());
+                match op {
+                    BinaryOp::Add => Value::Float(a + b),
+                    BinaryOp::Sub => Value::Float(a - b),
+                    BinaryOp::Mul => Value::Float(a * b),
+                    BinaryOp::Div => Value::Float(a / b),
+                    BinaryOp::Rem => Value::Float(a % b),
+                    BinaryOp::And => Value::Int(l.int() & r.int()),
+                    BinaryOp::Or => Value::Int(l.int() | r.int()),
+                    BinaryOp::Xor => Value::Int(l.int() ^ r.int()),
+                    BinaryOp::Compare(c) => compare(c, a.partial_cmp(&b)),
+                }
+            }
+        }
+    }
+}
+
+/// The functions of operation blocks, e.g. `ABS(%MW0)`
+fn function(name: &str, args: &[Value]) -> Result<Value> {
+    let x = match args {
+        [x] => *x,
+        _ => bail!("{} with {} arguments is not supported", name, args.len()),
+    };
+    Ok(match name {
+        "ABS" => match x {
+            Value::Int(i) => Value::Int(i.wrapping_abs()),
+            Value::Float(f) => Value::Float(f.abs()),
+        },
+        "SQRT" => Value::Float(x.float().sqrt()),
+        "LN" => Value::Float(x.float().ln()),
+        "LOG" => Value::Float(x.float().log10()),
+        "EXP" => Value::Float(x.float().exp()),
+        "SIN" => Value::Float(x.float().sin()),
+        "COS" => Value::Float(x.float().cos()),
+        "TAN" => Value::Float(x.float().tan()),
+        "TRUNC" => Value::Int(x.float().trunc() as i64),
+        "INT_TO_REAL" | "DINT_TO_REAL" => Value::Float(x.float()),
+        "REAL_TO_INT" | "REAL_TO_DINT" => Value::Int(x.float().round() as i64),
+        _ => bail!("Function {} is not supported", name),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+    use crate::project::{Pou, Rung};
+
+    #[test]
+    fn test_scan() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let mut sim = Simulator::new(&project).unwrap();
+        sim.memory.set_bit("%I0.4", true);
+        sim.memory.set_bit("%I0.5", true);
+        sim.memory.set_bit("%I0.2", true);
+        sim.memory.set("%SW18", Value::Int(0x7fff));
+        sim.scan().unwrap();
+        assert!(sim.memory.bit("%M10"));
+        assert!(sim.memory.bit(FIRST_SCAN));
+        assert_eq!(sim.memory.get("%QWM2"), Value::Int(0b110100));
+        assert_eq!(sim.memory.get("%QWM5"), Value::Int(3));
+        // %Q0.8 is false, so bit 15 is set and the word is negative
+        assert_eq!(sim.memory.get("%QWM6"), Value::Int(0x3fff - 0x8000));
+
+        sim.memory.set_bit("%I0.5", false);
+        sim.scan().unwrap();
+        assert!(!sim.memory.bit("%M10"));
+        assert!(!sim.memory.bit(FIRST_SCAN));
+        assert_eq!(sim.scans(), 2);
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let mut sim = Simulator::new(&project).unwrap();
+        assert_eq!(sim.cycle, DEFAULT_CYCLE);
+        sim.scan().unwrap();
+        assert!(!sim.memory.bit("%Q0.8"));
+        // A toggled heartbeat bit starts the 2.5 s off-delay of %TM0
+        sim.memory.set_bit("%IWM0:X8", true);
+        sim.scan().unwrap();
+        assert!(sim.memory.bit("%TM0.Q") && sim.memory.bit("%Q0.8"));
+        sim.run_for(Duration::from_millis(2400)).unwrap();
+        assert!(sim.memory.bit("%Q0.8"));
+        assert_eq!(sim.memory.get("%TM0.V"), Value::Int(23));
+        sim.run_for(Duration::from_millis(200)).unwrap();
+        assert!(!sim.memory.bit("%Q0.8"));
+        assert_eq!(sim.now(), Duration::from_millis(2620));
+    }
+
+    #[test]
+    fn test_grafcet() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let mut sim = Simulator::new(&project).unwrap();
+        let steps = |sim: &Simulator| -> Vec<u32> {
+            sim.active_steps()
+                .filter_map(|(_, step)| step.step_number)
+                .collect()
+        };
+        assert_eq!(steps(&sim), [1]);
+        // Both stow locks released, "Lock and unlock" to "Home"
+        sim.memory.set_bit("%I0.2", true);
+        sim.memory.set_bit("%I0.3", true);
+        sim.memory.set_bit("%I0.6", true);
+        sim.scan().unwrap();
+        assert_eq!(steps(&sim), [2]);
+        assert!(sim.memory.bit("%X2") && !sim.memory.bit("%X1"));
+        sim.scan().unwrap();
+        assert_eq!(steps(&sim), [2]);
+
+        // The OR fork after "Home", back to "Lock and unlock" when a lock is no longer
+        // released
+        sim.memory.set_bit("%I0.2", false);
+        sim.scan().unwrap();
+        assert_eq!(steps(&sim), [1]);
+        sim.memory.set_bit("%I0.2", true);
+        sim.scan().unwrap();
+        // Or on to "Below horizon, inch ok" when the home switch is released
+        sim.memory.set_bit("%I0.6", false);
+        sim.scan().unwrap();
+        assert_eq!(steps(&sim), [3]);
+    }
+
+    #[test]
+    fn test_logic() {
+        let body = Pou {
+            kind: PouKind::FunctionBlock,
+            name: "NOT_BOTH".to_string(),
+            rungs: vec![Rung::from_il(&["LD %A", "ANDN %B", "ST %OK"])],
+            fields: [
+                ("Inputs/UserDefineFunctionBlockInput[0]/Name", "A"),
+                ("Inputs/UserDefineFunctionBlockInput[0]/Index", "0"),
+                ("Inputs/UserDefineFunctionBlockInput[1]/Name", "B"),
+                ("Inputs/UserDefineFunctionBlockInput[1]/Index", "1"),
+                ("Outputs/UserDefineFunctionBlockOutput/Name", "OK"),
+                ("Outputs/UserDefineFunctionBlockOutput/Index", "0"),
+            ]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+            ..Default::default()
+        };
+        let program = Pou {
+            name: "Main".to_string(),
+            section: Some(1),
+            rungs: vec![
+                Rung::from_il(&[
+                    "LD %I0.0",
+                    "MPS",
+                    "AND %I0.1",
+                    "ST %Q0.0",
+                    "MRD",
+                    "AND( %I0.2",
+                    "ORN %I0.3",
+                    ")",
+                    "ST %Q0.1",
+                    "MPP",
+                    "STN %Q0.2",
+                ]),
+                Rung::from_il(&[
+                    "LDR %I0.0",
+                    "S %M0",
+                    "LD %I0.0",
+                    "JMPC %L1",
+                    "LD 1",
+                    "[ %MW0 := %MW0 + 1 ]",
+                ]),
+                Rung::from_il(&["%L1:", "LD [ %MW0 >= 2 ]", "ST %Q0.3"]),
+                Rung::from_il(&[
+                    "LD 1",
+                    "BLK_UDFB 7",
+                    "LD %I0.0",
+                    "ST %UDF0.PARAMW0:X0",
+                    "LD %I0.1",
+                    "ST %UDF0.PARAMW0:X1",
+                    "LD 1",
+                    "ST %UDF0.PARAMW0:X8",
+                    "UDF0",
+                    "LD %UDF0.RETW0:X0",
+                    "ST %Q0.4",
+                    "END_BLK_UDFB 7",
+                ]),
+            ],
+            ..Default::default()
+        };
+        let project = Project {
+            pous: vec![program, body],
+            ..Default::default()
+        };
+        let mut sim = Simulator::new(&project).unwrap();
+        sim.scan().unwrap();
+        sim.scan().unwrap();
+        let outputs = |memory: &Memory| -> Vec<bool> {
+            (0..5).map(|q| memory.bit(&format!("%Q0.{}", q))).collect()
+        };
+        assert_eq!(outputs(&sim.memory), [false, false, true, true, false]);
+        assert_eq!(sim.memory.get("%MW0"), Value::Int(2));
+
+        sim.memory.set_bit("%I0.0", true);
+        sim.memory.set_bit("%I0.3", true);
+        sim.scan().unwrap();
+        assert_eq!(outputs(&sim.memory), [false, false, false, true, true]);
+        assert!(sim.memory.bit("%M0"));
+        assert_eq!(sim.memory.get("%MW0"), Value::Int(2));
+    }
+
+    #[test]
+    fn test_subroutine_call() {
+        let program = Pou::program(
+            "Main",
+            vec![Rung::from_il(&["LD %I0.1", "CALLC %SR1", "ST %Q0.0"])],
+        );
+        let project = Project {
+            pous: vec![program],
+            ..Default::default()
+        };
+        let mut sim = Simulator::new(&project).unwrap();
+        sim.scan().unwrap();
+        sim.memory.set_bit("%I0.1", true);
+        sim.scan().unwrap();
+        // The call is skipped, the rest of the rung still runs
+        assert!(sim.memory.bit("%Q0.0"));
+        assert_eq!(sim.coverage.skipped_calls, [((0, 0, 1), 1)].into());
+        assert_eq!(
+            sim.coverage.report(&project).uncovered(),
+            ["Main line 2 CALLC %SR1: not simulated, skipped 1 times"]
+        );
+    }
+}
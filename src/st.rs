@@ -0,0 +1,771 @@
+//! Export of the IL as IEC 61131-3 Structured Text
+//!
+//! Every rung is converted to equations, which become assignments and `IF` statements with
+//! symbol names as variable names. Timers, counters and user function blocks become
+//! function block instances, which are called where the IL updates them, and `LDR` and
+//! `LDF` edges become `R_TRIG` and `F_TRIG` instances. The symbol tables are declared as
+//! global variables. Anything which has no direct equivalent, like jumps, bit strings and
+//! system bits, is marked with an `UNTRANSLATED` comment, so it can be found and migrated by
+//! hand.
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::equation::{equations, BoolExpr, Effect, Equation};
+use crate::il::{parse_rung, BinaryOp, Expr, Instruction, Operand, Statement, UnaryOp};
+use crate::lint::PREDEFINED_TABLES;
+use crate::project::{ObjectEntry, Pou, PouKind, Project, Rung};
+use crate::sim::udfb_scope;
+use crate::timers::base_millis;
+
+/// Marker of the constructs which aren't translated
+pub const UNTRANSLATED: &str = "UNTRANSLATED";
+
+/// A valid identifier, e.g. `Validate_inputs` for `Validate inputs` or `I0_4` for `%I0.4`
+pub(crate) fn identifier(name: &str) -> String {
+    let mut id: String = name
+        .trim_start_matches('%')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if id.starts_with(|c: char| c.is_ascii_digit()) {
+        id.insert(0, '_');
+    }
+    id
+}
+
+/// Text for inside a comment, which can't contain its end
+pub(crate) fn comment(text: &str) -> String {
+    text.replace("*)", "* )")
+}
+
+/// The IEC type of an object, with the initial value of a member for the preset of a timer
+/// or counter, e.g. `TOF` with `PT` `T#2500MS`
+pub(crate) struct ObjectType {
+    pub name: String,
+    pub preset: Option<(&'static str, String)>,
+}
+
+impl ObjectType {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            preset: None,
+        }
+    }
+
+    /// The type as written in a declaration, e.g. `TOF := (PT := T#2500MS)`
+    fn declaration(&self) -> String {
+        match &self.preset {
+            Some((member, value)) => format!("{} := ({} := {})", self.name, member, value),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// The type of an object, by the prefix of its address, or `None` if it has no equivalent
+pub(crate) fn object_type(object: &ObjectEntry) -> Option<ObjectType> {
+    let address = object.address.trim_start_matches('%');
+    let prefix: String = address
+        .chars()
+        .take_while(char::is_ascii_alphabetic)
+        .collect();
+    Some(match prefix.as_str() {
+        "I" | "Q" | "M" | "X" => ObjectType::new("BOOL"),
+        "IW" | "QW" | "MW" | "KW" | "IWM" | "QWM" => ObjectType::new("INT"),
+        "MD" | "KD" => ObjectType::new("DINT"),
+        "MF" | "KF" => ObjectType::new("REAL"),
+        "TM" => {
+            let kind = object.fields.get("Type").map_or("TON", String::as_str);
+            let preset = object
+                .fields
+                .get("Preset")
+                .and_then(|p| p.parse::<u64>().ok());
+            let base = object.fields.get("Base").and_then(|b| base_millis(b));
+            ObjectType {
+                preset: preset
+                    .zip(base)
+                    .map(|(preset, base)| ("PT", format!("T#{}MS", preset * base))),
+                ..ObjectType::new(kind)
+            }
+        }
+        "C" => ObjectType {
+            preset: object.fields.get("Preset").map(|p| ("PV", p.clone())),
+            ..ObjectType::new("CTUD")
+        },
+        _ => return None,
+    })
+}
+
+/// The symbol table entries which are declared as global variables: the ones with a symbol
+/// which aren't system objects, bit strings or the pins of a timer or counter
+pub(crate) fn declared_objects(project: &Project) -> impl Iterator<Item = &ObjectEntry> {
+    project.objects.iter().filter(|object| {
+        // Symbols of the pins of a timer or counter name the pin, not a variable
+        let pin = object
+            .address
+            .rsplit_once('.')
+            .is_some_and(|(_, pin)| pin.starts_with(|c: char| c.is_ascii_alphabetic()));
+        !object.symbol.is_empty()
+            && !pin
+            && !object.address.contains(':')
+            && !PREDEFINED_TABLES.contains(&object.table.as_str())
+    })
+}
+
+/// The inputs, outputs and local variables of the user function block with `UDF` number
+/// `n`, by address, as the variable section, name and type
+pub(crate) fn udfb_variables(
+    pou: &Pou,
+    n: usize,
+) -> Vec<(&'static str, String, &'static str, String)> {
+    let mut names: Vec<(String, String)> = udfb_scope(&pou.fields, n)
+        .into_iter()
+        .map(|(name, address)| (address, identifier(&name)))
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|(address, name)| {
+            let (section, kind) = if address.contains(".PARAMW") {
+                ("VAR_INPUT", "BOOL")
+            } else if address.contains(".RETW") {
+                ("VAR_OUTPUT", "BOOL")
+            } else {
+                ("VAR", "INT")
+            };
+            (section, name, kind, address)
+        })
+        .collect()
+}
+
+/// `base`, or the first of `base_2`, `base_3`, ... which isn't in `names` yet, added to them
+pub(crate) fn unique_name(names: &mut HashSet<String>, base: &str) -> String {
+    let mut name = base.to_string();
+    let mut n = 1;
+    while !names.insert(name.clone()) {
+        n += 1;
+        name = format!("{}_{}", base, n);
+    }
+    name
+}
+
+/// Pin of an IEC function block for a timer or counter pin, `None` if it has no equivalent
+fn block_pin(table: &str, pin: &str) -> Option<&'static str> {
+    Some(match (table, pin) {
+        ("Timers", "IN") => "IN",
+        ("Timers", "Q") => "Q",
+        ("Counters", "R") => "RESET",
+        ("Counters", "S") => "LOAD",
+        ("Counters", "CU") => "CU",
+        ("Counters", "CD") => "CD",
+        ("Counters", "P") => "PV",
+        ("Counters", "V") => "CV",
+        ("Counters", "D") => "QU",
+        _ => return None,
+    })
+}
+
+/// A user function block type, by its `UDF` number
+struct Udfb<'a> {
+    pou: &'a Pou,
+    /// Names of the inputs, outputs and local variables by their address, e.g.
+    /// `%UDF2.PARAMW0:X0` is `RUN_POL`
+    names: HashMap<String, String>,
+}
+
+struct Translator<'a> {
+    project: &'a Project,
+    objects: HashMap<&'a str, &'a ObjectEntry>,
+    udfbs: Vec<Udfb<'a>>,
+    /// The body of the user function block being translated
+    body: Option<usize>,
+    /// Instance names by `UDF` number, in the rung being translated
+    instances: HashMap<usize, String>,
+    /// User function block instances and their type, which need a declaration
+    declared: BTreeMap<String, String>,
+    /// Edge detection instances of the POU, with their type
+    triggers: Vec<(String, &'static str)>,
+    /// Statements which must come before the one being translated, e.g. trigger calls
+    before: Vec<String>,
+    /// Constructs which weren't translated in the statement
+    problems: BTreeSet<String>,
+    /// Addresses without a symbol, which are declared by their identifier
+    used: BTreeMap<String, String>,
+    /// Names of the POUs translated so far
+    pou_names: HashSet<String>,
+}
+
+impl<'a> Translator<'a> {
+    fn new(project: &'a Project) -> Self {
+        let udfbs = project
+            .pous
+            .iter()
+            .filter(|p| p.kind == PouKind::FunctionBlock)
+            .enumerate()
+            .map(|(n, pou)| Udfb {
+                pou,
+                names: udfb_scope(&pou.fields, n)
+                    .into_iter()
+                    .map(|(name, address)| (address, identifier(&name)))
+                    .collect(),
+            })
+            .collect();
+        Self {
+            project,
+            objects: project
+                .objects
+                .iter()
+                .map(|o| (o.address.as_str(), o))
+                .collect(),
+            udfbs,
+            body: None,
+            instances: HashMap::new(),
+            declared: BTreeMap::new(),
+            triggers: Vec::new(),
+            before: Vec::new(),
+            problems: BTreeSet::new(),
+            used: BTreeMap::new(),
+            pou_names: HashSet::new(),
+        }
+    }
+
+    fn untranslated(&mut self, what: String) {
+        self.problems.insert(what);
+    }
+
+    /// The variable name of an address
+    fn name(&mut self, address: &str) -> String {
+        // Inputs, outputs and locals of the function block body
+        if let Some(body) = self.body {
+            let base = address.split(':').next().unwrap_or(address);
+            let local = self.udfbs[body]
+                .names
+                .values()
+                .any(|n| identifier(base) == *n);
+            if local {
+                return match address.split_once(":X") {
+                    Some((base, bit)) => format!("{}.{}", identifier(base), bit),
+                    None => identifier(address),
+                };
+            }
+        }
+        // Inputs and outputs of a user function block call, e.g. `%UDF2.PARAMW0:X0`
+        if let Some(rest) = address.strip_prefix("%UDF") {
+            let number: String = rest.chars().take_while(char::is_ascii_digit).collect();
+            let n: usize = number.parse().unwrap_or(usize::MAX);
+            let pin = self
+                .udfbs
+                .get(n)
+                .and_then(|u| u.names.get(address))
+                .cloned();
+            return match (pin, self.instances.get(&n)) {
+                (Some(pin), Some(instance)) => format!("{}.{}", instance, pin),
+                _ => {
+                    self.untranslated(format!("function block memory {}", address));
+                    identifier(address)
+                }
+            };
+        }
+        if let Some((word, bit)) = address.split_once(":X") {
+            let bit = bit.trim_start_matches('0');
+            return format!(
+                "{}.{}",
+                self.name(word),
+                if bit.is_empty() { "0" } else { bit }
+            );
+        }
+        if address.contains(':') {
+            self.untranslated(format!("bit string {}", address));
+            return identifier(address);
+        }
+        if let Some((base, pin)) = address.rsplit_once('.') {
+            if let Some(object) = self.objects.get(base).copied() {
+                let base = self.name(base);
+                return match block_pin(&object.table, pin) {
+                    Some(pin) => format!("{}.{}", base, pin),
+                    None => {
+                        self.untranslated(format!("function block pin {}", address));
+                        format!("{}.{}", base, pin)
+                    }
+                };
+            }
+        }
+        match self.objects.get(address).copied() {
+            Some(object) if !object.symbol.is_empty() => {
+                if PREDEFINED_TABLES.contains(&object.table.as_str()) {
+                    self.untranslated(format!("system object {}", address));
+                }
+                identifier(&object.symbol)
+            }
+            _ => {
+                let name = identifier(address);
+                self.used.insert(name.clone(), address.to_string());
+                name
+            }
+        }
+    }
+
+    fn condition(&mut self, expr: &BoolExpr) -> String {
+        // Parenthesise operands of lower precedence: OR < XOR < AND < NOT
+        let level = |e: &BoolExpr| match e {
+            BoolExpr::Or(_) => 0,
+            BoolExpr::Xor(..) => 1,
+            BoolExpr::And(_) => 2,
+            _ => 3,
+        };
+        let inner = |t: &mut Self, e: &BoolExpr, min: usize| {
+            if level(e) < min {
+                format!("({})", t.condition(e))
+            } else {
+                t.condition(e)
+            }
+        };
+        match expr {
+            BoolExpr::Const(true) => "TRUE".to_string(),
+            BoolExpr::Const(false) => "FALSE".to_string(),
+            BoolExpr::Var(v) => self.name(v),
+            BoolExpr::Rising(v) | BoolExpr::Falling(v) => {
+                let kind = match expr {
+                    BoolExpr::Rising(_) => "R_TRIG",
+                    _ => "F_TRIG",
+                };
+                let trigger = format!("{}_{}", kind, self.triggers.len() + 1);
+                self.triggers.push((trigger.clone(), kind));
+                let clk = self.name(v);
+                self.before.push(format!("{}(CLK := {});", trigger, clk));
+                format!("{}.Q", trigger)
+            }
+            BoolExpr::Compare(e) => format!("({})", self.expr(e)),
+            BoolExpr::Not(e) => format!("NOT {}", inner(self, e, 3)),
+            BoolExpr::And(es) | BoolExpr::Or(es) => {
+                let (op, min) = match expr {
+                    BoolExpr::And(_) => (" AND ", 3),
+                    _ => (" OR ", 1),
+                };
+                let parts: Vec<String> = es.iter().map(|e| inner(self, e, min)).collect();
+                parts.join(op)
+            }
+            BoolExpr::Xor(a, b) => format!("{} XOR {}", inner(self, a, 2), inner(self, b, 2)),
+        }
+    }
+
+    fn operand(&mut self, operand: &Operand) -> String {
+        match operand {
+            Operand::Address(a) => self.name(a),
+            Operand::Name(n) => identifier(n),
+            Operand::Integer(i) => i.to_string(),
+            Operand::Float(f) => format!("{:?}", f),
+            Operand::Compare(e) => format!("({})", self.expr(e)),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        let side = |t: &mut Self, e: &Expr| match e {
+            Expr::Binary(..) => format!("({})", t.expr(e)),
+            _ => t.expr(e),
+        };
+        match expr {
+            Expr::Operand(o) => self.operand(o),
+            Expr::Unary(UnaryOp::Neg, e) => format!("-{}", side(self, e)),
+            Expr::Unary(UnaryOp::Not, e) => format!("NOT {}", side(self, e)),
+            Expr::Binary(l, op, r) => {
+                let op = match op {
+                    BinaryOp::Rem => "MOD".to_string(),
+                    op => op.to_string(),
+                };
+                format!("{} {} {}", side(self, l), op, side(self, r))
+            }
+            Expr::Call(f, args) => {
+                let args: Vec<String> = args.iter().map(|a| self.expr(a)).collect();
+                format!("{}({})", f, args.join(", "))
+            }
+        }
+    }
+
+    /// Copies of the instance memory around a user function block call, which an IEC
+    /// function block instance keeps by itself, e.g. `[ %UDF2.RETW0 := %UDF70.RETW0 ]`
+    fn is_instance_copy(dest: &Operand, expr: &Expr) -> bool {
+        fn memory(address: &str) -> bool {
+            address.starts_with("%UDF") && !address.contains(':')
+        }
+        fn uses_memory(expr: &Expr) -> bool {
+            match expr {
+                Expr::Operand(Operand::Address(a)) => memory(a),
+                Expr::Unary(_, e) => uses_memory(e),
+                Expr::Binary(l, _, r) => uses_memory(l) || uses_memory(r),
+                _ => false,
+            }
+        }
+        matches!(dest, Operand::Address(a) if memory(a)) || uses_memory(expr)
+    }
+
+    /// The statements of an equation, `None` for editor generated ones without equivalent
+    fn equation(&mut self, equation: &Equation, result: &str) -> Option<Vec<String>> {
+        let when = |t: &mut Self, statement: String| {
+            let condition = t.condition(&equation.condition);
+            match equation.condition {
+                BoolExpr::Const(true) => vec![statement],
+                _ => vec![
+                    format!("IF {} THEN", condition),
+                    format!("    {}", statement),
+                    "END_IF;".to_string(),
+                ],
+            }
+        };
+        Some(match &equation.effect {
+            // The enable inputs of user function blocks, which have no name
+            Effect::Assign(bit)
+                if bit.contains(".PARAMW")
+                    && !self.udfbs.iter().any(|u| u.names.contains_key(bit)) =>
+            {
+                return None
+            }
+            Effect::Assign(bit) => {
+                let bit = self.name(bit);
+                vec![format!(
+                    "{} := {};",
+                    bit,
+                    self.condition(&equation.condition)
+                )]
+            }
+            Effect::Set(bit) => {
+                let bit = self.name(bit);
+                when(self, format!("{} := TRUE;", bit))
+            }
+            Effect::Reset(bit) => {
+                let bit = self.name(bit);
+                when(self, format!("{} := FALSE;", bit))
+            }
+            Effect::Operation(dest, expr) if Self::is_instance_copy(dest, expr) => return None,
+            Effect::Operation(dest, expr) => {
+                let statement = format!("{} := {};", self.operand(dest), self.expr(expr));
+                when(self, statement)
+            }
+            Effect::Call(udf) => {
+                let n = udf.trim_start_matches("UDF").parse().unwrap_or(usize::MAX);
+                let instance = self.instances.get(&n).cloned().unwrap_or_else(|| {
+                    self.untranslated(format!("call of {}", udf));
+                    udf.clone()
+                });
+                when(self, format!("{}();", instance))
+            }
+            Effect::Transition => {
+                vec![format!(
+                    "{} := {};",
+                    result,
+                    self.condition(&equation.condition)
+                )]
+            }
+        })
+    }
+
+    /// Instance names of the user function block calls of a rung, by the ladder element
+    /// of the block, or its `BLK_UDFB` number
+    fn find_instances(&mut self, rung: &Rung, statements: &[Statement]) {
+        self.instances.clear();
+        let mut blk = None;
+        for statement in statements {
+            match &statement.instruction {
+                Instruction::BlkUdfb(n) => blk = Some(*n),
+                Instruction::CallUdf(udf) => {
+                    let n: usize = udf.trim_start_matches("UDF").parse().unwrap_or(usize::MAX);
+                    let udfb = match self.udfbs.get(n) {
+                        Some(udfb) => udfb,
+                        None => continue,
+                    };
+                    let ladder = rung.ladder.iter().find(|l| {
+                        l.element_type == "UserDefineFunctionBlock"
+                            && l.element_name == udfb.pou.name
+                    });
+                    let instance = match (ladder, &blk) {
+                        (Some(l), _) => identifier(&l.descriptor),
+                        (None, Some(blk)) => format!("{}_{}", udfb.pou.name, blk),
+                        (None, None) => udfb.pou.name.clone(),
+                    };
+                    self.declared
+                        .insert(instance.clone(), udfb.pou.name.clone());
+                    self.instances.insert(n, instance);
+                }
+                Instruction::EndBlkUdfb(_) => blk = None,
+                _ => {}
+            }
+        }
+    }
+
+    /// The statements of a rung, with `result` the variable of a transition condition
+    fn rung(&mut self, rung: &Rung, result: &str, out: &mut Vec<String>) {
+        if !rung.name.is_empty() || !rung.main_comment.is_empty() {
+            let text = [rung.name.trim(), rung.main_comment.trim()]
+                .iter()
+                .filter(|t| !t.is_empty())
+                .copied()
+                .collect::<Vec<_>>()
+                .join(": ");
+            out.push(format!("(* {} *)", comment(&text)));
+        }
+        let statements = parse_rung(rung).ok();
+        let translated = statements.as_ref().and_then(|s| Some((s, equations(s)?)));
+        let (statements, equations) = match translated {
+            Some(translated) => translated,
+            None => {
+                let reason = match statements {
+                    Some(_) => "jumps, returns or subroutine calls",
+                    None => "IL which can't be parsed",
+                };
+                out.push(format!("(* {}: {}", UNTRANSLATED, reason));
+                for line in rung.il_text() {
+                    out.push(format!("    {}", comment(line)));
+                }
+                out.push("*)".to_string());
+                return;
+            }
+        };
+        self.find_instances(rung, statements);
+        // Timers and counters are called where the IL updates them
+        let mut calls = Vec::new();
+        let mut block = None;
+        for statement in statements {
+            match &statement.instruction {
+                Instruction::Blk(instance) => block = Some((instance.clone(), false)),
+                Instruction::OutBlk | Instruction::EndBlk => {
+                    if let Some((instance, false)) = &block {
+                        calls.push((statement.span.line, instance.clone()));
+                    }
+                    block = match statement.instruction {
+                        Instruction::OutBlk => block.map(|(i, _)| (i, true)),
+                        _ => None,
+                    };
+                }
+                _ => {}
+            }
+        }
+        let mut calls = calls.into_iter().peekable();
+        for equation in &equations {
+            while let Some((_, instance)) = calls.next_if(|(line, _)| *line < equation.span.line) {
+                let call = format!("{}();", self.name(&instance));
+                self.emit(vec![call], out);
+            }
+            if let Some(statements) = self.equation(equation, result) {
+                self.emit(statements, out);
+            }
+        }
+        for (_, instance) in calls {
+            let call = format!("{}();", self.name(&instance));
+            self.emit(vec![call], out);
+        }
+    }
+
+    /// Add the statements, after any trigger calls and with the problems marked
+    fn emit(&mut self, statements: Vec<String>, out: &mut Vec<String>) {
+        for problem in std::mem::take(&mut self.problems) {
+            out.push(format!("(* {}: {} *)", UNTRANSLATED, comment(&problem)));
+        }
+        out.append(&mut self.before);
+        out.extend(statements);
+    }
+
+    /// The declaration and body of a POU
+    fn pou(&mut self, p: usize) -> String {
+        let pou = &self.project.pous[p];
+        self.triggers.clear();
+        self.body = None;
+        // Steps and transitions are prefixed with their chart, e.g. `Chart_step_2_Home`, and
+        // transitions of a chart can have the same name
+        let mut base = pou.name.clone();
+        if let (PouKind::Step, Some(n)) = (pou.kind, pou.step_number) {
+            base = format!("{} {}", n, base);
+        }
+        if let Some(chart) = &pou.chart {
+            base = format!("{} {} {}", chart, pou.kind, base);
+        }
+        let base = identifier(&base);
+        let name = unique_name(&mut self.pou_names, &base);
+        let mut text = String::new();
+        let mut header = Vec::new();
+        let mut vars: Vec<(&str, Vec<String>)> = Vec::new();
+        let (keyword, end) = match pou.kind {
+            PouKind::Program => ("PROGRAM", "END_PROGRAM"),
+            PouKind::Step => {
+                header.push(format!(
+                    "(* {}: Grafcet chart {}, this is the action of the step *)",
+                    UNTRANSLATED,
+                    comment(pou.chart.as_deref().unwrap_or_default())
+                ));
+                ("PROGRAM", "END_PROGRAM")
+            }
+            PouKind::Transition => ("FUNCTION", "END_FUNCTION"),
+            PouKind::FunctionBlock => {
+                let n = self.udfbs.iter().position(|u| std::ptr::eq(u.pou, pou));
+                self.body = n;
+                let mut sections: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+                for (section, name, kind, address) in udfb_variables(pou, n.unwrap_or_default()) {
+                    sections
+                        .entry(section)
+                        .or_default()
+                        .push(format!("{} : {}; (* {} *)", name, kind, address));
+                }
+                for section in ["VAR_INPUT", "VAR_OUTPUT", "VAR"] {
+                    if let Some(lines) = sections.remove(section) {
+                        vars.push((section, lines));
+                    }
+                }
+                ("FUNCTION_BLOCK", "END_FUNCTION_BLOCK")
+            }
+            PouKind::Function => {
+                header.push(format!("(* {}: user function *)", UNTRANSLATED));
+                ("FUNCTION", "END_FUNCTION")
+            }
+        };
+        let declared_before = self.declared.clone();
+        let mut body = Vec::new();
+        for rung in &pou.rungs {
+            self.rung(rung, &name, &mut body);
+        }
+        let mut locals = Vec::new();
+        if pou.kind == PouKind::FunctionBlock {
+            // Nested instances belong to the function block
+            for (instance, kind) in self.declared.clone() {
+                if !declared_before.contains_key(&instance) {
+                    self.declared.remove(&instance);
+                    locals.push(format!("{} : {};", instance, kind));
+                }
+            }
+        }
+        locals.extend(
+            self.triggers
+                .iter()
+                .map(|(name, kind)| format!("{} : {};", name, kind)),
+        );
+        if !locals.is_empty() {
+            match vars.iter_mut().find(|(s, _)| *s == "VAR") {
+                Some((_, lines)) => lines.extend(locals),
+                None => vars.push(("VAR", locals)),
+            }
+        }
+
+        for line in &header {
+            let _ = writeln!(text, "{}", line);
+        }
+        if !pou.comment.is_empty() {
+            let _ = writeln!(text, "(* {} *)", comment(&pou.comment));
+        }
+        match pou.kind {
+            PouKind::Transition => {
+                let _ = writeln!(text, "{} {} : BOOL", keyword, name);
+            }
+            _ => {
+                let _ = writeln!(text, "{} {}", keyword, name);
+            }
+        }
+        for (section, lines) in vars {
+            let _ = writeln!(text, "{}", section);
+            for line in lines {
+                let _ = writeln!(text, "    {}", line);
+            }
+            let _ = writeln!(text, "END_VAR");
+        }
+        for line in body {
+            let _ = writeln!(text, "    {}", line);
+        }
+        let _ = writeln!(text, "{}", end);
+        text
+    }
+}
+
+/// Global variables for the symbol table entries, the user function block instances and
+/// the addresses without a symbol
+fn globals(translator: &Translator) -> String {
+    let mut text = String::from("VAR_GLOBAL\n");
+    let project = translator.project;
+    for object in declared_objects(project) {
+        let name = identifier(&object.symbol);
+        let mut note = object.address.clone();
+        if !object.comment.is_empty() {
+            note = format!("{} {}", note, object.comment);
+        }
+        let _ = match object_type(object) {
+            Some(kind) => writeln!(
+                text,
+                "    {} : {}; (* {} *)",
+                name,
+                kind.declaration(),
+                comment(&note)
+            ),
+            None => writeln!(
+                text,
+                "    (* {}: {} {} *)",
+                UNTRANSLATED,
+                name,
+                comment(&note)
+            ),
+        };
+    }
+    for (instance, kind) in &translator.declared {
+        let _ = writeln!(text, "    {} : {};", instance, kind);
+    }
+    for (name, address) in &translator.used {
+        let object = ObjectEntry {
+            address: address.clone(),
+            ..translator
+                .objects
+                .get(address.as_str())
+                .copied()
+                .cloned()
+                .unwrap_or_default()
+        };
+        let _ = match object_type(&object) {
+            Some(kind) => writeln!(
+                text,
+                "    {} : {}; (* {} *)",
+                name,
+                kind.declaration(),
+                address
+            ),
+            None => writeln!(text, "    (* {}: {} *)", UNTRANSLATED, address),
+        };
+    }
+    text.push_str("END_VAR\n");
+    text
+}
+
+/// The POUs of a project as Structured Text, with the global variables first
+pub fn export(project: &Project) -> String {
+    let mut translator = Translator::new(project);
+    let pous: Vec<String> = (0..project.pous.len()).map(|p| translator.pou(p)).collect();
+    let mut text = globals(&translator);
+    for pou in pous {
+        text.push('\n');
+        text.push_str(&pou);
+    }
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let st = export(&project);
+        let expected = [
+            "    COMMAND_HEARTBEAT : TOF := (PT := T#2500MS); (* %TM0 *)\n",
+            "    TRACK_DRIVE0 : TRACK_DRIVE;\n",
+            "PROGRAM Validate_inputs\n    IO_INVALID := FALSE;\n    (* Flaps *)\n    IF FLAPS_IN AND FLAPS_OUT THEN\n        IO_INVALID := TRUE;\n    END_IF;\n",
+            "    (* UNTRANSLATED: bit string %Q0.0:16 *)\n",
+            "FUNCTION_BLOCK POL_DRIVE\nVAR_INPUT\n    RUN_POL : BOOL; (* %UDF2.PARAMW0:X0 *)\n",
+            "    CHANGED_CMD.0 := MW_CMD.11 XOR OUT_RUN_POL OR ",
+            "    COMMAND_HEARTBEAT.IN := ",
+            "    COMMAND_HEARTBEAT();\n",
+            "FUNCTION Operational_states_transition_Stow_lock_released : BOOL\n",
+            "FUNCTION Operational_states_transition_Horizon_switch__below_2 : BOOL\n",
+        ];
+        for fragment in &expected {
+            assert!(st.contains(fragment), "{}\n\n{}", fragment, st);
+        }
+        assert!(!st.contains("UDF70"), "{}", st);
+        assert!(!st.contains("POL_DRIVE_TIMER : "), "{}", st);
+    }
+}
@@ -0,0 +1,215 @@
+//! Usage report of the timers and counters of a project
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+use crate::lint::base_address;
+use crate::project::{ObjectEntry, Project};
+use crate::xref::{cmp_address, references, Access};
+
+/// Symbol tables with the timer and counter configuration
+pub const TABLES: &[&str] = &["Timers", "Counters"];
+
+/// Length of a time base like `OneHundredMilliSeconds` in milliseconds
+pub fn base_millis(base: &str) -> Option<u64> {
+    Some(match base {
+        "OneMilliSecond" | "OneMilliSeconds" => 1,
+        "TenMilliSeconds" => 10,
+        "OneHundredMilliSeconds" => 100,
+        "OneSecond" | "OneSeconds" => 1000,
+        "OneMinute" | "OneMinutes" => 60_000,
+        _ => return None,
+    })
+}
+
+/// Milliseconds as seconds without trailing zeros, e.g. `2.5`
+fn format_seconds(millis: u64) -> String {
+    match millis % 1000 {
+        0 => (millis / 1000).to_string(),
+        ms => format!("{}.{:03}", millis / 1000, ms)
+            .trim_end_matches('0')
+            .to_string(),
+    }
+}
+
+/// One timer or counter, with the rungs using it
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockUsage<'a> {
+    pub object: &'a ObjectEntry,
+    /// Rung contexts of the `BLK` structures of the instance
+    pub calls: Vec<String>,
+    /// Rung contexts where each input pin, e.g. `IN`, is set
+    pub inputs: BTreeMap<String, Vec<String>>,
+    /// Rung contexts where each output, e.g. `Q` or `V`, is used
+    pub outputs: BTreeMap<String, Vec<String>>,
+}
+
+impl BlockUsage<'_> {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.object.fields.get(name).map(String::as_str)
+    }
+
+    /// Timer type, `TON`, `TOF` or `TP`
+    pub fn timer_type(&self) -> Option<&str> {
+        self.field("Type")
+    }
+
+    pub fn preset(&self) -> Option<u32> {
+        self.field("Preset")?.parse().ok()
+    }
+
+    /// Effective preset time of a timer in milliseconds
+    pub fn millis(&self) -> Option<u64> {
+        Some(u64::from(self.preset()?) * base_millis(self.field("Base")?)?)
+    }
+
+    /// Problems with the usage, like an unused instance or a timer started in several rungs
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.calls.is_empty() && self.inputs.is_empty() && self.outputs.is_empty() {
+            warnings.push("never used".to_string());
+            return warnings;
+        }
+        if self.calls.is_empty() && self.inputs.is_empty() {
+            warnings.push("outputs are used, but it is never started".to_string());
+        }
+        for (pin, rungs) in &self.inputs {
+            if rungs.len() > 1 {
+                warnings.push(format!("{} is set in {} rungs", pin, rungs.len()));
+            }
+        }
+        if self.calls.len() > 1 {
+            warnings.push(format!("called in {} rungs", self.calls.len()));
+        }
+        if !self.calls.is_empty() && self.outputs.is_empty() {
+            warnings.push("outputs are never used".to_string());
+        }
+        warnings
+    }
+}
+
+impl Display for BlockUsage<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.object.address)?;
+        if !self.object.symbol.is_empty() {
+            write!(f, " [{}]", self.object.symbol)?;
+        }
+        if let Some(t) = self.timer_type() {
+            write!(f, " {}", t)?;
+        }
+        match (self.millis(), self.preset()) {
+            (Some(ms), _) => write!(f, " {} s", format_seconds(ms))?,
+            (None, Some(preset)) => write!(f, " preset {}", preset)?,
+            _ => {}
+        }
+        if !self.object.comment.is_empty() {
+            write!(f, " ({})", self.object.comment)?;
+        }
+        writeln!(f)?;
+        for ctx in &self.calls {
+            writeln!(f, "  called  {}", ctx)?;
+        }
+        for (pin, rungs) in &self.inputs {
+            for ctx in rungs {
+                writeln!(f, "  {:7} {}", pin, ctx)?;
+            }
+        }
+        for (pin, rungs) in &self.outputs {
+            for ctx in rungs {
+                writeln!(f, "  .{:6} {}", pin, ctx)?;
+            }
+        }
+        for warning in self.warnings() {
+            writeln!(f, "  warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every configured timer and counter, sorted by address
+pub fn block_usage(project: &Project) -> Vec<BlockUsage<'_>> {
+    let mut usage: Vec<BlockUsage> = project
+        .objects
+        .iter()
+        .filter(|o| TABLES.contains(&o.table.as_str()))
+        .map(|object| BlockUsage {
+            object,
+            calls: Vec::new(),
+            inputs: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+        })
+        .collect();
+    usage.sort_by(|a, b| cmp_address(&a.object.address, &b.object.address));
+
+    // Unnamed rungs of a POU share their context, so rungs are told apart by index
+    let mut seen = HashSet::new();
+    for r in references(project) {
+        let base = base_address(&r.address);
+        let block = match usage.iter_mut().find(|u| u.object.address == base) {
+            Some(block) => block,
+            None => continue,
+        };
+        if !seen.insert((r.address.clone(), r.access, r.pou, r.rung)) {
+            continue;
+        }
+        let ctx = project.pous[r.pou].rung_context(r.rung).to_string();
+        let pin = r.address[base.len()..].trim_start_matches('.').to_string();
+        let rungs = match r.access {
+            Access::Call => &mut block.calls,
+            Access::Write => block.inputs.entry(pin).or_default(),
+            Access::Read => block.outputs.entry(pin).or_default(),
+        };
+        rungs.push(ctx);
+    }
+    usage
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_block_usage() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let usage = block_usage(&project);
+        assert_eq!(usage.len(), 4);
+        assert_eq!(
+            usage[0].to_string(),
+            "%TM0 [COMMAND_HEARTBEAT] TOF 2.5 s
+  called  Modbus command input > Heartbeat timer
+  IN      Modbus command input > Heartbeat timer
+  .Q      Modbus command input > Heartbeat timer
+"
+        );
+        assert_eq!(usage[1].millis(), Some(5000));
+        assert_eq!(usage[1].outputs["Q"].len(), 3);
+
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let project =
+            Project::from_bytes(orig.replace("BLK  %TM3", "BLK  %TM2").as_bytes()).unwrap();
+        let usage = block_usage(&project);
+        assert_eq!(
+            usage[2].warnings(),
+            ["IN is set in 2 rungs", "called in 2 rungs"]
+        );
+        assert_eq!(
+            usage[3].warnings(),
+            ["outputs are used, but it is never started"]
+        );
+
+        // 3 x 100 ms isn't exact in floating point
+        let project = Project::from_bytes(
+            orig.replacen("<Preset>25</Preset>", "<Preset>3</Preset>", 1)
+                .as_bytes(),
+        )
+        .unwrap();
+        let usage = block_usage(&project);
+        assert_eq!(usage[0].millis(), Some(300));
+        assert!(usage[0]
+            .to_string()
+            .starts_with("%TM0 [COMMAND_HEARTBEAT] TOF 0.3 s\n"));
+        assert_eq!(format_seconds(60_000), "60");
+        assert_eq!(format_seconds(1010), "1.01");
+    }
+}
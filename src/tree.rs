@@ -0,0 +1,102 @@
+//! XML element tree of the whole document, for comparing what the project model skips
+use anyhow::Context;
+use quick_xml::events::Event;
+
+use crate::{CurrentTag, VisitProcessing, VisitResult, XmlNodeVisitor};
+
+/// A plain XML element, with the text of leaf elements unescaped
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Element {
+    pub name: String,
+    pub text: String,
+    pub children: Vec<Element>,
+}
+
+impl Element {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The first child element with the given name
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    /// Text of the first child with the given name, empty if there is no such child
+    pub fn child_text(&self, name: &str) -> &str {
+        self.child(name).map_or("", |c| c.text.as_str())
+    }
+
+    /// Follow a '/' separated path of child names
+    pub fn find(&self, path: &str) -> Option<&Element> {
+        path.split('/')
+            .try_fold(self, |elem, name| elem.child(name))
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+}
+
+/// Collects the whole document into an `Element` tree
+#[derive(Debug, Default)]
+pub struct TreeBuilder {
+    stack: Vec<Element>,
+    root: Option<Element>,
+}
+
+impl TreeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The root element, if the document has been fully processed
+    pub fn into_root(self) -> Option<Element> {
+        self.root
+    }
+
+    fn close(&mut self, mut elem: Element) {
+        if !elem.is_leaf() {
+            // Only indentation between the child elements
+            elem.text.clear();
+        }
+        match self.stack.last_mut() {
+            Some(parent) => parent.children.push(elem),
+            None => self.root = Some(elem),
+        }
+    }
+}
+
+impl XmlNodeVisitor for TreeBuilder {
+    fn visit<'a>(&mut self, event: Event<'a>, _: CurrentTag) -> VisitResult<'a> {
+        match &event {
+            Event::Start(start) => self
+                .stack
+                .push(Element::new(std::str::from_utf8(start.local_name())?)),
+            Event::Empty(empty) => {
+                self.close(Element::new(std::str::from_utf8(empty.local_name())?))
+            }
+            Event::Text(txt) | Event::CData(txt) => {
+                if let Some(elem) = self.stack.last_mut() {
+                    let txt = txt
+                        .unescaped()
+                        .with_context(|| format!("Failed to unescape {:?}", txt))?;
+                    elem.text.push_str(std::str::from_utf8(&txt)?);
+                }
+            }
+            Event::End(_) => {
+                let elem = self.stack.pop().context("Unbalanced end tag")?;
+                self.close(elem);
+            }
+            _ => {}
+        }
+        Ok(VisitProcessing::Continue(event))
+    }
+}
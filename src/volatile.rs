@@ -0,0 +1,142 @@
+//! Project fields which change when a project is just opened and saved
+use quick_xml::events::Event;
+
+use crate::{CurrentTag, VisitProcessing, VisitResult, XmlNodeVisitor};
+
+/// Element names which are ignored unless configured otherwise
+pub const DEFAULT_VOLATILE: &[&str] = &[
+    "FullName",
+    "ProjectVersion",
+    "CurrentCultureName",
+    "IsLadderSelected",
+    "DownloadSettings",
+];
+
+/// A set of element names whose contents (including all child elements) are ignored
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Volatile {
+    names: Vec<String>,
+}
+
+impl Default for Volatile {
+    fn default() -> Self {
+        Self::new(DEFAULT_VOLATILE.iter().copied())
+    }
+}
+
+impl Volatile {
+    pub fn new<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            names: names.into_iter().map(str::to_string).collect(),
+        }
+    }
+
+    /// Parse a comma separated list of element names, an empty list ignores nothing
+    pub fn from_list(list: &str) -> Self {
+        Self::new(list.split(',').map(str::trim).filter(|n| !n.is_empty()))
+    }
+
+    pub fn none() -> Self {
+        Self { names: Vec::new() }
+    }
+
+    pub fn is_volatile(&self, name: &str) -> bool {
+        self.names.iter().any(|n| n == name)
+    }
+
+    /// Check if any element in a settings key like `GlobalProperties/DownloadSettings/ResetMemories` is volatile
+    pub fn is_volatile_key(&self, key: &str) -> bool {
+        key.split('/')
+            .map(|elem| elem.split('[').next().unwrap_or(elem))
+            .any(|elem| self.is_volatile(elem))
+    }
+}
+
+/// Drops volatile elements from the event stream
+#[derive(Debug)]
+pub struct VolatileFilter<'a> {
+    volatile: &'a Volatile,
+    /// Nesting depth inside the element being dropped
+    skipping: usize,
+    /// Indentation which is dropped with the element following it, if that one is volatile
+    indentation: Option<(Event<'static>, CurrentTag)>,
+}
+
+impl<'a> VolatileFilter<'a> {
+    pub fn new(volatile: &'a Volatile) -> Self {
+        Self {
+            volatile,
+            skipping: 0,
+            indentation: None,
+        }
+    }
+
+    fn name_is_volatile(&self, name: &[u8]) -> bool {
+        std::str::from_utf8(name).is_ok_and(|name| self.volatile.is_volatile(name))
+    }
+}
+
+impl XmlNodeVisitor for VolatileFilter<'_> {
+    fn visit<'a>(&mut self, event: Event<'a>, current: CurrentTag) -> VisitResult<'a> {
+        let skip = match &event {
+            Event::Start(start) => {
+                if self.skipping > 0 || self.name_is_volatile(start.local_name()) {
+                    self.skipping += 1;
+                }
+                self.skipping > 0
+            }
+            Event::End(_) if self.skipping > 0 => {
+                self.skipping -= 1;
+                true
+            }
+            Event::Empty(empty) => self.skipping > 0 || self.name_is_volatile(empty.local_name()),
+            Event::Text(txt)
+                if self.skipping == 0
+                    && self.indentation.is_none()
+                    && txt.iter().all(u8::is_ascii_whitespace) =>
+            {
+                self.indentation = Some((event.into_owned(), current));
+                return Ok(VisitProcessing::NextNode);
+            }
+            _ => self.skipping > 0,
+        };
+        if skip {
+            // The indentation of a dropped element goes with it
+            self.indentation = None;
+            return Ok(VisitProcessing::NextNode);
+        }
+        Ok(match self.indentation.take() {
+            Some((held, tag)) => VisitProcessing::Prepend(held, tag, event),
+            None => VisitProcessing::Continue(event),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use quick_xml::Writer;
+
+    use super::*;
+    use crate::process_bytes;
+
+    struct Output(Writer<Vec<u8>>);
+    impl XmlNodeVisitor for Output {
+        fn visit<'a>(&mut self, event: Event<'a>, _: CurrentTag) -> VisitResult<'a> {
+            self.0.write_event(&event)?;
+            Ok(VisitProcessing::Continue(event))
+        }
+    }
+
+    #[test]
+    fn test_volatile_filter() {
+        let xml = b"<P>\n  <FullName>C:\\x</FullName>\n  <Name>n</Name>\n  <DownloadSettings>\n    <A>1</A>\n  </DownloadSettings>\n</P>";
+        let volatile = Volatile::default();
+        let mut filter = VolatileFilter::new(&volatile);
+        let mut output = Output(Writer::new(Vec::new()));
+        process_bytes(xml, &mut [&mut filter, &mut output]).unwrap();
+        assert_eq!(output.0.into_inner(), b"<P>\n  <Name>n</Name>\n</P>");
+
+        assert!(volatile.is_volatile_key("GlobalProperties/DownloadSettings/ResetMemories"));
+        assert!(!Volatile::from_list("").is_volatile_key("FullName"));
+    }
+}
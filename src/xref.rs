@@ -0,0 +1,531 @@
+//! Address references in the IL code, classified as reads, writes or function block calls
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+
+use crate::il::{parse_line, Expr, Instruction, Operand, StoreOp};
+use crate::project::{Project, Rung};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Access {
+    Read,
+    Write,
+    /// Function block invocation, e.g. `BLK %TM0`
+    Call,
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Call => "call",
+        })
+    }
+}
+
+/// How a write assigns the address
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WriteOp {
+    /// `ST`, `STN`, `S` or `R`
+    Store(StoreOp),
+    /// `[ dest := expr ]`
+    Operation,
+    /// An input pin of a function block, e.g. `IN` in a `BLK`
+    Input,
+}
+
+impl Display for WriteOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Store(StoreOp::St) => "ST",
+            Self::Store(StoreOp::Stn) => "STN",
+            Self::Store(StoreOp::Set) => "S",
+            Self::Store(StoreOp::Reset) => "R",
+            Self::Operation => ":=",
+            Self::Input => "an input",
+        })
+    }
+}
+
+/// One use of an address in an IL line
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Reference {
+    pub address: String,
+    pub access: Access,
+    /// The instruction of a write
+    pub write: Option<WriteOp>,
+    /// Index into `Project::pous`
+    pub pou: usize,
+    pub rung: usize,
+    pub line: usize,
+}
+
+fn is_address(word: &str) -> bool {
+    word.starts_with('%')
+}
+
+/// The addresses read by an expression, in order
+fn expr_addresses<'a>(expr: &'a Expr, out: &mut Vec<&'a str>) {
+    match expr {
+        Expr::Operand(operand) => operand_addresses(operand, out),
+        Expr::Unary(_, e) => expr_addresses(e, out),
+        Expr::Binary(a, _, b) => {
+            expr_addresses(a, out);
+            expr_addresses(b, out);
+        }
+        Expr::Call(_, args) => args.iter().for_each(|e| expr_addresses(e, out)),
+    }
+}
+
+fn operand_addresses<'a>(operand: &'a Operand, out: &mut Vec<&'a str>) {
+    match operand {
+        Operand::Address(address) => out.push(address),
+        Operand::Compare(expr) => expr_addresses(expr, out),
+        _ => {}
+    }
+}
+
+/// The addresses used by one rung, as (line index, address, access, how a write assigns it)
+///
+/// Function block pins which are used without an address inside a `BLK` ... `END_BLK`
+/// structure are returned with the block instance, e.g. `%TM0.Q`. Lines which can't be parsed
+/// are reported by `plc-diff lint`, all their addresses are taken as reads.
+pub fn rung_references(rung: &Rung) -> Vec<(usize, Cow<'_, str>, Access, Option<WriteOp>)> {
+    let mut refs = Vec::new();
+    let mut block: Option<String> = None;
+    for (idx, line) in rung.il.iter().enumerate() {
+        let statement = match parse_line(&line.text, idx) {
+            Ok(statement) => statement,
+            Err(_) => {
+                refs.extend(
+                    line.words()
+                        .filter(|w| is_address(w))
+                        .map(|w| (idx, Cow::Borrowed(w), Access::Read, None)),
+                );
+                continue;
+            }
+        };
+        let mut push = |operand: &Operand, write: Option<WriteOp>| {
+            let write = match operand {
+                Operand::Compare(_) => None,
+                _ => write,
+            };
+            let access = match write {
+                Some(_) => Access::Write,
+                None => Access::Read,
+            };
+            match (operand, &block) {
+                (Operand::Name(pin), Some(instance)) => refs.push((
+                    idx,
+                    Cow::Owned(format!("{}.{}", instance, pin)),
+                    access,
+                    write,
+                )),
+                _ => {
+                    let mut addresses = Vec::new();
+                    operand_addresses(operand, &mut addresses);
+                    refs.extend(
+                        addresses
+                            .into_iter()
+                            .map(|a| (idx, Cow::Owned(a.to_string()), access, write)),
+                    );
+                }
+            }
+        };
+        match &statement.instruction {
+            Instruction::Logic { operand, .. }
+            | Instruction::Open {
+                operand: Some(operand),
+                ..
+            } => push(operand, None),
+            Instruction::Store { op, operand } => push(operand, Some(WriteOp::Store(*op))),
+            Instruction::FbInput(pin) => push(&Operand::Name(pin.clone()), Some(WriteOp::Input)),
+            Instruction::Operation { dest, expr } => {
+                push(dest, Some(WriteOp::Operation));
+                let mut addresses = Vec::new();
+                expr_addresses(expr, &mut addresses);
+                refs.extend(
+                    addresses
+                        .into_iter()
+                        .map(|a| (idx, Cow::Owned(a.to_string()), Access::Read, None)),
+                );
+            }
+            Instruction::Blk(instance) => {
+                refs.push((idx, Cow::Owned(instance.clone()), Access::Call, None));
+                block = Some(instance.clone());
+            }
+            Instruction::EndBlk => block = None,
+            // User function call, "UDF4"
+            Instruction::CallUdf(call) => {
+                refs.push((idx, Cow::Owned(format!("%{}", call)), Access::Call, None))
+            }
+            Instruction::Call { target, .. } => {
+                refs.push((idx, Cow::Owned(target.clone()), Access::Call, None))
+            }
+            _ => {}
+        }
+    }
+    refs
+}
+
+/// Every address reference in the project
+pub fn references(project: &Project) -> Vec<Reference> {
+    project
+        .rungs()
+        .flat_map(|(pou, rung, r)| {
+            rung_references(r)
+                .into_iter()
+                .map(move |(line, address, access, write)| Reference {
+                    address: address.into_owned(),
+                    access,
+                    write,
+                    pou,
+                    rung,
+                    line,
+                })
+        })
+        .collect()
+}
+
+/// All references to one address
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XrefEntry {
+    pub address: String,
+    pub symbol: String,
+    pub references: Vec<Reference>,
+}
+
+/// Cross-reference of every used address, sorted by address
+pub fn cross_reference(project: &Project) -> Vec<XrefEntry> {
+    let symbols = project.symbols();
+    let mut by_address: BTreeMap<String, Vec<Reference>> = BTreeMap::new();
+    for r in references(project) {
+        by_address.entry(r.address.clone()).or_default().push(r);
+    }
+    let mut entries: Vec<XrefEntry> = by_address
+        .into_iter()
+        .map(|(address, references)| XrefEntry {
+            symbol: symbols.get(address.as_str()).unwrap_or(&"").to_string(),
+            address,
+            references,
+        })
+        .collect();
+    entries.sort_by(|a, b| cmp_address(&a.address, &b.address));
+    entries
+}
+
+impl XrefEntry {
+    /// Write the entry with the rung context and IL line of each reference
+    pub fn render(&self, project: &Project) -> String {
+        let mut out = self.address.clone();
+        if !self.symbol.is_empty() {
+            out.push_str(&format!(" [{}]", self.symbol));
+        }
+        out.push('\n');
+        for r in &self.references {
+            let pou = &project.pous[r.pou];
+            out.push_str(&format!(
+                "  {:5} {} (line {}): {}\n",
+                r.access,
+                pou.rung_context(r.rung),
+                r.line + 1,
+                pou.rungs[r.rung].il[r.line].text
+            ));
+        }
+        out
+    }
+}
+
+/// Compare addresses with numbers in numerical order, so that `%I0.2` sorts before `%I0.10`
+pub fn cmp_address(a: &str, b: &str) -> Ordering {
+    fn parts(s: &str) -> impl Iterator<Item = (bool, &str)> {
+        let mut rest = s;
+        std::iter::from_fn(move || {
+            let first = rest.chars().next()?;
+            let digit = first.is_ascii_digit();
+            let end = rest
+                .find(|c: char| c.is_ascii_digit() != digit)
+                .unwrap_or(rest.len());
+            let (part, tail) = rest.split_at(end);
+            rest = tail;
+            Some((digit, part))
+        })
+    }
+    let mut a_parts = parts(a);
+    let mut b_parts = parts(b);
+    loop {
+        match (a_parts.next(), b_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some((true, x)), Some((true, y))) => {
+                let x = x.trim_start_matches('0');
+                let y = y.trim_start_matches('0');
+                match x.len().cmp(&y.len()).then_with(|| x.cmp(y)) {
+                    Ordering::Equal => {}
+                    other => return other,
+                }
+            }
+            (Some((_, x)), Some((_, y))) => match x.cmp(y) {
+                Ordering::Equal => {}
+                other => return other,
+            },
+        }
+    }
+}
+
+/// The rungs reading and writing an address, by POU and rung index
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AddressUsage {
+    pub readers: BTreeSet<(usize, usize)>,
+    pub writers: BTreeSet<(usize, usize)>,
+    pub callers: BTreeSet<(usize, usize)>,
+}
+
+impl AddressUsage {
+    /// The rungs of each access as labels, see `Pou::rung_label`
+    fn labels(&self, project: &Project) -> Vec<(Access, BTreeSet<String>)> {
+        let labels = |rungs: &BTreeSet<(usize, usize)>| {
+            rungs
+                .iter()
+                .map(|&(pou, rung)| project.pous[pou].rung_label(rung))
+                .collect()
+        };
+        vec![
+            (Access::Read, labels(&self.readers)),
+            (Access::Write, labels(&self.writers)),
+            (Access::Call, labels(&self.callers)),
+        ]
+    }
+}
+
+/// Usage of every referenced address in the project
+pub fn usage(project: &Project) -> BTreeMap<String, AddressUsage> {
+    let mut usage: BTreeMap<String, AddressUsage> = BTreeMap::new();
+    for r in references(project) {
+        let rung = (r.pou, r.rung);
+        let entry = usage.entry(r.address).or_default();
+        match r.access {
+            Access::Read => entry.readers.insert(rung),
+            Access::Write => entry.writers.insert(rung),
+            Access::Call => entry.callers.insert(rung),
+        };
+    }
+    usage
+}
+
+/// An address whose set of reading or writing rungs differs between two versions
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UsageChange {
+    pub address: String,
+    pub symbol: String,
+    pub old: AddressUsage,
+    pub new: AddressUsage,
+    /// Rung labels which no longer use the address, by access
+    pub removed: Vec<(Access, String)>,
+    /// Rung labels which now use the address, by access
+    pub added: Vec<(Access, String)>,
+}
+
+impl UsageChange {
+    pub fn newly_referenced(&self) -> bool {
+        self.old == AddressUsage::default()
+    }
+
+    pub fn no_longer_referenced(&self) -> bool {
+        self.new == AddressUsage::default()
+    }
+}
+
+/// Addresses whose usage changed, optionally only those starting with one of `prefixes`
+///
+/// Rungs are compared by their labels, so a reference which moves between unnamed rungs of
+/// a POU is a change, but one in a named rung which moves with it is not.
+pub fn usage_diff(old: &Project, new: &Project, prefixes: &[&str]) -> Vec<UsageChange> {
+    let (mut old_usage, mut new_usage) = (usage(old), usage(new));
+    let (old_symbols, new_symbols) = (old.symbols(), new.symbols());
+    let mut addresses: Vec<String> = old_usage.keys().chain(new_usage.keys()).cloned().collect();
+    addresses.sort_by(|a, b| cmp_address(a, b));
+    addresses.dedup();
+    addresses
+        .into_iter()
+        .filter(|a| prefixes.is_empty() || prefixes.iter().any(|p| address_has_prefix(a, p)))
+        .filter_map(|address| {
+            let old_rungs = old_usage.remove(&address).unwrap_or_default();
+            let new_rungs = new_usage.remove(&address).unwrap_or_default();
+            let (mut removed, mut added) = (Vec::new(), Vec::new());
+            let labels = old_rungs.labels(old).into_iter().zip(new_rungs.labels(new));
+            for ((access, old_labels), (_, new_labels)) in labels {
+                removed.extend(
+                    old_labels
+                        .difference(&new_labels)
+                        .map(|l| (access, l.clone())),
+                );
+                added.extend(
+                    new_labels
+                        .difference(&old_labels)
+                        .map(|l| (access, l.clone())),
+                );
+            }
+            if removed.is_empty() && added.is_empty() {
+                return None;
+            }
+            let symbol = new_symbols
+                .get(address.as_str())
+                .or_else(|| old_symbols.get(address.as_str()))
+                .unwrap_or(&"")
+                .to_string();
+            Some(UsageChange {
+                address,
+                symbol,
+                old: old_rungs,
+                new: new_rungs,
+                removed,
+                added,
+            })
+        })
+        .collect()
+}
+
+/// Check the address type, so that the prefix `%M` matches `%M10` but not `%MW10`
+pub(crate) fn address_has_prefix(address: &str, prefix: &str) -> bool {
+    address
+        .strip_prefix(prefix)
+        .is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_alphabetic()))
+}
+
+impl Display for UsageChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.address)?;
+        if !self.symbol.is_empty() {
+            write!(f, " [{}]", self.symbol)?;
+        }
+        if self.newly_referenced() {
+            write!(f, " (newly referenced)")?;
+        } else if self.no_longer_referenced() {
+            write!(f, " (no longer referenced)")?;
+        }
+        writeln!(f)?;
+        for access in [Access::Read, Access::Write, Access::Call] {
+            for (_, label) in self.removed.iter().filter(|(a, _)| *a == access) {
+                writeln!(f, "  - {:5} {}", access, label)?;
+            }
+            for (_, label) in self.added.iter().filter(|(a, _)| *a == access) {
+                writeln!(f, "  + {:5} {}", access, label)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+    use crate::project::Pou;
+
+    #[test]
+    fn test_rung_references() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let refs = rung_references(&project.pous[0].rungs[1]);
+        assert_eq!(
+            refs,
+            vec![
+                (0, "%I0.5".into(), Access::Read, None),
+                (1, "%I0.4".into(), Access::Read, None),
+                (
+                    2,
+                    "%M10".into(),
+                    Access::Write,
+                    Some(WriteOp::Store(StoreOp::Set))
+                )
+            ]
+        );
+        let timer = &project.pous[2].rungs[1];
+        assert_eq!(timer.name, "Heartbeat timer");
+        let refs = rung_references(timer);
+        assert_eq!(refs[0], (0, "%TM0".into(), Access::Call, None));
+        assert_eq!(
+            refs[3],
+            (3, "%TM0.IN".into(), Access::Write, Some(WriteOp::Input))
+        );
+        assert_eq!(refs[4], (5, "%TM0.Q".into(), Access::Read, None));
+
+        // Operands of comparisons and operations
+        let mut rung = project.pous[0].rungs[1].clone();
+        rung.il[0].text = "LD [%MW0 > 5]".to_string();
+        rung.il[1].text = "[%MW1 := %MW2 + %KW0]".to_string();
+        let refs = rung_references(&rung);
+        assert_eq!(
+            refs,
+            vec![
+                (0, "%MW0".into(), Access::Read, None),
+                (1, "%MW1".into(), Access::Write, Some(WriteOp::Operation)),
+                (1, "%MW2".into(), Access::Read, None),
+                (1, "%KW0".into(), Access::Read, None),
+                (
+                    2,
+                    "%M10".into(),
+                    Access::Write,
+                    Some(WriteOp::Store(StoreOp::Set))
+                )
+            ]
+        );
+        assert_eq!(cmp_address("%I0.2", "%I0.10"), Ordering::Less);
+        assert!(address_has_prefix("%M10", "%M") && !address_has_prefix("%MW10", "%M"));
+    }
+
+    #[test]
+    fn test_cross_reference() {
+        let project = Project::from_file(Path::new("tests/orig.smbp")).unwrap();
+        let xref = cross_reference(&project);
+        let m10 = xref.iter().find(|e| e.address == "%M10").unwrap();
+        assert_eq!(m10.symbol, "IO_INVALID");
+        assert!(m10
+            .render(&project)
+            .starts_with("%M10 [IO_INVALID]\n  write Validate inputs (line 2): R  %M10\n"));
+    }
+
+    #[test]
+    fn test_usage_diff() {
+        let orig = std::fs::read_to_string(Path::new("tests/orig.smbp")).unwrap();
+        let old = Project::from_bytes(orig.as_bytes()).unwrap();
+        let new =
+            Project::from_bytes(orig.replacen("AND  %I0.4", "AND  %I0.9", 1).as_bytes()).unwrap();
+        let changes = usage_diff(&old, &new, &["%I"]);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].address, "%I0.4");
+        assert!(changes[1].newly_referenced());
+        assert_eq!(
+            changes[1].to_string(),
+            "%I0.9 [WEST_OF_SOUTH] (newly referenced)\n  + read  Validate inputs > Flaps\n"
+        );
+        // Unnamed rungs of one POU swap their outputs
+        let project = |outputs: [&str; 2]| Project {
+            pous: vec![Pou::program(
+                "Main",
+                vec![
+                    Rung::from_il(&["LD %I0.0", outputs[0]]),
+                    Rung::from_il(&["LD %I0.1", outputs[1]]),
+                ],
+            )],
+            ..Default::default()
+        };
+        let old = project(["ST %Q0.0", "ST %Q0.1"]);
+        let new = project(["ST %Q0.1", "ST %Q0.0"]);
+        let changes: Vec<String> = usage_diff(&old, &new, &["%Q"])
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "%Q0.0\n  - write Main rung 1\n  + write Main rung 2\n",
+                "%Q0.1\n  - write Main rung 2\n  + write Main rung 1\n",
+            ]
+        );
+    }
+}